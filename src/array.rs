@@ -0,0 +1,97 @@
+//! Conversions between tensors and `ndarray` arrays (requires the `ndarray` feature)
+//!
+//! ggml lists dimensions fastest-varying first, while `ndarray` shapes are row-major
+//! (slowest-varying first), so a tensor with `dims` `[n_embd, n_vocab]` becomes an array
+//! of shape `[n_vocab, n_embd]`. The data itself needs no reordering.
+
+use std::borrow::Cow;
+
+use ndarray::{ArrayBase, ArrayD, ArrayViewD, CowArray, Data, Dimension, IxDyn, ShapeError};
+
+use crate::convert::convert_f32_to_f16;
+use crate::metadata::{GgufError, Result, TensorType};
+use crate::tensors::{Tensor, TensorInfo};
+
+impl Tensor {
+    /// Get the row-major shape of this tensor, i.e. its `dims` reversed
+    pub fn ndarray_shape(&self) -> Vec<usize> {
+        self.info.dims.iter().rev().map(|&d| d as usize).collect()
+    }
+
+    /// Convert to an owned f32 array, dequantizing if needed
+    pub fn to_ndarray(&self) -> Result<ArrayD<f32>> {
+        ArrayD::from_shape_vec(self.ndarray_shape(), self.as_f32_vec()?)
+            .map_err(|e| self.shape_error(e))
+    }
+
+    /// Get an f32 array, borrowing the data when it's aligned F32 and converting otherwise
+    ///
+    /// Call `.view()` on the result for an `ArrayViewD<f32>`.
+    pub fn as_ndarray(&self) -> Result<CowArray<'_, f32, IxDyn>> {
+        if self.info.tensor_type != TensorType::F32 {
+            return self.to_ndarray().map(CowArray::from);
+        }
+
+        let shape = self.ndarray_shape();
+        match self.as_f32_slice()? {
+            Cow::Borrowed(values) => ArrayViewD::from_shape(shape, values)
+                .map(CowArray::from)
+                .map_err(|e| self.shape_error(e)),
+            Cow::Owned(values) => ArrayD::from_shape_vec(shape, values)
+                .map(CowArray::from)
+                .map_err(|e| self.shape_error(e)),
+        }
+    }
+
+    /// Build a tensor from an f32 array, storing it as F32 or F16
+    ///
+    /// The array is read in logical row-major order regardless of its memory layout, and
+    /// its shape is reversed into ggml's `dims`.
+    pub fn from_ndarray<S, D>(
+        name: &str,
+        array: &ArrayBase<S, D>,
+        tensor_type: TensorType,
+    ) -> Result<Tensor>
+    where
+        S: Data<Elem = f32>,
+        D: Dimension,
+    {
+        let data = match tensor_type {
+            TensorType::F32 => array.iter().flat_map(|v| v.to_le_bytes()).collect(),
+            TensorType::F16 => {
+                let values: Vec<f32> = array.iter().copied().collect();
+                let mut half = vec![0; values.len()];
+                convert_f32_to_f16(&values, &mut half);
+                half.iter().flat_map(|v| v.to_le_bytes()).collect()
+            }
+            _ => {
+                return Err(GgufError::Unsupported(format!(
+                    "Cannot build a {:?} tensor from an array",
+                    tensor_type
+                )));
+            }
+        };
+
+        let mut dims: Vec<u64> = array.shape().iter().rev().map(|&d| d as u64).collect();
+        if dims.is_empty() {
+            dims.push(1);
+        }
+        Ok(Tensor {
+            info: TensorInfo {
+                name: name.to_string(),
+                n_dims: dims.len() as u32,
+                dims,
+                tensor_type,
+                offset: 0,
+            },
+            data,
+        })
+    }
+
+    fn shape_error(&self, err: ShapeError) -> GgufError {
+        GgufError::InvalidFormat(format!(
+            "Tensor '{}' with dims {:?} doesn't match its data: {}",
+            self.info.name, self.info.dims, err
+        ))
+    }
+}
@@ -0,0 +1,61 @@
+//! Convert a Hugging Face llama-family checkpoint to GGUF
+//!
+//! Usage: gguf-convert-hf [--type TYPE] MODEL_DIR OUTPUT
+//!
+//! MODEL_DIR holds `config.json` and the `*.safetensors` weights. Weight matrices keep
+//! their float type unless `--type` (e.g. `F16` or `Q8_0`) is given.
+
+use std::process::ExitCode;
+
+use gguf_llms::{TensorType, convert_hf_to_gguf};
+
+const USAGE: &str = "usage: gguf-convert-hf [--type TYPE] MODEL_DIR OUTPUT";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut tensor_type = None;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--type" => {
+                let name = args.next().ok_or(USAGE)?;
+                tensor_type = Some(
+                    TensorType::from_name(&name)
+                        .ok_or_else(|| format!("Unknown tensor type '{}'", name))?,
+                );
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+    let [model_dir, output] = <[String; 2]>::try_from(positional).map_err(|_| USAGE)?;
+
+    let infos = convert_hf_to_gguf(&model_dir, &output, tensor_type)
+        .map_err(|e| format!("{}: {}", model_dir, e))?;
+    for info in &infos {
+        println!(
+            "{:<40} {:>8} {:?}",
+            info.name,
+            info.tensor_type.name(),
+            info.dims
+        );
+    }
+    let size: u64 = infos.iter().map(|i| i.byte_size()).sum();
+    println!(
+        "Wrote {} ({} tensors, {:.2} MiB)",
+        output,
+        infos.len(),
+        size as f64 / (1 << 20) as f64
+    );
+    Ok(())
+}
@@ -0,0 +1,53 @@
+//! Export a GGUF model as a Hugging Face checkpoint
+//!
+//! Usage: gguf-export-hf [--type F32|F16|BF16] INPUT OUTPUT_DIR
+//!
+//! Writes `model.safetensors` and `config.json` to OUTPUT_DIR. Quantized tensors are
+//! dequantized; all tensors are written as F32 unless `--type` says otherwise. INPUT may
+//! be any shard of a split model.
+
+use std::process::ExitCode;
+
+use gguf_llms::{ShardedGguf, TensorType, export_gguf_to_hf};
+
+const USAGE: &str = "usage: gguf-export-hf [--type F32|F16|BF16] INPUT OUTPUT_DIR";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut tensor_type = TensorType::F32;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--type" => {
+                let name = args.next().ok_or(USAGE)?;
+                tensor_type = TensorType::from_name(&name)
+                    .ok_or_else(|| format!("Unknown tensor type '{}'", name))?;
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+    let [input, output_dir] = <[String; 2]>::try_from(positional).map_err(|_| USAGE)?;
+
+    let model = ShardedGguf::open(&input).map_err(|e| format!("{}: {}", input, e))?;
+    let infos = export_gguf_to_hf(&model, &output_dir, tensor_type)
+        .map_err(|e| format!("{}: {}", output_dir, e))?;
+    let size: u64 = infos.iter().map(|i| i.byte_size()).sum();
+    println!(
+        "Wrote {} tensors ({:.2} MiB) to {}",
+        infos.len(),
+        size as f64 / (1 << 20) as f64,
+        output_dir
+    );
+    Ok(())
+}
@@ -0,0 +1,133 @@
+//! Report the statistics of an importance matrix
+//!
+//! Usage: gguf-imatrix [--top N] [--model MODEL] IMATRIX
+//!
+//! Prints the share of the total importance held by each layer, then the importance
+//! distribution and the N most important columns (5 by default) of each entry. With
+//! `--model`, entries are also matched against the model's tensors.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+use gguf_llms::{GgufError, GgufHeader, GgufReader, Imatrix, TensorInfo, TensorLoader};
+
+const USAGE: &str = "usage: gguf-imatrix [--top N] [--model MODEL] IMATRIX";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut top = 5;
+    let mut model = None;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--top" => {
+                let n = args.next().ok_or(USAGE)?;
+                top = n
+                    .parse()
+                    .map_err(|_| format!("Invalid column count '{}'", n))?;
+            }
+            "--model" => model = Some(args.next().ok_or(USAGE)?),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+    let [path] = <[String; 1]>::try_from(positional).map_err(|_| USAGE)?;
+    let imatrix = Imatrix::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+
+    println!(
+        "{}: {} entries, {} chunks{}",
+        path,
+        imatrix.entries.len(),
+        imatrix.chunk_count,
+        if imatrix.datasets.is_empty() {
+            String::new()
+        } else {
+            format!(" of {}", imatrix.datasets.join(", "))
+        }
+    );
+
+    println!(
+        "\n{:>5} {:>7} {:>14} {:>7}",
+        "layer", "entries", "importance", "share"
+    );
+    for layer in imatrix.layer_stats() {
+        println!(
+            "{:>5} {:>7} {:>14.4e} {:>6.2}%",
+            layer.layer,
+            layer.entry_count,
+            layer.sum,
+            layer.share * 100.0
+        );
+    }
+
+    println!(
+        "\n{:<40} {:>11} {:>11} {:>11} {:>11} {:>6}",
+        "entry", "mean", "std_dev", "median", "max", "zeros"
+    );
+    for entry in imatrix.entry_stats(top) {
+        println!(
+            "{:<40} {:>11.4e} {:>11.4e} {:>11.4e} {:>11.4e} {:>5.1}%",
+            entry.name,
+            entry.mean,
+            entry.std_dev,
+            entry.median,
+            entry.max,
+            entry.zero_fraction * 100.0
+        );
+        if entry.active_matrices < entry.matrix_count {
+            println!(
+                "    {} of {} matrices saw no activations",
+                entry.matrix_count - entry.active_matrices,
+                entry.matrix_count
+            );
+        }
+        let columns: Vec<String> = entry
+            .top_columns
+            .iter()
+            .map(|c| {
+                if entry.matrix_count > 1 {
+                    format!("{}:{}={:.3e}", c.matrix, c.column, c.importance)
+                } else {
+                    format!("{}={:.3e}", c.column, c.importance)
+                }
+            })
+            .collect();
+        if !columns.is_empty() {
+            println!("    top columns: {}", columns.join(" "));
+        }
+    }
+
+    if let Some(model) = model {
+        let infos = read_tensor_infos(&model).map_err(|e| format!("{}: {}", model, e))?;
+        let coverage = imatrix.coverage(&infos);
+        println!("\n{}: {} tensors matched", model, coverage.matched.len());
+        for (label, names) in [
+            ("mismatched", &coverage.mismatched),
+            ("missing", &coverage.missing),
+            ("unused", &coverage.unused),
+        ] {
+            for name in names {
+                println!("    {:<10} {}", label, name);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_tensor_infos(path: &str) -> Result<Vec<TensorInfo>, GgufError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = GgufHeader::parse(&mut reader)?;
+    GgufReader::read_metadata(&mut reader, header.n_kv)?;
+    TensorLoader::read_tensor_info(&mut reader, header.n_tensors)
+}
@@ -0,0 +1,71 @@
+//! Dump tensors of a GGUF file to NumPy `.npy` or `.npz` files
+//!
+//! Usage: gguf-npy [--f32 | --raw] INPUT OUTPUT TENSOR...
+//!
+//! An OUTPUT ending in `.npz` receives all the named tensors; otherwise exactly one
+//! tensor is written as `.npy`. Tensors keep their type when NumPy has it and are
+//! dequantized to `float32` otherwise; `--f32` always converts and `--raw` writes the
+//! stored bytes as `uint8`.
+
+use std::process::ExitCode;
+
+use gguf_llms::npy::{export_npy, export_npz};
+use gguf_llms::{NpyDtype, ShardedGguf};
+
+const USAGE: &str = "usage: gguf-npy [--f32 | --raw] INPUT OUTPUT TENSOR...";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut dtype = NpyDtype::Native;
+    let mut positional = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--f32" => dtype = NpyDtype::Float32,
+            "--raw" => dtype = NpyDtype::Raw,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() < 3 {
+        return Err(USAGE.to_string());
+    }
+    let names = positional.split_off(2);
+    let [input, output] = <[String; 2]>::try_from(positional).map_err(|_| USAGE)?;
+
+    let model = ShardedGguf::open(&input).map_err(|e| format!("{}: {}", input, e))?;
+    let tensors = names
+        .iter()
+        .map(|name| model.load_tensor(name))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("{}: {}", input, e))?;
+
+    let result = if output.ends_with(".npz") {
+        export_npz(&output, &tensors, dtype)
+    } else if let [tensor] = tensors.as_slice() {
+        export_npy(&output, tensor, dtype)
+    } else {
+        return Err(format!(
+            "Writing {} tensors needs an .npz output",
+            tensors.len()
+        ));
+    };
+    result.map_err(|e| format!("{}: {}", output, e))?;
+    for tensor in &tensors {
+        println!(
+            "{:<40} {:>8} {:?}",
+            tensor.info.name,
+            tensor.info.tensor_type.name(),
+            tensor.info.dims
+        );
+    }
+    Ok(())
+}
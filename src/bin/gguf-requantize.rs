@@ -0,0 +1,123 @@
+//! Requantize a GGUF file
+//!
+//! Usage: gguf-requantize [--imatrix FILE] [--rule PATTERN=TYPE[@N]]... INPUT OUTPUT FILE_TYPE
+//!
+//! FILE_TYPE is a llama.cpp file type such as `Q4_K_M`, selecting a preset policy.
+//! Each `--rule` takes precedence over the preset (and earlier rules over later ones);
+//! a `@N` suffix limits it to the first and last N layers. `--imatrix` reads an
+//! importance matrix (GGUF or legacy `.dat`) to weight the quantization error.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::process::ExitCode;
+
+use gguf_llms::requantize::{block_count, requantize_file};
+use gguf_llms::{
+    FileType, GgufError, GgufHeader, GgufReader, Imatrix, QuantizationPolicy, TensorLoader,
+    TensorType, TypeRule,
+};
+
+const USAGE: &str =
+    "usage: gguf-requantize [--imatrix FILE] [--rule PATTERN=TYPE[@N]]... INPUT OUTPUT FILE_TYPE";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut rules = Vec::new();
+    let mut imatrix = None;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--rule" => {
+                let rule = args.next().ok_or(USAGE)?;
+                rules.push(parse_rule(&rule)?);
+            }
+            "--imatrix" => {
+                let path = args.next().ok_or(USAGE)?;
+                imatrix = Some(Imatrix::open(&path).map_err(|e| format!("{}: {}", path, e))?);
+            }
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+    let [input, output, file_type] = <[String; 3]>::try_from(positional).map_err(|_| USAGE)?;
+    let file_type = FileType::from_name(&file_type)
+        .ok_or_else(|| format!("Unknown file type '{}'", file_type))?;
+
+    let block_count = read_block_count(&input).map_err(|e| e.to_string())?;
+    let mut policy = QuantizationPolicy::preset(file_type, block_count);
+    policy.rules.splice(0..0, rules);
+    if let Some(imatrix) = imatrix {
+        policy = policy.with_imatrix(imatrix);
+    }
+
+    let report = requantize_file(&input, &output, &policy).map_err(|e| e.to_string())?;
+    for tensor in &report.tensors {
+        println!(
+            "{:<48} {:>8} -> {:<8} {:>12} -> {:>12} bytes{}",
+            tensor.name,
+            tensor.original_type.name(),
+            tensor.tensor_type.name(),
+            tensor.original_size,
+            tensor.size,
+            if tensor.used_imatrix {
+                " (imatrix)"
+            } else {
+                ""
+            }
+        );
+    }
+    println!(
+        "Wrote {} ({}): {:.2} MiB -> {:.2} MiB",
+        output,
+        file_type.name(),
+        report.original_size() as f64 / (1 << 20) as f64,
+        report.size() as f64 / (1 << 20) as f64
+    );
+    Ok(())
+}
+
+/// Parse `PATTERN=TYPE[@N]`
+fn parse_rule(rule: &str) -> Result<TypeRule, String> {
+    let (pattern, spec) = rule
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid rule '{}', expected PATTERN=TYPE[@N]", rule))?;
+    let (type_name, layers) = match spec.split_once('@') {
+        Some((type_name, layers)) => {
+            let layers = layers
+                .parse::<usize>()
+                .map_err(|_| format!("Invalid layer count in rule '{}'", rule))?;
+            (type_name, Some(layers))
+        }
+        None => (spec, None),
+    };
+    let tensor_type = TensorType::from_name(type_name)
+        .ok_or_else(|| format!("Unknown tensor type '{}'", type_name))?;
+    if !tensor_type.can_quantize() {
+        return Err(format!("Quantization to {} is not supported", type_name));
+    }
+
+    let rule = TypeRule::new(pattern, tensor_type);
+    Ok(match layers {
+        Some(n) => rule.first_layers(n).last_layers(n),
+        None => rule,
+    })
+}
+
+/// Count the layers of the input from its tensor names
+fn read_block_count(path: &str) -> Result<usize, GgufError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let header = GgufHeader::parse(&mut reader)?;
+    GgufReader::read_metadata(&mut reader, header.n_kv)?;
+    let infos = TensorLoader::read_tensor_info(&mut reader, header.n_tensors)?;
+    Ok(block_count(&infos))
+}
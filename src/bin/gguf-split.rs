@@ -0,0 +1,97 @@
+//! Split a GGUF file into shards, or merge shards back into one file
+//!
+//! Usage: gguf-split [--split-max-tensors N | --split-max-size N[K|M|G]] [--dry-run] INPUT PREFIX
+//!        gguf-split --merge INPUT OUTPUT
+//!
+//! Shards are named `{PREFIX}-00001-of-0000N.gguf`, as llama.cpp expects. When merging,
+//! INPUT may be any of the shards. Splitting defaults to 128 tensors per shard.
+
+use std::process::ExitCode;
+
+use gguf_llms::split::{SplitLimit, merge_model, plan_split, split_model, split_path};
+use gguf_llms::{ShardedGguf, TensorInfo};
+
+const USAGE: &str = "usage: gguf-split [--split-max-tensors N | --split-max-size N[K|M|G]] \
+                     [--dry-run] INPUT PREFIX\n       gguf-split --merge INPUT OUTPUT";
+
+fn main() -> ExitCode {
+    match run(std::env::args().skip(1).collect()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("{}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Vec<String>) -> Result<(), String> {
+    let mut limit = SplitLimit::MaxTensors(128);
+    let mut merge = false;
+    let mut dry_run = false;
+    let mut positional = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--split-max-tensors" => {
+                let n = args.next().ok_or(USAGE)?;
+                limit = SplitLimit::MaxTensors(
+                    n.parse()
+                        .map_err(|_| format!("Invalid tensor count '{}'", n))?,
+                );
+            }
+            "--split-max-size" => {
+                limit = SplitLimit::MaxSize(parse_size(&args.next().ok_or(USAGE)?)?)
+            }
+            "--merge" => merge = true,
+            "--split" => merge = false,
+            "--dry-run" => dry_run = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => positional.push(arg),
+        }
+    }
+    let [input, output] = <[String; 2]>::try_from(positional).map_err(|_| USAGE)?;
+    let model = ShardedGguf::open(&input).map_err(|e| format!("{}: {}", input, e))?;
+
+    if merge {
+        merge_model(&model, &output).map_err(|e| format!("{}: {}", output, e))?;
+        println!(
+            "Merged {} shards ({} tensors) into {}",
+            model.shards.len(),
+            model.tensor_count(),
+            output
+        );
+        return Ok(());
+    }
+
+    let infos: Vec<&TensorInfo> = model.tensor_infos().collect();
+    let ranges = plan_split(&infos, limit);
+    for (no, range) in ranges.iter().enumerate() {
+        let size: u64 = infos[range.clone()].iter().map(|i| i.byte_size()).sum();
+        println!(
+            "{}: {} tensors, {:.2} MiB",
+            split_path(&output, no as u16, ranges.len() as u16).display(),
+            range.len(),
+            size as f64 / (1 << 20) as f64
+        );
+    }
+    if !dry_run {
+        split_model(&model, &output, limit).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Parse a size in bytes with an optional `K`, `M` or `G` suffix
+fn parse_size(size: &str) -> Result<u64, String> {
+    let (digits, unit) = match size.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("Invalid size '{}'", size))
+}
@@ -23,24 +23,32 @@ pub fn extract_model_config(metadata: &HashMap<String, Value>) -> Result<ModelCo
     let block_count = get_u32_field(metadata, &format!("{}.block_count", arch_prefix))?;
     let context_length = get_u32_field(metadata, &format!("{}.context_length", arch_prefix))?;
     let embedding_length = get_u32_field(metadata, &format!("{}.embedding_length", arch_prefix))?;
-    let feed_forward_length =
-        get_u32_field(metadata, &format!("{}.feed_forward_length", arch_prefix))?;
     let attention_head_count =
         get_u32_field(metadata, &format!("{}.attention.head_count", arch_prefix))?;
 
     // Extract optional fields
+    let feed_forward_length =
+        get_optional_u32_field(metadata, &format!("{}.feed_forward_length", arch_prefix));
     let attention_head_count_kv = get_optional_u32_field(
         metadata,
         &format!("{}.attention.head_count_kv", arch_prefix),
     );
     let attention_key_length =
         get_optional_u32_field(metadata, &format!("{}.attention.key_length", arch_prefix));
+    // RMSNorm models use the `_rms_` key, LayerNorm models (falcon, bert, ...) the plain one
     let layer_norm_epsilon = get_optional_f32_field(
         metadata,
         &format!("{}.attention.layer_norm_rms_epsilon", arch_prefix),
-    );
+    )
+    .or_else(|| {
+        get_optional_f32_field(
+            metadata,
+            &format!("{}.attention.layer_norm_epsilon", arch_prefix),
+        )
+    });
     let rope_freq_base =
         get_optional_f32_field(metadata, &format!("{}.rope.freq_base", arch_prefix));
+    let experts = extract_expert_config(metadata, arch_prefix)?;
 
     Ok(ModelConfig {
         architecture,
@@ -53,6 +61,453 @@ pub fn extract_model_config(metadata: &HashMap<String, Value>) -> Result<ModelCo
         attention_key_length,
         layer_norm_epsilon,
         rope_freq_base,
+        experts,
+    })
+}
+
+/// Architecture-specific model configuration, selected by `general.architecture`
+#[derive(Debug, Clone)]
+pub enum ArchitectureConfig {
+    /// `llama`, `mistral` and `mixtral`
+    Llama(LlamaConfig),
+    /// `qwen2` and `qwen2moe`
+    Qwen2(QwenConfig),
+    /// `qwen3` and `qwen3moe`
+    Qwen3(QwenConfig),
+    /// `gemma2` and `gemma3`
+    Gemma(GemmaConfig),
+    /// `phi3`
+    Phi3(Phi3Config),
+    /// `falcon`
+    Falcon(FalconConfig),
+    /// `bert`
+    Bert(BertConfig),
+    /// `mamba`
+    Mamba(MambaConfig),
+    /// Any other architecture, described only by the generic keys
+    Generic(ModelConfig),
+}
+
+impl ArchitectureConfig {
+    /// Get the generic configuration shared by all architectures
+    pub fn base(&self) -> &ModelConfig {
+        match self {
+            ArchitectureConfig::Llama(c) => &c.base,
+            ArchitectureConfig::Qwen2(c) | ArchitectureConfig::Qwen3(c) => &c.base,
+            ArchitectureConfig::Gemma(c) => &c.base,
+            ArchitectureConfig::Phi3(c) => &c.base,
+            ArchitectureConfig::Falcon(c) => &c.base,
+            ArchitectureConfig::Bert(c) => &c.base,
+            ArchitectureConfig::Mamba(c) => &c.base,
+            ArchitectureConfig::Generic(c) => c,
+        }
+    }
+
+    /// Get the architecture name (e.g., "qwen3")
+    pub fn architecture(&self) -> &str {
+        &self.base().architecture
+    }
+
+    /// Get the expert configuration, if this is a mixture-of-experts model
+    pub fn experts(&self) -> Option<&ExpertConfig> {
+        self.base().experts.as_ref()
+    }
+}
+
+/// Mixture-of-experts parameters (`{arch}.expert_*`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpertConfig {
+    /// Total number of experts per layer
+    pub expert_count: u32,
+    /// Number of experts routed to per token
+    pub expert_used_count: u32,
+    /// Hidden dimension of each expert's feed-forward network
+    pub expert_feed_forward_length: Option<u32>,
+    /// Hidden dimension of the shared expert, if any
+    pub expert_shared_feed_forward_length: Option<u32>,
+}
+
+/// Configuration for llama-family models (including Mistral and Mixtral)
+#[derive(Debug, Clone)]
+pub struct LlamaConfig {
+    /// Generic configuration
+    pub base: ModelConfig,
+    /// Number of dimensions rotated by RoPE
+    pub rope_dimension_count: Option<u32>,
+    /// Vocabulary size
+    pub vocab_size: Option<u32>,
+    /// Sliding window size (Mistral)
+    pub sliding_window: Option<u32>,
+}
+
+/// Configuration for Qwen2 and Qwen3 models, dense or MoE
+#[derive(Debug, Clone)]
+pub struct QwenConfig {
+    /// Generic configuration
+    pub base: ModelConfig,
+    /// Value head dimension
+    pub attention_value_length: Option<u32>,
+}
+
+/// Configuration for Gemma 2 and Gemma 3 models
+#[derive(Debug, Clone)]
+pub struct GemmaConfig {
+    /// Generic configuration
+    pub base: ModelConfig,
+    /// Sliding window size used by the local attention layers
+    pub sliding_window: Option<u32>,
+    /// Soft cap applied to attention logits
+    pub attn_logit_softcapping: Option<f32>,
+    /// Soft cap applied to the final output logits
+    pub final_logit_softcapping: Option<f32>,
+    /// Value head dimension
+    pub attention_value_length: Option<u32>,
+}
+
+/// Configuration for Phi-3 models
+#[derive(Debug, Clone)]
+pub struct Phi3Config {
+    /// Generic configuration
+    pub base: ModelConfig,
+    /// Number of dimensions rotated by RoPE
+    pub rope_dimension_count: Option<u32>,
+    /// Sliding window size
+    pub sliding_window: Option<u32>,
+}
+
+/// Configuration for Falcon models
+#[derive(Debug, Clone)]
+pub struct FalconConfig {
+    /// Generic configuration
+    pub base: ModelConfig,
+    /// Whether attention is causal
+    pub causal: Option<bool>,
+}
+
+/// Configuration for BERT encoder models
+#[derive(Debug, Clone)]
+pub struct BertConfig {
+    /// Generic configuration
+    pub base: ModelConfig,
+    /// Whether attention is causal (false for BERT)
+    pub causal: Option<bool>,
+    /// Pooling type used for embeddings
+    pub pooling_type: Option<u32>,
+    /// Number of token types (segment embeddings)
+    pub token_type_count: Option<u32>,
+}
+
+/// Configuration for Mamba state-space models
+#[derive(Debug, Clone)]
+pub struct MambaConfig {
+    /// Generic configuration
+    pub base: ModelConfig,
+    /// Convolution kernel size
+    pub ssm_conv_kernel: u32,
+    /// Inner (expanded) dimension
+    pub ssm_inner_size: u32,
+    /// State dimension
+    pub ssm_state_size: u32,
+    /// Rank of the time step projection
+    pub ssm_time_step_rank: u32,
+    /// Whether B, C and dt are RMS-normalized
+    pub ssm_dt_b_c_rms: Option<bool>,
+}
+
+/// Extract the architecture-specific configuration from GGUF metadata
+///
+/// Unknown architectures are returned as `ArchitectureConfig::Generic`.
+pub fn extract_architecture_config(
+    metadata: &HashMap<String, Value>,
+) -> Result<ArchitectureConfig> {
+    let architecture = metadata
+        .get("general.architecture")
+        .and_then(|v| v.as_string())
+        .ok_or_else(|| GgufError::InvalidFormat("Missing general.architecture".to_string()))?;
+
+    // Mamba has no attention heads, so the generic extraction can't be used as-is
+    if architecture == "mamba" {
+        return extract_mamba_config(metadata, architecture);
+    }
+
+    let base = extract_model_config(metadata)?;
+    let arch = base.architecture.clone();
+    let key = |suffix: &str| format!("{}.{}", arch, suffix);
+
+    let config = match arch.as_str() {
+        "llama" | "mistral" | "mixtral" => ArchitectureConfig::Llama(LlamaConfig {
+            rope_dimension_count: get_optional_u32_field(metadata, &key("rope.dimension_count")),
+            vocab_size: get_optional_u32_field(metadata, &key("vocab_size")),
+            sliding_window: get_optional_u32_field(metadata, &key("attention.sliding_window")),
+            base,
+        }),
+        "qwen2" | "qwen2moe" | "qwen3" | "qwen3moe" => {
+            let qwen = QwenConfig {
+                attention_value_length: get_optional_u32_field(
+                    metadata,
+                    &key("attention.value_length"),
+                ),
+                base,
+            };
+            if arch.starts_with("qwen2") {
+                ArchitectureConfig::Qwen2(qwen)
+            } else {
+                ArchitectureConfig::Qwen3(qwen)
+            }
+        }
+        "gemma2" | "gemma3" => ArchitectureConfig::Gemma(GemmaConfig {
+            sliding_window: get_optional_u32_field(metadata, &key("attention.sliding_window")),
+            attn_logit_softcapping: get_optional_f32_field(
+                metadata,
+                &key("attn_logit_softcapping"),
+            ),
+            final_logit_softcapping: get_optional_f32_field(
+                metadata,
+                &key("final_logit_softcapping"),
+            ),
+            attention_value_length: get_optional_u32_field(
+                metadata,
+                &key("attention.value_length"),
+            ),
+            base,
+        }),
+        "phi3" => ArchitectureConfig::Phi3(Phi3Config {
+            rope_dimension_count: get_optional_u32_field(metadata, &key("rope.dimension_count")),
+            sliding_window: get_optional_u32_field(metadata, &key("attention.sliding_window")),
+            base,
+        }),
+        "falcon" => ArchitectureConfig::Falcon(FalconConfig {
+            causal: get_optional_bool_field(metadata, &key("attention.causal")),
+            base,
+        }),
+        "bert" => ArchitectureConfig::Bert(BertConfig {
+            causal: get_optional_bool_field(metadata, &key("attention.causal")),
+            pooling_type: get_optional_u32_field(metadata, &key("pooling_type")),
+            token_type_count: get_optional_u32_field(metadata, "tokenizer.ggml.token_type_count"),
+            base,
+        }),
+        _ => ArchitectureConfig::Generic(base),
+    };
+
+    Ok(config)
+}
+
+fn extract_expert_config(
+    metadata: &HashMap<String, Value>,
+    arch: &str,
+) -> Result<Option<ExpertConfig>> {
+    let Some(expert_count) = get_optional_u32_field(metadata, &format!("{}.expert_count", arch))
+    else {
+        return Ok(None);
+    };
+    // Dense checkpoints sometimes carry `expert_count = 0`
+    if expert_count == 0 {
+        return Ok(None);
+    }
+
+    Ok(Some(ExpertConfig {
+        expert_count,
+        expert_used_count: get_u32_field(metadata, &format!("{}.expert_used_count", arch))?,
+        expert_feed_forward_length: get_optional_u32_field(
+            metadata,
+            &format!("{}.expert_feed_forward_length", arch),
+        ),
+        expert_shared_feed_forward_length: get_optional_u32_field(
+            metadata,
+            &format!("{}.expert_shared_feed_forward_length", arch),
+        ),
+    }))
+}
+
+fn extract_mamba_config(
+    metadata: &HashMap<String, Value>,
+    arch: &str,
+) -> Result<ArchitectureConfig> {
+    let key = |suffix: &str| format!("{}.{}", arch, suffix);
+
+    let base = ModelConfig {
+        architecture: arch.to_string(),
+        block_count: get_u32_field(metadata, &key("block_count"))?,
+        context_length: get_u32_field(metadata, &key("context_length"))?,
+        embedding_length: get_u32_field(metadata, &key("embedding_length"))?,
+        feed_forward_length: get_optional_u32_field(metadata, &key("feed_forward_length")),
+        attention_head_count: get_optional_u32_field(metadata, &key("attention.head_count"))
+            .unwrap_or(0),
+        attention_head_count_kv: get_optional_u32_field(metadata, &key("attention.head_count_kv")),
+        attention_key_length: None,
+        layer_norm_epsilon: get_optional_f32_field(
+            metadata,
+            &key("attention.layer_norm_rms_epsilon"),
+        ),
+        rope_freq_base: None,
+        experts: None,
+    };
+
+    Ok(ArchitectureConfig::Mamba(MambaConfig {
+        ssm_conv_kernel: get_u32_field(metadata, &key("ssm.conv_kernel"))?,
+        ssm_inner_size: get_u32_field(metadata, &key("ssm.inner_size"))?,
+        ssm_state_size: get_u32_field(metadata, &key("ssm.state_size"))?,
+        ssm_time_step_rank: get_u32_field(metadata, &key("ssm.time_step_rank"))?,
+        ssm_dt_b_c_rms: get_optional_bool_field(metadata, &key("ssm.dt_b_c_rms")),
+        base,
+    }))
+}
+
+/// RoPE frequency scaling method (`{arch}.rope.scaling.type`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RopeScalingType {
+    /// No scaling
+    None,
+    /// Linear position interpolation
+    Linear,
+    /// YaRN (NTK-by-parts interpolation with attention temperature)
+    Yarn,
+    /// Phi-3 style LongRoPE with per-dimension factors stored as tensors
+    LongRope,
+}
+
+impl RopeScalingType {
+    /// Parse the scaling type name as written by llama.cpp's converters.
+    ///
+    /// Returns `None` if the name is not recognized.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(RopeScalingType::None),
+            "linear" => Some(RopeScalingType::Linear),
+            "yarn" => Some(RopeScalingType::Yarn),
+            "longrope" => Some(RopeScalingType::LongRope),
+            _ => None,
+        }
+    }
+}
+
+/// YaRN parameters, with llama.cpp's defaults filled in for missing keys
+#[derive(Debug, Clone, PartialEq)]
+pub struct YarnConfig {
+    /// Extrapolation mix factor (default 1.0)
+    pub ext_factor: f32,
+    /// Attention magnitude scaling factor (default 1.0)
+    pub attn_factor: f32,
+    /// Low correction dimension boundary (default 32.0)
+    pub beta_fast: f32,
+    /// High correction dimension boundary (default 1.0)
+    pub beta_slow: f32,
+    /// Multiplier applied to `ln(factor)` in the attention temperature (DeepSeek)
+    pub log_multiplier: Option<f32>,
+}
+
+/// RoPE context-extension scaling parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct RopeScaling {
+    /// Scaling method
+    pub scaling_type: RopeScalingType,
+    /// Context extension factor (new context / original context)
+    pub factor: f32,
+    /// Attention scaling factor
+    pub attn_factor: Option<f32>,
+    /// Context length the model was originally trained with
+    pub original_context_length: Option<u32>,
+    /// Whether the model was fine-tuned with scaling applied
+    pub finetuned: Option<bool>,
+    /// YaRN parameters, present when `scaling_type` is `Yarn`
+    pub yarn: Option<YarnConfig>,
+}
+
+impl RopeScaling {
+    /// Get the frequency scale applied to positions (`1 / factor`), as used by ggml's rope ops
+    pub fn freq_scale(&self) -> f32 {
+        if self.factor == 0.0 {
+            1.0
+        } else {
+            1.0 / self.factor
+        }
+    }
+}
+
+/// Rotary position embedding configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct RopeConfig {
+    /// Number of dimensions rotated per head (defaults to the head dimension when absent)
+    pub dimension_count: Option<u32>,
+    /// Base frequency (theta)
+    pub freq_base: Option<f32>,
+    /// Context-extension scaling, if the model uses any
+    pub scaling: Option<RopeScaling>,
+}
+
+/// Extract the RoPE configuration from GGUF metadata
+///
+/// Handles both the `{arch}.rope.scaling.*` keys and the legacy `{arch}.rope.scale_linear` key.
+pub fn extract_rope_config(metadata: &HashMap<String, Value>) -> Result<RopeConfig> {
+    let arch = metadata
+        .get("general.architecture")
+        .and_then(|v| v.as_string())
+        .ok_or_else(|| GgufError::InvalidFormat("Missing general.architecture".to_string()))?;
+    let key = |suffix: &str| format!("{}.{}", arch, suffix);
+
+    let dimension_count = get_optional_u32_field(metadata, &key("rope.dimension_count"));
+    let freq_base = get_optional_f32_field(metadata, &key("rope.freq_base"));
+
+    let scaling_type = match metadata
+        .get(&key("rope.scaling.type"))
+        .and_then(|v| v.as_string())
+    {
+        Some(name) => Some(RopeScalingType::from_name(name).ok_or_else(|| {
+            GgufError::Unsupported(format!("Unknown RoPE scaling type: {}", name))
+        })?),
+        None => None,
+    };
+    let factor = get_optional_f32_field(metadata, &key("rope.scaling.factor"));
+    let scale_linear = get_optional_f32_field(metadata, &key("rope.scale_linear"));
+
+    let scaling = match (scaling_type, factor, scale_linear) {
+        (Some(RopeScalingType::None), _, _) | (None, None, None) => None,
+        (None, None, Some(factor)) => Some(RopeScaling {
+            scaling_type: RopeScalingType::Linear,
+            factor,
+            attn_factor: None,
+            original_context_length: None,
+            finetuned: None,
+            yarn: None,
+        }),
+        (scaling_type, factor, scale_linear) => {
+            let scaling_type = scaling_type.unwrap_or(RopeScalingType::Linear);
+            let yarn = (scaling_type == RopeScalingType::Yarn).then(|| YarnConfig {
+                ext_factor: get_optional_f32_field(metadata, &key("rope.scaling.yarn_ext_factor"))
+                    .unwrap_or(1.0),
+                attn_factor: get_optional_f32_field(
+                    metadata,
+                    &key("rope.scaling.yarn_attn_factor"),
+                )
+                .unwrap_or(1.0),
+                beta_fast: get_optional_f32_field(metadata, &key("rope.scaling.yarn_beta_fast"))
+                    .unwrap_or(32.0),
+                beta_slow: get_optional_f32_field(metadata, &key("rope.scaling.yarn_beta_slow"))
+                    .unwrap_or(1.0),
+                log_multiplier: get_optional_f32_field(
+                    metadata,
+                    &key("rope.scaling.yarn_log_multiplier"),
+                ),
+            });
+
+            Some(RopeScaling {
+                scaling_type,
+                factor: factor.or(scale_linear).unwrap_or(1.0),
+                attn_factor: get_optional_f32_field(metadata, &key("rope.scaling.attn_factor")),
+                original_context_length: get_optional_u32_field(
+                    metadata,
+                    &key("rope.scaling.original_context_length"),
+                ),
+                finetuned: get_optional_bool_field(metadata, &key("rope.scaling.finetuned")),
+                yarn,
+            })
+        }
+    };
+
+    Ok(RopeConfig {
+        dimension_count,
+        freq_base,
+        scaling,
     })
 }
 
@@ -71,3 +526,7 @@ fn get_optional_u32_field(metadata: &HashMap<String, Value>, key: &str) -> Optio
 fn get_optional_f32_field(metadata: &HashMap<String, Value>, key: &str) -> Option<f32> {
     metadata.get(key).and_then(|v| v.as_f64()).map(|v| v as f32)
 }
+
+fn get_optional_bool_field(metadata: &HashMap<String, Value>, key: &str) -> Option<bool> {
+    metadata.get(key).and_then(|v| v.as_bool())
+}
@@ -0,0 +1,302 @@
+//! Floating point format conversions
+//!
+//! Scalar and bulk conversions between F32 and the 16-bit formats used by GGUF tensors
+//! (IEEE half precision and bfloat16). Bulk conversions use F16C on x86_64 when the CPU
+//! supports it (detected at runtime) and NEON on aarch64, falling back to the scalar
+//! versions elsewhere. All paths produce bit-identical results, including for NaNs,
+//! which are quieted with their payload preserved as the hardware instructions do.
+
+/// Convert an IEEE half precision value (given as its bits) to F32
+pub fn f16_to_f32(bits: u16) -> f32 {
+    let sign = ((bits & 0x8000) as u32) << 16;
+    let exponent = ((bits >> 10) & 0x1f) as u32;
+    let mantissa = (bits & 0x3ff) as u32;
+
+    let f32_bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        // Subnormal: normalize so the leading 1 becomes the implicit bit
+        (0, _) => {
+            let shift = mantissa.leading_zeros() - 21;
+            sign | ((113 - shift) << 23) | (((mantissa << shift) & 0x3ff) << 13)
+        }
+        (31, 0) => sign | 0x7f80_0000,
+        (31, _) => sign | 0x7fc0_0000 | (mantissa << 13),
+        // Normal: adjust the exponent bias from 15 to 127
+        _ => sign | ((exponent + 112) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(f32_bits)
+}
+
+/// Convert an F32 value to IEEE half precision bits, rounding to nearest even
+pub fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        return if mantissa == 0 {
+            sign | 0x7c00
+        } else {
+            sign | 0x7e00 | (mantissa >> 13) as u16
+        };
+    }
+
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // Too small to round up to the smallest subnormal
+        if half_exponent < -10 {
+            return sign;
+        }
+        // Subnormal result, counted in units of 2^-24
+        let significand = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        let half = significand >> shift;
+        let rounded = round_nearest_even(half, significand & ((1 << shift) - 1), 1 << (shift - 1));
+        return sign | rounded as u16;
+    }
+
+    // A carry out of the mantissa correctly bumps the exponent (up to infinity)
+    let half = ((half_exponent as u32) << 10) | (mantissa >> 13);
+    sign | round_nearest_even(half, mantissa & 0x1fff, 0x1000) as u16
+}
+
+fn round_nearest_even(value: u32, remainder: u32, halfway: u32) -> u32 {
+    if remainder > halfway || (remainder == halfway && value & 1 == 1) {
+        value + 1
+    } else {
+        value
+    }
+}
+
+/// Convert a bfloat16 value (given as its bits) to F32
+pub fn bf16_to_f32(bits: u16) -> f32 {
+    f32::from_bits((bits as u32) << 16)
+}
+
+/// Convert an F32 value to bfloat16 bits, rounding to nearest even
+///
+/// NaNs stay NaN (quieted), as in ggml's `ggml_compute_fp32_to_bf16`.
+pub fn f32_to_bf16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    if value.is_nan() {
+        return ((bits >> 16) | 64) as u16;
+    }
+    ((bits + (0x7fff + ((bits >> 16) & 1))) >> 16) as u16
+}
+
+/// Convert a slice of half precision values to F32
+///
+/// # Panics
+///
+/// Panics if `src` and `dst` have different lengths.
+pub fn convert_f16_to_f32(src: &[u16], dst: &mut [f32]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("f16c") && is_x86_feature_detected!("avx") {
+        // SAFETY: the required CPU features were detected above
+        return unsafe { x86::f16_to_f32(src, dst) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    // SAFETY: NEON is part of the aarch64 baseline
+    return unsafe { neon::f16_to_f32(src, dst) };
+
+    #[allow(unreachable_code)]
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = f16_to_f32(*s);
+    }
+}
+
+/// Convert a slice of F32 values to half precision, rounding to nearest even
+///
+/// # Panics
+///
+/// Panics if `src` and `dst` have different lengths.
+pub fn convert_f32_to_f16(src: &[f32], dst: &mut [u16]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("f16c") && is_x86_feature_detected!("avx") {
+        // SAFETY: the required CPU features were detected above
+        return unsafe { x86::f32_to_f16(src, dst) };
+    }
+    #[cfg(target_arch = "aarch64")]
+    // SAFETY: NEON is part of the aarch64 baseline
+    return unsafe { neon::f32_to_f16(src, dst) };
+
+    #[allow(unreachable_code)]
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = f32_to_f16(*s);
+    }
+}
+
+/// Convert a slice of bfloat16 values to F32
+///
+/// # Panics
+///
+/// Panics if `src` and `dst` have different lengths.
+pub fn convert_bf16_to_f32(src: &[u16], dst: &mut [f32]) {
+    assert_eq!(
+        src.len(),
+        dst.len(),
+        "source and destination lengths differ"
+    );
+
+    #[cfg(target_arch = "x86_64")]
+    // SAFETY: SSE2 is part of the x86_64 baseline
+    return unsafe { x86::bf16_to_f32(src, dst) };
+    #[cfg(target_arch = "aarch64")]
+    // SAFETY: NEON is part of the aarch64 baseline
+    return unsafe { neon::bf16_to_f32(src, dst) };
+
+    #[allow(unreachable_code)]
+    for (d, s) in dst.iter_mut().zip(src) {
+        *d = bf16_to_f32(*s);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "avx,f16c")]
+    pub(super) unsafe fn f16_to_f32(src: &[u16], dst: &mut [f32]) {
+        let blocks = src.len() / 8;
+        for i in 0..blocks {
+            // SAFETY: `i * 8 + 8 <= len` for both slices, and the loads/stores are unaligned
+            unsafe {
+                let half = _mm_loadu_si128(src.as_ptr().add(i * 8) as *const __m128i);
+                _mm256_storeu_ps(dst.as_mut_ptr().add(i * 8), _mm256_cvtph_ps(half));
+            }
+        }
+        for j in blocks * 8..src.len() {
+            dst[j] = super::f16_to_f32(src[j]);
+        }
+    }
+
+    #[target_feature(enable = "avx,f16c")]
+    pub(super) unsafe fn f32_to_f16(src: &[f32], dst: &mut [u16]) {
+        let blocks = src.len() / 8;
+        for i in 0..blocks {
+            // SAFETY: `i * 8 + 8 <= len` for both slices, and the loads/stores are unaligned
+            unsafe {
+                let single = _mm256_loadu_ps(src.as_ptr().add(i * 8));
+                let half = _mm256_cvtps_ph::<_MM_FROUND_TO_NEAREST_INT>(single);
+                _mm_storeu_si128(dst.as_mut_ptr().add(i * 8) as *mut __m128i, half);
+            }
+        }
+        for j in blocks * 8..src.len() {
+            dst[j] = super::f32_to_f16(src[j]);
+        }
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn bf16_to_f32(src: &[u16], dst: &mut [f32]) {
+        let blocks = src.len() / 8;
+        let zero = _mm_setzero_si128();
+        for i in 0..blocks {
+            // SAFETY: `i * 8 + 8 <= len` for both slices, and the loads/stores are unaligned
+            unsafe {
+                let half = _mm_loadu_si128(src.as_ptr().add(i * 8) as *const __m128i);
+                // Interleaving zeros below each value shifts it into the high half
+                let lo = _mm_unpacklo_epi16(zero, half);
+                let hi = _mm_unpackhi_epi16(zero, half);
+                let out = dst.as_mut_ptr().add(i * 8);
+                _mm_storeu_ps(out, _mm_castsi128_ps(lo));
+                _mm_storeu_ps(out.add(4), _mm_castsi128_ps(hi));
+            }
+        }
+        for j in blocks * 8..src.len() {
+            dst[j] = super::bf16_to_f32(src[j]);
+        }
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+    use std::arch::asm;
+
+    // Half precision NEON types aren't stable yet, so the conversions use inline assembly
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn f16_to_f32(src: &[u16], dst: &mut [f32]) {
+        let blocks = src.len() / 8;
+        for i in 0..blocks {
+            // SAFETY: `i * 8 + 8 <= len` for both slices
+            unsafe {
+                let half = vld1q_u16(src.as_ptr().add(i * 8));
+                let lo: float32x4_t;
+                let hi: float32x4_t;
+                asm!(
+                    "fcvtl {lo:v}.4s, {half:v}.4h",
+                    "fcvtl2 {hi:v}.4s, {half:v}.8h",
+                    half = in(vreg) half,
+                    lo = out(vreg) lo,
+                    hi = out(vreg) hi,
+                    options(pure, nomem, nostack),
+                );
+                vst1q_f32(dst.as_mut_ptr().add(i * 8), lo);
+                vst1q_f32(dst.as_mut_ptr().add(i * 8 + 4), hi);
+            }
+        }
+        for j in blocks * 8..src.len() {
+            dst[j] = super::f16_to_f32(src[j]);
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn f32_to_f16(src: &[f32], dst: &mut [u16]) {
+        let blocks = src.len() / 8;
+        for i in 0..blocks {
+            // SAFETY: `i * 8 + 8 <= len` for both slices
+            unsafe {
+                let lo = vld1q_f32(src.as_ptr().add(i * 8));
+                let hi = vld1q_f32(src.as_ptr().add(i * 8 + 4));
+                let half: uint16x8_t;
+                asm!(
+                    "fcvtn {half:v}.4h, {lo:v}.4s",
+                    "fcvtn2 {half:v}.8h, {hi:v}.4s",
+                    lo = in(vreg) lo,
+                    hi = in(vreg) hi,
+                    half = out(vreg) half,
+                    options(pure, nomem, nostack),
+                );
+                vst1q_u16(dst.as_mut_ptr().add(i * 8), half);
+            }
+        }
+        for j in blocks * 8..src.len() {
+            dst[j] = super::f32_to_f16(src[j]);
+        }
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn bf16_to_f32(src: &[u16], dst: &mut [f32]) {
+        let blocks = src.len() / 8;
+        for i in 0..blocks {
+            // SAFETY: `i * 8 + 8 <= len` for both slices
+            unsafe {
+                let half = vld1q_u16(src.as_ptr().add(i * 8));
+                let lo = vshll_n_u16::<16>(vget_low_u16(half));
+                let hi = vshll_high_n_u16::<16>(half);
+                vst1q_u32(dst.as_mut_ptr().add(i * 8) as *mut u32, lo);
+                vst1q_u32(dst.as_mut_ptr().add(i * 8 + 4) as *mut u32, hi);
+            }
+        }
+        for j in blocks * 8..src.len() {
+            dst[j] = super::bf16_to_f32(src[j]);
+        }
+    }
+}
@@ -0,0 +1,664 @@
+//! Hugging Face checkpoint conversion
+//!
+//! Converts llama-family checkpoints (safetensors weights and `config.json`) to GGUF the
+//! way llama.cpp's `convert_hf_to_gguf.py` does: tensors are renamed (e.g.
+//! `model.layers.0.self_attn.q_proj.weight` to `blk.0.attn_q.weight`), the rows of the Q
+//! and K projections of llama models are permuted for ggml's RoPE layout, and the
+//! configuration is written as `{arch}.*` metadata. Tokenizers are not converted.
+//!
+//! [`export_gguf_to_hf`] goes the other way, writing a `model.safetensors` and a generated
+//! `config.json` from a GGUF model.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use serde_json::Value as Json;
+
+use crate::metadata::{GgufError, Result, TensorType, Value};
+use crate::model::block_index;
+use crate::requantize::{FileType, QUANTIZATION_VERSION, QuantizationPolicy, block_count};
+use crate::safetensors::{SafetensorsFile, SafetensorsWriter};
+use crate::schema::TensorKind;
+use crate::split::ShardedGguf;
+use crate::tensors::{Tensor, TensorInfo};
+use crate::writer::GgufWriter;
+
+/// A model architecture supported by the converter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HfArchitecture {
+    /// `LlamaForCausalLM` and `MistralForCausalLM`
+    Llama,
+    /// `Qwen2ForCausalLM`
+    Qwen2,
+    /// `Qwen3ForCausalLM`
+    Qwen3,
+}
+
+impl HfArchitecture {
+    /// Get the architecture of an HF `architectures` entry
+    pub fn from_hf_name(name: &str) -> Option<Self> {
+        match name {
+            "LlamaForCausalLM" | "MistralForCausalLM" => Some(Self::Llama),
+            "Qwen2ForCausalLM" => Some(Self::Qwen2),
+            "Qwen3ForCausalLM" => Some(Self::Qwen3),
+            _ => None,
+        }
+    }
+
+    /// Get the architecture of a `general.architecture` value
+    pub fn from_gguf_name(name: &str) -> Option<Self> {
+        match name {
+            "llama" | "mistral" => Some(Self::Llama),
+            "qwen2" => Some(Self::Qwen2),
+            "qwen3" => Some(Self::Qwen3),
+            _ => None,
+        }
+    }
+
+    /// Get the `general.architecture` name
+    pub fn gguf_name(&self) -> &'static str {
+        match self {
+            Self::Llama => "llama",
+            Self::Qwen2 => "qwen2",
+            Self::Qwen3 => "qwen3",
+        }
+    }
+
+    /// Get the HF model class name
+    pub fn hf_name(&self) -> &'static str {
+        match self {
+            Self::Llama => "LlamaForCausalLM",
+            Self::Qwen2 => "Qwen2ForCausalLM",
+            Self::Qwen3 => "Qwen3ForCausalLM",
+        }
+    }
+
+    /// Get the HF `model_type`
+    pub fn model_type(&self) -> &'static str {
+        self.gguf_name()
+    }
+
+    /// Check if llama.cpp permutes the Q and K projections of this architecture
+    pub fn permutes_qk(&self) -> bool {
+        matches!(self, Self::Llama)
+    }
+}
+
+/// HF names of the tensor kinds, without the `.weight`/`.bias` suffix
+const HF_PATTERNS: [(TensorKind, &str); 14] = [
+    (TensorKind::TokenEmbd, "model.embed_tokens"),
+    (TensorKind::OutputNorm, "model.norm"),
+    (TensorKind::Output, "lm_head"),
+    (TensorKind::AttnNorm, "model.layers.{bid}.input_layernorm"),
+    (TensorKind::AttnQ, "model.layers.{bid}.self_attn.q_proj"),
+    (TensorKind::AttnK, "model.layers.{bid}.self_attn.k_proj"),
+    (TensorKind::AttnV, "model.layers.{bid}.self_attn.v_proj"),
+    (TensorKind::AttnOut, "model.layers.{bid}.self_attn.o_proj"),
+    (TensorKind::AttnQNorm, "model.layers.{bid}.self_attn.q_norm"),
+    (TensorKind::AttnKNorm, "model.layers.{bid}.self_attn.k_norm"),
+    (
+        TensorKind::FfnNorm,
+        "model.layers.{bid}.post_attention_layernorm",
+    ),
+    (TensorKind::FfnGate, "model.layers.{bid}.mlp.gate_proj"),
+    (TensorKind::FfnUp, "model.layers.{bid}.mlp.up_proj"),
+    (TensorKind::FfnDown, "model.layers.{bid}.mlp.down_proj"),
+];
+
+/// Name of the RoPE frequency factors llama.cpp stores for llama 3 RoPE scaling
+pub const ROPE_FREQS: &str = "rope_freqs.weight";
+
+/// Map an HF tensor name to its GGUF name and kind
+pub fn gguf_tensor_name(hf_name: &str) -> Option<(String, TensorKind)> {
+    let (base, suffix) = hf_name.rsplit_once('.')?;
+    let bid = base
+        .strip_prefix("model.layers.")
+        .and_then(|rest| rest.split_once('.'))
+        .and_then(|(bid, _)| bid.parse::<usize>().ok());
+    HF_PATTERNS.iter().find_map(|&(kind, pattern)| {
+        let matches = match bid {
+            Some(bid) => pattern.replace("{bid}", &bid.to_string()) == base,
+            None => pattern == base,
+        };
+        let name = kind
+            .default_pattern()
+            .replace("{bid}", &bid.unwrap_or(0).to_string());
+        matches.then(|| (format!("{}.{}", name, suffix), kind))
+    })
+}
+
+/// Map a GGUF tensor name to its HF name and kind
+pub fn hf_tensor_name(gguf_name: &str) -> Option<(String, TensorKind)> {
+    let (base, suffix) = gguf_name.rsplit_once('.')?;
+    let bid = block_index(gguf_name);
+    HF_PATTERNS.iter().find_map(|&(kind, pattern)| {
+        let gguf_pattern = kind.default_pattern();
+        let matches = match bid {
+            Some(bid) => gguf_pattern.replace("{bid}", &bid.to_string()) == base,
+            None => gguf_pattern == base,
+        };
+        let name = pattern.replace("{bid}", &bid.unwrap_or(0).to_string());
+        matches.then(|| (format!("{}.{}", name, suffix), kind))
+    })
+}
+
+/// Reorder the rows of an HF Q or K projection (weight or bias) into llama.cpp's layout
+///
+/// HF checkpoints store each head's rotary pairs as two halves; ggml expects them
+/// interleaved. `head_count` is the number of heads of the projection (the KV head count
+/// for K).
+pub fn permute_qk(tensor: &Tensor, head_count: usize) -> Result<Tensor> {
+    reorder_head_rows(tensor, head_count, false)
+}
+
+/// Undo [`permute_qk`], restoring HF's layout
+pub fn unpermute_qk(tensor: &Tensor, head_count: usize) -> Result<Tensor> {
+    reorder_head_rows(tensor, head_count, true)
+}
+
+fn reorder_head_rows(tensor: &Tensor, head_count: usize, inverse: bool) -> Result<Tensor> {
+    let info = &tensor.info;
+    // Rows of a weight run along dims[0]; a bias has one element per row
+    let row_count = match info.dims.as_slice() {
+        [len] => *len,
+        dims => dims[1..].iter().product(),
+    } as usize;
+    let head_dim = row_count / head_count.max(1);
+    if head_count == 0
+        || row_count == 0
+        || !row_count.is_multiple_of(head_count)
+        || !head_dim.is_multiple_of(2)
+        || !tensor.data.len().is_multiple_of(row_count.max(1))
+        || (info.dims.len() == 1 && info.tensor_type.is_quantized())
+    {
+        return Err(GgufError::InvalidFormat(format!(
+            "Cannot permute tensor '{}' of shape {:?} for {} heads",
+            info.name, info.dims, head_count
+        )));
+    }
+
+    let row_size = tensor.data.len() / row_count;
+    let half = head_dim / 2;
+    let mut data = vec![0u8; tensor.data.len()];
+    for head in 0..head_count {
+        for i in 0..half {
+            for pair in 0..2 {
+                // HF row (head, pair, i) becomes ggml row (head, i, pair)
+                let hf = head * head_dim + pair * half + i;
+                let ggml = head * head_dim + i * 2 + pair;
+                let (src, dst) = if inverse { (ggml, hf) } else { (hf, ggml) };
+                data[dst * row_size..(dst + 1) * row_size]
+                    .copy_from_slice(&tensor.data[src * row_size..(src + 1) * row_size]);
+            }
+        }
+    }
+    Ok(Tensor {
+        info: info.clone(),
+        data,
+    })
+}
+
+/// Convert an HF `config.json` to GGUF metadata
+///
+/// Writes `general.architecture` and the `{arch}.*` model keys. Returns
+/// `GgufError::Unsupported` for architectures other than llama, mistral and qwen2/3,
+/// and for RoPE scaling types llama.cpp doesn't support.
+pub fn config_to_metadata(config: &Json) -> Result<(HfArchitecture, HashMap<String, Value>)> {
+    let class = config["architectures"]
+        .get(0)
+        .and_then(Json::as_str)
+        .ok_or_else(|| GgufError::InvalidFormat("config.json has no architectures".to_string()))?;
+    let arch = HfArchitecture::from_hf_name(class)
+        .ok_or_else(|| GgufError::Unsupported(format!("Architecture {}", class)))?;
+
+    let get_u32 = |key: &str| config[key].as_u64().map(|v| v as u32);
+    let require_u32 = |key: &str| {
+        get_u32(key).ok_or_else(|| GgufError::InvalidFormat(format!("config.json has no {}", key)))
+    };
+    let heads = require_u32("num_attention_heads")?;
+    let hidden = require_u32("hidden_size")?;
+    let head_dim = get_u32("head_dim");
+
+    let prefix = arch.gguf_name();
+    let mut metadata = HashMap::new();
+    let mut set = |key: &str, value: Value| {
+        metadata.insert(format!("{}.{}", prefix, key), value);
+    };
+    set(
+        "block_count",
+        Value::Uint32(require_u32("num_hidden_layers")?),
+    );
+    set(
+        "context_length",
+        Value::Uint32(require_u32("max_position_embeddings")?),
+    );
+    set("embedding_length", Value::Uint32(hidden));
+    set(
+        "feed_forward_length",
+        Value::Uint32(require_u32("intermediate_size")?),
+    );
+    set("attention.head_count", Value::Uint32(heads));
+    set(
+        "attention.head_count_kv",
+        Value::Uint32(get_u32("num_key_value_heads").unwrap_or(heads)),
+    );
+    if let Some(eps) = config["rms_norm_eps"].as_f64() {
+        set(
+            "attention.layer_norm_rms_epsilon",
+            Value::Float32(eps as f32),
+        );
+    }
+    if let Some(theta) = config["rope_theta"].as_f64() {
+        set("rope.freq_base", Value::Float32(theta as f32));
+    }
+    set(
+        "rope.dimension_count",
+        Value::Uint32(head_dim.unwrap_or(hidden / heads.max(1))),
+    );
+    if let Some(head_dim) = head_dim {
+        set("attention.key_length", Value::Uint32(head_dim));
+        set("attention.value_length", Value::Uint32(head_dim));
+    }
+    if let Some(vocab) = get_u32("vocab_size") {
+        set("vocab_size", Value::Uint32(vocab));
+    }
+
+    if let Some(scaling) = config["rope_scaling"].as_object() {
+        let kind = scaling
+            .get("rope_type")
+            .or_else(|| scaling.get("type"))
+            .and_then(Json::as_str)
+            .unwrap_or("none");
+        let factor = scaling.get("factor").and_then(Json::as_f64);
+        match (kind, factor) {
+            ("linear" | "yarn", Some(factor)) => {
+                set("rope.scaling.type", Value::String(kind.to_string()));
+                set("rope.scaling.factor", Value::Float32(factor as f32));
+                if let Some(context) = scaling
+                    .get("original_max_position_embeddings")
+                    .and_then(Json::as_u64)
+                {
+                    set(
+                        "rope.scaling.original_context_length",
+                        Value::Uint32(context as u32),
+                    );
+                }
+            }
+            // Applied through the `rope_freqs` tensor, see `llama3_rope_factors`
+            ("llama3", _) | ("none" | "default", _) => {}
+            _ => {
+                return Err(GgufError::Unsupported(format!(
+                    "RoPE scaling type {}",
+                    kind
+                )));
+            }
+        }
+    }
+
+    metadata.insert(
+        "general.architecture".to_string(),
+        Value::String(prefix.to_string()),
+    );
+    Ok((arch, metadata))
+}
+
+/// Generate an HF `config.json` from GGUF metadata
+///
+/// The inverse of [`config_to_metadata`]. Keys that depend on the tensors
+/// (`tie_word_embeddings`, `torch_dtype`, and `vocab_size` when not in the metadata) are
+/// left to the caller; [`export_gguf_to_hf`] fills them in.
+pub fn metadata_to_config(metadata: &HashMap<String, Value>) -> Result<(HfArchitecture, Json)> {
+    let name = metadata
+        .get("general.architecture")
+        .and_then(Value::as_string)
+        .ok_or_else(|| GgufError::InvalidFormat("Missing general.architecture".to_string()))?;
+    let arch = HfArchitecture::from_gguf_name(name)
+        .ok_or_else(|| GgufError::Unsupported(format!("Architecture {}", name)))?;
+
+    let get = |key: &str| metadata.get(&format!("{}.{}", name, key));
+    let get_u64 = |key: &str| get(key).and_then(Value::as_u64);
+    let require_u64 = |key: &str| {
+        get_u64(key).ok_or_else(|| GgufError::InvalidFormat(format!("Missing {}.{}", name, key)))
+    };
+    // Go through the shortest decimal form of the f32, so 1e-5 stays 1e-5
+    let get_f64 = |key: &str| {
+        get(key)
+            .and_then(Value::as_f64)
+            .and_then(|v| (v as f32).to_string().parse::<f64>().ok())
+    };
+
+    let heads = require_u64("attention.head_count")?;
+    let mut config = serde_json::json!({
+        "architectures": [arch.hf_name()],
+        "model_type": arch.model_type(),
+        "hidden_act": "silu",
+        "hidden_size": require_u64("embedding_length")?,
+        "intermediate_size": require_u64("feed_forward_length")?,
+        "num_hidden_layers": require_u64("block_count")?,
+        "num_attention_heads": heads,
+        "num_key_value_heads": get_u64("attention.head_count_kv").unwrap_or(heads),
+        "max_position_embeddings": require_u64("context_length")?,
+    });
+    if let Some(eps) = get_f64("attention.layer_norm_rms_epsilon") {
+        config["rms_norm_eps"] = eps.into();
+    }
+    if let Some(theta) = get_f64("rope.freq_base") {
+        config["rope_theta"] = theta.into();
+    }
+    if let Some(head_dim) = get_u64("attention.key_length") {
+        config["head_dim"] = head_dim.into();
+    }
+    if let Some(vocab) = get_u64("vocab_size") {
+        config["vocab_size"] = vocab.into();
+    }
+
+    let scaling = get("rope.scaling.type").and_then(Value::as_string);
+    if let (Some(kind @ ("linear" | "yarn")), Some(factor)) =
+        (scaling, get_f64("rope.scaling.factor"))
+    {
+        let mut rope_scaling = serde_json::json!({"rope_type": kind, "factor": factor});
+        if let Some(context) = get_u64("rope.scaling.original_context_length") {
+            rope_scaling["original_max_position_embeddings"] = context.into();
+        }
+        config["rope_scaling"] = rope_scaling;
+    }
+    Ok((arch, config))
+}
+
+/// Compute the RoPE frequency factors of llama 3 RoPE scaling, if the config uses it
+pub fn llama3_rope_factors(config: &Json) -> Option<Vec<f32>> {
+    let scaling = config["rope_scaling"].as_object()?;
+    if scaling.get("rope_type").and_then(Json::as_str) != Some("llama3") {
+        return None;
+    }
+    let get = |key: &str, default: f64| scaling.get(key).and_then(Json::as_f64).unwrap_or(default);
+    let factor = get("factor", 8.0);
+    let low_freq_factor = get("low_freq_factor", 1.0);
+    let high_freq_factor = get("high_freq_factor", 4.0);
+    let old_context = get("original_max_position_embeddings", 8192.0);
+
+    let base = config["rope_theta"].as_f64().unwrap_or(10000.0);
+    let heads = config["num_attention_heads"].as_u64()?;
+    let dim = config["head_dim"]
+        .as_u64()
+        .unwrap_or(config["hidden_size"].as_u64()? / heads.max(1)) as f64;
+
+    let low_freq_wavelen = old_context / low_freq_factor;
+    let high_freq_wavelen = old_context / high_freq_factor;
+    let factors = (0..dim as usize / 2)
+        .map(|i| {
+            let freq = 1.0 / base.powf(2.0 * i as f64 / dim);
+            let wavelen = 2.0 * PI / freq;
+            if wavelen < high_freq_wavelen {
+                1.0
+            } else if wavelen > low_freq_wavelen {
+                factor as f32
+            } else {
+                let smooth = (old_context / wavelen - low_freq_factor)
+                    / (high_freq_factor - low_freq_factor);
+                (1.0 / ((1.0 - smooth) / factor + smooth)) as f32
+            }
+        })
+        .collect();
+    Some(factors)
+}
+
+/// Convert an HF model directory (`config.json` and `*.safetensors`) to a GGUF file
+///
+/// Weight matrices are written as `tensor_type` (falling back as in
+/// [`QuantizationPolicy`] when rows aren't whole blocks), or in their original float
+/// type if `None`; 1-D tensors are always written as F32. Returns the infos of the
+/// tensors written.
+pub fn convert_hf_to_gguf<P: AsRef<Path>, Q: AsRef<Path>>(
+    model_dir: P,
+    output: Q,
+    tensor_type: Option<TensorType>,
+) -> Result<Vec<TensorInfo>> {
+    let model_dir = model_dir.as_ref();
+    let config: Json = serde_json::from_reader(File::open(model_dir.join("config.json"))?)
+        .map_err(|e| GgufError::InvalidFormat(format!("Invalid config.json: {}", e)))?;
+    let (arch, mut metadata) = config_to_metadata(&config)?;
+    let heads = config["num_attention_heads"].as_u64().unwrap_or(0) as usize;
+    let kv_heads = config["num_key_value_heads"]
+        .as_u64()
+        .map_or(heads, |n| n as usize);
+
+    let mut files = Vec::new();
+    for path in safetensors_paths(model_dir)? {
+        files.push(SafetensorsFile::open(path)?);
+    }
+    // (file, source info, output info, kind)
+    let mut tensors = Vec::new();
+    for (file, safetensors) in files.iter().enumerate() {
+        for info in safetensors.tensor_infos() {
+            if info.name.ends_with(".rotary_emb.inv_freq") {
+                continue;
+            }
+            let (name, kind) = gguf_tensor_name(&info.name).ok_or_else(|| {
+                GgufError::Unsupported(format!("No GGUF name for tensor '{}'", info.name))
+            })?;
+            let output = TensorInfo {
+                name,
+                offset: 0,
+                ..info.clone()
+            };
+            tensors.push((file, info.clone(), output, kind));
+        }
+    }
+
+    let block_count = block_count(&tensors.iter().map(|t| t.2.clone()).collect::<Vec<_>>());
+    let policy = tensor_type.map(QuantizationPolicy::new);
+    for (_, _, output, _) in &mut tensors {
+        output.tensor_type = match (&policy, output.dims.len()) {
+            (_, 1) => TensorType::F32,
+            (Some(policy), _) => policy.tensor_type(output, block_count),
+            (None, _) => output.tensor_type,
+        };
+    }
+    tensors.sort_by_key(|t| tensor_order(&t.2.name));
+
+    let rope_factors = llama3_rope_factors(&config).map(|factors| Tensor {
+        info: TensorInfo {
+            name: ROPE_FREQS.to_string(),
+            n_dims: 1,
+            dims: vec![factors.len() as u64],
+            tensor_type: TensorType::F32,
+            offset: 0,
+        },
+        data: factors.iter().flat_map(|f| f.to_le_bytes()).collect(),
+    });
+
+    if let Some(file_type) = tensor_type.and_then(FileType::for_type) {
+        metadata.insert(
+            "general.file_type".to_string(),
+            Value::Uint32(file_type as u32),
+        );
+        if tensor_type.is_some_and(|t| t.is_quantized()) {
+            metadata.insert(
+                "general.quantization_version".to_string(),
+                Value::Uint32(QUANTIZATION_VERSION),
+            );
+        }
+    }
+
+    let mut infos: Vec<TensorInfo> = tensors.iter().map(|t| t.2.clone()).collect();
+    if let Some(tensor) = &rope_factors {
+        infos.insert(0, tensor.info.clone());
+    }
+    let mut writer = GgufWriter::new(BufWriter::new(File::create(output)?), &metadata, &infos)?;
+    if let Some(tensor) = &rope_factors {
+        writer.write_tensor(tensor)?;
+    }
+    for (file, info, output, kind) in &tensors {
+        let mut tensor = files[*file].load_tensor(info)?;
+        tensor.info.name = output.name.clone();
+        if arch.permutes_qk() {
+            match kind {
+                TensorKind::AttnQ => tensor = permute_qk(&tensor, heads)?,
+                TensorKind::AttnK => tensor = permute_qk(&tensor, kv_heads)?,
+                _ => {}
+            }
+        }
+        if tensor.info.tensor_type != output.tensor_type {
+            tensor = tensor.quantize(output.tensor_type)?;
+        }
+        writer.write_tensor(&tensor)?;
+    }
+    writer.finish()?;
+    Ok(infos)
+}
+
+/// Export a GGUF model as `model.safetensors` and `config.json` in `output_dir`
+///
+/// Tensors are renamed to their HF names, dequantized or converted to `tensor_type`
+/// (F32, F16 or BF16), and the Q/K permutation of llama models is undone. The
+/// `rope_freqs` tensor is dropped, so llama 3 RoPE scaling has to be restored in the
+/// config by hand. Returns the infos of the tensors written.
+///
+/// # Errors
+///
+/// Returns `GgufError::Unsupported` for other output types, other architectures, and
+/// tensors without an HF name or of types that can't be dequantized (e.g. IQ2_XXS).
+pub fn export_gguf_to_hf<P: AsRef<Path>>(
+    model: &ShardedGguf,
+    output_dir: P,
+    tensor_type: TensorType,
+) -> Result<Vec<TensorInfo>> {
+    let torch_dtype = match tensor_type {
+        TensorType::F32 => "float32",
+        TensorType::F16 => "float16",
+        TensorType::BF16 => "bfloat16",
+        _ => {
+            return Err(GgufError::Unsupported(format!(
+                "Exporting {:?} tensors",
+                tensor_type
+            )));
+        }
+    };
+    let (arch, mut config) = metadata_to_config(model.metadata())?;
+    let heads = config["num_attention_heads"].as_u64().unwrap_or(0) as usize;
+    let kv_heads = config["num_key_value_heads"].as_u64().unwrap_or(0) as usize;
+
+    // (GGUF name, output info, kind)
+    let mut tensors = Vec::new();
+    for info in model.tensor_infos() {
+        if info.name == ROPE_FREQS {
+            continue;
+        }
+        let (name, kind) = hf_tensor_name(&info.name).ok_or_else(|| {
+            GgufError::Unsupported(format!("No HF name for tensor '{}'", info.name))
+        })?;
+        // Fail before the output is created rather than halfway through writing it
+        if !info.tensor_type.can_dequantize() {
+            return Err(GgufError::Unsupported(format!(
+                "Exporting tensor '{}' of type {}",
+                info.name,
+                info.tensor_type.name()
+            )));
+        }
+        let output = TensorInfo {
+            name,
+            tensor_type,
+            offset: 0,
+            ..info.clone()
+        };
+        tensors.push((info.name.clone(), output, kind));
+    }
+
+    let embeddings = model.tensor_info("token_embd.weight").map(|(_, info)| info);
+    if config.get("vocab_size").is_none()
+        && let Some(vocab) = embeddings.and_then(|info| info.dims.get(1))
+    {
+        config["vocab_size"] = (*vocab).into();
+    }
+    config["tie_word_embeddings"] = model.tensor_info("output.weight").is_none().into();
+    config["torch_dtype"] = torch_dtype.into();
+
+    let output_dir = output_dir.as_ref();
+    std::fs::create_dir_all(output_dir)?;
+    let infos: Vec<TensorInfo> = tensors.iter().map(|t| t.1.clone()).collect();
+    let file = BufWriter::new(File::create(output_dir.join("model.safetensors"))?);
+    let metadata = HashMap::from([("format".to_string(), "pt".to_string())]);
+    let mut writer = SafetensorsWriter::new(file, &metadata, &infos)?;
+    for (name, output, kind) in &tensors {
+        let mut tensor = model.load_tensor(name)?;
+        if tensor.info.tensor_type != tensor_type {
+            tensor = tensor.quantize(tensor_type)?;
+        }
+        tensor.info.name = output.name.clone();
+        if arch.permutes_qk() {
+            match kind {
+                TensorKind::AttnQ => tensor = unpermute_qk(&tensor, heads)?,
+                TensorKind::AttnK => tensor = unpermute_qk(&tensor, kv_heads)?,
+                _ => {}
+            }
+        }
+        writer.write_tensor(&tensor)?;
+    }
+    writer.finish()?;
+
+    let config = serde_json::to_string_pretty(&config)
+        .map_err(|e| GgufError::InvalidFormat(format!("config.json: {}", e)))?;
+    std::fs::write(output_dir.join("config.json"), config + "\n")?;
+    Ok(infos)
+}
+
+/// List the weight files of a model directory in name order
+///
+/// Sharded checkpoints list their files in `model.safetensors.index.json`; without an
+/// index, the `model*.safetensors` files are used. Other safetensors files, such as the
+/// `consolidated.safetensors` copy of the weights shipped with Mistral models, are ignored.
+fn safetensors_paths(model_dir: &Path) -> Result<Vec<PathBuf>> {
+    let index_path = model_dir.join("model.safetensors.index.json");
+    let mut paths = Vec::new();
+    if index_path.exists() {
+        let index: Json = serde_json::from_reader(File::open(&index_path)?).map_err(|e| {
+            GgufError::InvalidFormat(format!("Invalid model.safetensors.index.json: {}", e))
+        })?;
+        let weight_map = index["weight_map"].as_object().ok_or_else(|| {
+            GgufError::InvalidFormat("model.safetensors.index.json has no weight_map".to_string())
+        })?;
+        for file in weight_map.values() {
+            // Only accept plain file names, which can't point outside the model directory
+            let name = file
+                .as_str()
+                .filter(|name| Path::new(name).file_name() == Some(name.as_ref()))
+                .ok_or_else(|| {
+                    GgufError::InvalidFormat(format!("Invalid weight file in index: {}", file))
+                })?;
+            paths.push(model_dir.join(name));
+        }
+    } else {
+        for entry in std::fs::read_dir(model_dir)? {
+            let path = entry?.path();
+            let is_weights = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("model") && name.ends_with(".safetensors"));
+            if is_weights {
+                paths.push(path);
+            }
+        }
+    }
+    if paths.is_empty() {
+        return Err(GgufError::InvalidFormat(format!(
+            "No safetensors files in {}",
+            model_dir.display()
+        )));
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+/// Sort key putting the embeddings first and the output head last
+fn tensor_order(name: &str) -> (usize, usize, String) {
+    match block_index(name) {
+        _ if name.starts_with("token_embd.") => (0, 0, name.to_string()),
+        Some(bid) => (1, bid, name.to_string()),
+        None => (2, 0, name.to_string()),
+    }
+}
@@ -0,0 +1,437 @@
+//! Importance matrices
+//!
+//! An importance matrix ("imatrix") records, for each weight tensor, the squared
+//! activations seen by each of its input columns while running a model over calibration
+//! data. Quantizers use the mean values as per-column weights, so the error on the
+//! columns that matter most is minimized (see [`quant::quantize_weighted`]).
+//!
+//! Both of llama.cpp's formats are read: GGUF files with `{name}.in_sum2` and
+//! `{name}.counts` tensors, and the older `.dat` files. Summary statistics per entry
+//! and per layer ([`Imatrix::entry_stats`], [`Imatrix::layer_stats`]) help choosing
+//! which tensors to keep at higher precision.
+//!
+//! [`quant::quantize_weighted`]: crate::quant::quantize_weighted
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::metadata::{GGUF_MAGIC, GgufError, GgufHeader, GgufReader, Result, Value};
+use crate::model::block_index;
+use crate::tensors::{TensorInfo, TensorLoader};
+
+/// Imatrix data of one weight tensor
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImatrixEntry {
+    /// Sums of squared activations per column, for each matrix in turn
+    ///
+    /// MoE expert tensors hold one matrix per expert; other tensors a single one.
+    pub sums: Vec<f32>,
+    /// Number of activations summed for each matrix
+    pub counts: Vec<f32>,
+}
+
+impl ImatrixEntry {
+    /// Get the number of matrices (experts) covered by this entry
+    pub fn matrix_count(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Get the number of columns of each matrix
+    pub fn column_count(&self) -> usize {
+        self.sums.len() / self.counts.len().max(1)
+    }
+
+    /// Get the mean squared activation of each column, matrix after matrix
+    ///
+    /// Matrices that saw no activations (e.g. experts never routed to during
+    /// calibration) get uniform weights of 1, as in llama.cpp.
+    pub fn importance(&self) -> Vec<f32> {
+        let columns = self.column_count();
+        let mut importance = Vec::with_capacity(self.sums.len());
+        for (sums, &count) in self.sums.chunks(columns.max(1)).zip(&self.counts) {
+            if count > 0.0 {
+                importance.extend(sums.iter().map(|s| s / count));
+            } else {
+                importance.extend(std::iter::repeat_n(1.0, sums.len()));
+            }
+        }
+        importance
+    }
+}
+
+/// An importance matrix, mapping weight tensor names to their activation statistics
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Imatrix {
+    /// Entries by weight tensor name (e.g. `"blk.0.attn_q.weight"`)
+    pub entries: HashMap<String, ImatrixEntry>,
+    /// Calibration datasets the matrix was computed from
+    pub datasets: Vec<String>,
+    /// Number of calibration chunks processed
+    pub chunk_count: u32,
+    /// Tokens per calibration chunk, if recorded
+    pub chunk_size: Option<u32>,
+}
+
+impl Imatrix {
+    /// Read an imatrix file in either format
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        reader.seek(SeekFrom::Start(0))?;
+
+        if u32::from_le_bytes(magic) == GGUF_MAGIC {
+            Self::read_gguf(&mut reader)
+        } else {
+            Self::read_legacy(&mut reader)
+        }
+    }
+
+    /// Read an imatrix stored as GGUF
+    pub fn read_gguf<R: Read + Seek>(reader: &mut R) -> Result<Self> {
+        let header = GgufHeader::parse(reader)?;
+        let metadata = GgufReader::read_metadata(reader, header.n_kv)?;
+        let tensor_infos = TensorLoader::read_tensor_info(reader, header.n_tensors)?;
+        let tensor_data_start = TensorLoader::get_aligned_tensor_data_start(reader, &metadata)?;
+
+        let mut sums = HashMap::new();
+        let mut counts = HashMap::new();
+        for info in &tensor_infos {
+            let (name, map) = if let Some(name) = info.name.strip_suffix(".in_sum2") {
+                (name, &mut sums)
+            } else if let Some(name) = info.name.strip_suffix(".counts") {
+                (name, &mut counts)
+            } else {
+                continue;
+            };
+            let tensor = TensorLoader::load_tensor(reader, info, tensor_data_start)?;
+            map.insert(name.to_string(), tensor.as_f32_vec()?);
+        }
+
+        let mut entries = HashMap::with_capacity(sums.len());
+        for (name, sums) in sums {
+            let counts = counts.remove(&name).ok_or_else(|| {
+                GgufError::InvalidFormat(format!("Missing counts for imatrix entry '{}'", name))
+            })?;
+            if counts.is_empty() || !sums.len().is_multiple_of(counts.len()) {
+                return Err(GgufError::InvalidFormat(format!(
+                    "Imatrix entry '{}' has {} sums for {} matrices",
+                    name,
+                    sums.len(),
+                    counts.len()
+                )));
+            }
+            entries.insert(name, ImatrixEntry { sums, counts });
+        }
+
+        let datasets = match metadata.get("imatrix.datasets") {
+            Some(Value::Array(_, values)) => values
+                .iter()
+                .filter_map(|v| v.as_string().map(str::to_string))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let get_u32 = |key: &str| metadata.get(key).and_then(|v| v.as_u64()).map(|v| v as u32);
+
+        Ok(Self {
+            entries,
+            datasets,
+            chunk_count: get_u32("imatrix.chunk_count").unwrap_or(0),
+            chunk_size: get_u32("imatrix.chunk_size"),
+        })
+    }
+
+    /// Read an imatrix in llama.cpp's legacy `.dat` format
+    ///
+    /// Each entry stores a single call count, and sums already scaled to it.
+    pub fn read_legacy<R: Read>(reader: &mut R) -> Result<Self> {
+        let n_entries = read_count(reader, "entry count")?;
+        let mut entries = HashMap::with_capacity(n_entries);
+        for _ in 0..n_entries {
+            let name_len = read_count(reader, "name length")?;
+            let mut name = vec![0u8; name_len];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name)?;
+
+            let ncall = read_i32_le(reader)?;
+            let nval = read_count(reader, "value count")?;
+            let mut sums = vec![0u8; nval * 4];
+            reader.read_exact(&mut sums)?;
+            let sums = sums
+                .chunks_exact(4)
+                .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect();
+
+            entries.insert(
+                name,
+                ImatrixEntry {
+                    sums,
+                    counts: vec![ncall as f32],
+                },
+            );
+        }
+
+        // Newer files append the chunk count and the dataset name
+        let mut imatrix = Self {
+            entries,
+            ..Self::default()
+        };
+        let mut first = [0u8; 1];
+        if reader.read(&mut first)? == 0 {
+            return Ok(imatrix);
+        }
+        let mut rest = [0u8; 3];
+        reader.read_exact(&mut rest)?;
+        let chunk_count = i32::from_le_bytes([first[0], rest[0], rest[1], rest[2]]);
+        imatrix.chunk_count = chunk_count.max(0) as u32;
+
+        let mut dataset = vec![0u8; read_count(reader, "dataset name length")?];
+        reader.read_exact(&mut dataset)?;
+        imatrix.datasets.push(String::from_utf8(dataset)?);
+        Ok(imatrix)
+    }
+
+    /// Get the per-column importance of a weight tensor, if the matrix covers it
+    ///
+    /// See [`ImatrixEntry::importance`].
+    pub fn importance(&self, name: &str) -> Option<Vec<f32>> {
+        self.entries.get(name).map(ImatrixEntry::importance)
+    }
+
+    /// Get the entry for a tensor, if there is one with a matching shape
+    ///
+    /// An entry matches when it has one importance per column of the tensor, for a
+    /// single matrix or for each matrix (expert) of a 3-D tensor.
+    pub fn entry_for(&self, info: &TensorInfo) -> Option<&ImatrixEntry> {
+        self.entries
+            .get(&info.name)
+            .filter(|entry| entry_matches(entry, info))
+    }
+
+    /// Match the entries against a model's tensors
+    pub fn coverage(&self, tensor_infos: &[TensorInfo]) -> ImatrixCoverage {
+        let mut coverage = ImatrixCoverage::default();
+        for info in tensor_infos {
+            match self.entries.get(&info.name) {
+                Some(entry) if entry_matches(entry, info) => {
+                    coverage.matched.push(info.name.clone())
+                }
+                Some(_) => coverage.mismatched.push(info.name.clone()),
+                // Only matrix multiplication weights collect activations
+                None if info.dims.len() >= 2
+                    && info.tensor_type.is_float()
+                    && info.name.ends_with(".weight")
+                    && !info.name.starts_with("token_embd.") =>
+                {
+                    coverage.missing.push(info.name.clone())
+                }
+                None => {}
+            }
+        }
+        let mut unused: Vec<String> = self
+            .entries
+            .keys()
+            .filter(|name| !tensor_infos.iter().any(|info| &info.name == *name))
+            .cloned()
+            .collect();
+        unused.sort();
+        coverage.unused = unused;
+        coverage
+    }
+
+    /// Compute statistics of every entry, ordered by layer then name
+    ///
+    /// `top_columns` is the number of most important columns to report per entry.
+    pub fn entry_stats(&self, top_columns: usize) -> Vec<EntryStats> {
+        let mut stats: Vec<EntryStats> = self
+            .entries
+            .iter()
+            .map(|(name, entry)| EntryStats::new(name, entry, top_columns))
+            .collect();
+        stats.sort_by(|a, b| (a.layer, &a.name).cmp(&(b.layer, &b.name)));
+        stats
+    }
+
+    /// Aggregate the importance of the entries of each `blk.{N}.` layer
+    ///
+    /// Entries outside the transformer blocks (e.g. `output.weight`) are not included.
+    pub fn layer_stats(&self) -> Vec<LayerStats> {
+        let stats = self.entry_stats(0);
+        let total: f64 = stats
+            .iter()
+            .filter(|s| s.layer.is_some())
+            .map(|s| s.sum)
+            .sum();
+
+        let mut layers: Vec<LayerStats> = Vec::new();
+        for entry in &stats {
+            let Some(layer) = entry.layer else { continue };
+            if layers.last().is_none_or(|l| l.layer != layer) {
+                layers.push(LayerStats {
+                    layer,
+                    entry_count: 0,
+                    sum: 0.0,
+                    max_mean: 0.0,
+                    share: 0.0,
+                });
+            }
+            let stats = layers.last_mut().unwrap();
+            stats.entry_count += 1;
+            stats.sum += entry.sum;
+            stats.max_mean = stats.max_mean.max(entry.mean);
+        }
+        for layer in &mut layers {
+            layer.share = if total > 0.0 { layer.sum / total } else { 0.0 };
+        }
+        layers
+    }
+}
+
+fn entry_matches(entry: &ImatrixEntry, info: &TensorInfo) -> bool {
+    let matrices: u64 = info.dims.iter().skip(2).product();
+    entry.column_count() as u64 == info.row_len()
+        && (entry.matrix_count() == 1 || entry.matrix_count() as u64 == matrices)
+}
+
+/// How the entries of an imatrix line up with a model's tensors
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImatrixCoverage {
+    /// Tensors with a matching entry
+    pub matched: Vec<String>,
+    /// Tensors whose entry has the wrong number of columns or matrices
+    pub mismatched: Vec<String>,
+    /// Weight matrices without an entry
+    pub missing: Vec<String>,
+    /// Entries naming no tensor of the model, sorted
+    pub unused: Vec<String>,
+}
+
+/// Importance of one column of an entry
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColumnImportance {
+    /// Matrix (expert) index, 0 for dense tensors
+    pub matrix: usize,
+    /// Column index within the matrix
+    pub column: usize,
+    /// Mean squared activation
+    pub importance: f32,
+}
+
+/// Distribution of the importance of one entry
+///
+/// Matrices that saw no activations are left out of the statistics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryStats {
+    /// Weight tensor name
+    pub name: String,
+    /// Transformer block index, if the tensor belongs to one
+    pub layer: Option<usize>,
+    /// Number of matrices (experts)
+    pub matrix_count: usize,
+    /// Number of matrices that saw activations
+    pub active_matrices: usize,
+    /// Number of columns of each matrix
+    pub column_count: usize,
+    /// Sum of the importance of all columns
+    pub sum: f64,
+    /// Mean column importance
+    pub mean: f32,
+    /// Standard deviation of the column importance
+    pub std_dev: f32,
+    /// Smallest column importance
+    pub min: f32,
+    /// Median column importance
+    pub median: f32,
+    /// Largest column importance
+    pub max: f32,
+    /// Fraction of columns with zero importance
+    pub zero_fraction: f32,
+    /// Most important columns, in decreasing order of importance
+    pub top_columns: Vec<ColumnImportance>,
+}
+
+impl EntryStats {
+    fn new(name: &str, entry: &ImatrixEntry, top_columns: usize) -> Self {
+        let columns = entry.column_count();
+        let mut values: Vec<ColumnImportance> = Vec::with_capacity(entry.sums.len());
+        let mut active_matrices = 0;
+        for (matrix, (sums, &count)) in entry
+            .sums
+            .chunks(columns.max(1))
+            .zip(&entry.counts)
+            .enumerate()
+        {
+            if count <= 0.0 {
+                continue;
+            }
+            active_matrices += 1;
+            values.extend(sums.iter().enumerate().map(|(column, s)| ColumnImportance {
+                matrix,
+                column,
+                importance: s / count,
+            }));
+        }
+
+        let n = values.len().max(1) as f64;
+        let sum: f64 = values.iter().map(|v| v.importance as f64).sum();
+        let mean = sum / n;
+        let variance = values
+            .iter()
+            .map(|v| (v.importance as f64 - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        let zeros = values.iter().filter(|v| v.importance == 0.0).count();
+
+        values.sort_by(|a, b| b.importance.total_cmp(&a.importance));
+        let median = match values.len() {
+            0 => 0.0,
+            len if len % 2 == 1 => values[len / 2].importance,
+            len => (values[len / 2 - 1].importance + values[len / 2].importance) / 2.0,
+        };
+        Self {
+            name: name.to_string(),
+            layer: block_index(name),
+            matrix_count: entry.matrix_count(),
+            active_matrices,
+            column_count: columns,
+            sum,
+            mean: mean as f32,
+            std_dev: variance.sqrt() as f32,
+            min: values.last().map_or(0.0, |v| v.importance),
+            median,
+            max: values.first().map_or(0.0, |v| v.importance),
+            zero_fraction: zeros as f32 / n as f32,
+            top_columns: values.into_iter().take(top_columns).collect(),
+        }
+    }
+}
+
+/// Importance aggregated over the entries of one layer
+#[derive(Debug, Clone, PartialEq)]
+pub struct LayerStats {
+    /// Transformer block index
+    pub layer: usize,
+    /// Number of entries in the layer
+    pub entry_count: usize,
+    /// Sum of the importance of all columns of the layer's entries
+    pub sum: f64,
+    /// Largest mean column importance of an entry
+    pub max_mean: f32,
+    /// Fraction of the importance of all layers held by this one
+    pub share: f64,
+}
+
+fn read_i32_le<R: Read>(reader: &mut R) -> Result<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+/// Read a non-negative i32 count
+fn read_count<R: Read>(reader: &mut R, what: &str) -> Result<usize> {
+    let count = read_i32_le(reader)?;
+    usize::try_from(count)
+        .map_err(|_| GgufError::InvalidFormat(format!("Negative imatrix {}: {}", what, count)))
+}
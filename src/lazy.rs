@@ -0,0 +1,150 @@
+//! Lazily loaded tensors
+//!
+//! A [`LazyTensor`] holds a tensor's metadata and a handle to the file it lives in, and
+//! only reads its data on first access. Models built from lazy tensors can be walked one
+//! layer at a time with [`TransformerBlock::prefetch`] and [`TransformerBlock::evict`],
+//! keeping memory use bounded.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::metadata::Result;
+use crate::model::TransformerBlock;
+use crate::tensors::{Tensor, TensorHandle, TensorInfo, TensorLoader};
+
+trait ReadSeek: Read + Seek + Send {}
+
+impl<R: Read + Seek + Send> ReadSeek for R {}
+
+/// A shared, seekable source of tensor data (usually the GGUF file)
+pub struct TensorSource {
+    reader: Mutex<Box<dyn ReadSeek>>,
+    tensor_data_start: u64,
+}
+
+impl TensorSource {
+    /// Create a source from a reader and the start of its tensor data section
+    pub fn new<R: Read + Seek + Send + 'static>(reader: R, tensor_data_start: u64) -> Self {
+        Self {
+            reader: Mutex::new(Box::new(reader)),
+            tensor_data_start,
+        }
+    }
+
+    /// Open a GGUF file as a tensor source
+    pub fn open<P: AsRef<Path>>(path: P, tensor_data_start: u64) -> Result<Self> {
+        let file = File::open(path)?;
+        Ok(Self::new(BufReader::new(file), tensor_data_start))
+    }
+
+    /// Get the start of the tensor data section
+    pub fn tensor_data_start(&self) -> u64 {
+        self.tensor_data_start
+    }
+
+    /// Read a tensor's data from the source
+    pub fn load(&self, info: &TensorInfo) -> Result<Tensor> {
+        let mut reader = lock(&self.reader);
+        TensorLoader::load_tensor(&mut *reader, info, self.tensor_data_start)
+    }
+}
+
+impl fmt::Debug for TensorSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TensorSource")
+            .field("tensor_data_start", &self.tensor_data_start)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A tensor whose data is read from its source on first access
+///
+/// Clones share the same cached data.
+#[derive(Debug, Clone)]
+pub struct LazyTensor {
+    /// Tensor metadata
+    pub info: TensorInfo,
+    source: Arc<TensorSource>,
+    data: Arc<Mutex<Option<Arc<Vec<u8>>>>>,
+}
+
+impl LazyTensor {
+    /// Create a lazy handle for a tensor in `source`
+    pub fn new(info: TensorInfo, source: Arc<TensorSource>) -> Self {
+        Self {
+            info,
+            source,
+            data: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Get the raw tensor data, reading it from the source if not loaded yet
+    pub fn data(&self) -> Result<Arc<Vec<u8>>> {
+        let mut cached = lock(&self.data);
+        if let Some(data) = cached.as_ref() {
+            return Ok(Arc::clone(data));
+        }
+
+        let data = Arc::new(self.source.load(&self.info)?.data);
+        *cached = Some(Arc::clone(&data));
+        Ok(data)
+    }
+
+    /// Check if the data is currently loaded
+    pub fn is_loaded(&self) -> bool {
+        lock(&self.data).is_some()
+    }
+
+    /// Load the data now, so a later [`LazyTensor::data`] call doesn't block on I/O
+    pub fn prefetch(&self) -> Result<()> {
+        self.data().map(|_| ())
+    }
+
+    /// Drop the cached data
+    ///
+    /// Memory is freed once all `Arc`s returned by [`LazyTensor::data`] are dropped too.
+    pub fn evict(&self) {
+        lock(&self.data).take();
+    }
+
+    /// Materialize an owned [`Tensor`]
+    pub fn load(&self) -> Result<Tensor> {
+        Ok(Tensor {
+            info: self.info.clone(),
+            data: self.data()?.as_ref().clone(),
+        })
+    }
+}
+
+impl TensorHandle for LazyTensor {
+    fn info(&self) -> &TensorInfo {
+        &self.info
+    }
+}
+
+impl TransformerBlock<LazyTensor> {
+    /// Load the data of every tensor in this block
+    pub fn prefetch(&self) -> Result<()> {
+        for tensor in self.tensors() {
+            tensor.prefetch()?;
+        }
+        Ok(())
+    }
+
+    /// Drop the cached data of every tensor in this block
+    pub fn evict(&self) {
+        for tensor in self.tensors() {
+            tensor.evict();
+        }
+    }
+}
+
+/// Lock a mutex, recovering the data if another thread panicked while holding it
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
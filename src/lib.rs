@@ -1,14 +1,46 @@
 //! GGUF Interface Library - Provides functionality for parsing GGUF files
 
+#[cfg(feature = "ndarray")]
+pub mod array;
 pub mod config;
+pub mod convert;
+pub mod hf;
+pub mod imatrix;
+pub mod lazy;
 pub mod metadata;
 pub mod model;
+pub mod npy;
+pub mod parallel;
+pub mod quant;
+pub mod requantize;
+pub mod safetensors;
+pub mod schema;
+pub mod split;
 pub mod tensors;
+pub mod validate;
+pub mod writer;
 
 // Re-export the main types for easier access
-pub use config::extract_model_config;
+pub use config::{
+    ArchitectureConfig, RopeConfig, extract_architecture_config, extract_model_config,
+    extract_rope_config,
+};
+pub use hf::{HfArchitecture, convert_hf_to_gguf, export_gguf_to_hf};
+pub use imatrix::{EntryStats, Imatrix, ImatrixCoverage, ImatrixEntry, LayerStats};
+pub use lazy::{LazyTensor, TensorSource};
 pub use metadata::{
     GGUF_MAGIC, GgufError, GgufHeader, GgufReader, Result, TensorType, Value, ValueType,
 };
-pub use model::{Model, ModelBuilder, ModelConfig};
-pub use tensors::{Tensor, TensorInfo, TensorLoader};
+pub use model::{
+    FeedForward, LayerSelection, Model, ModelBuilder, ModelConfig, ModelFragment, OutputWeights,
+};
+pub use npy::NpyDtype;
+pub use parallel::ParallelLoader;
+pub use requantize::{FileType, QuantizationPolicy, TypeRule};
+pub use schema::{SchemaRegistry, TensorKind, TensorSchema};
+pub use split::{ShardedGguf, SplitInfo};
+pub use tensors::{
+    CancellationToken, LoadProgress, Tensor, TensorHandle, TensorInfo, TensorLoader,
+};
+pub use validate::ShapeMismatch;
+pub use writer::GgufWriter;
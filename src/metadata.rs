@@ -5,6 +5,9 @@ use std::io::{self, Read};
 /// Magic number for GGUF files ('GGUF' in little-endian)
 pub const GGUF_MAGIC: u32 = 0x46554747;
 
+/// Default alignment of the tensor data section, used when `general.alignment` is absent
+pub const GGUF_DEFAULT_ALIGNMENT: u64 = 32;
+
 /// Result type for GGUF operations
 pub type Result<T> = std::result::Result<T, GgufError>;
 
@@ -19,6 +22,8 @@ pub enum GgufError {
     Unsupported(String),
     /// Invalid UTF-8 string data
     InvalidUtf8(std::string::FromUtf8Error),
+    /// The operation was cancelled through a cancellation token
+    Cancelled,
 }
 
 impl fmt::Display for GgufError {
@@ -28,6 +33,7 @@ impl fmt::Display for GgufError {
             GgufError::InvalidFormat(msg) => write!(f, "Invalid GGUF format: {}", msg),
             GgufError::Unsupported(msg) => write!(f, "Unsupported feature: {}", msg),
             GgufError::InvalidUtf8(err) => write!(f, "Invalid UTF-8: {}", err),
+            GgufError::Cancelled => write!(f, "Operation cancelled"),
         }
     }
 }
@@ -153,7 +159,7 @@ impl ValueType {
 }
 
 /// A parsed GGUF metadata value, holding the actual data.
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Uint8(u8),
     Int8(i8),
@@ -239,7 +245,7 @@ impl Value {
 }
 
 /// GGUF tensor data types
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 #[repr(u32)]
 pub enum TensorType {
     F32 = 0,
@@ -270,6 +276,7 @@ pub enum TensorType {
     I64 = 27,
     F64 = 28,
     Iq1M = 29,
+    BF16 = 30,
 }
 
 impl TensorType {
@@ -304,9 +311,183 @@ impl TensorType {
             27 => Some(TensorType::I64),
             28 => Some(TensorType::F64),
             29 => Some(TensorType::Iq1M),
+            30 => Some(TensorType::BF16),
             _ => None,
         }
     }
+
+    /// Get the number of elements per quantization block (1 for unquantized types)
+    pub fn block_size(&self) -> u64 {
+        match self {
+            TensorType::F32
+            | TensorType::F16
+            | TensorType::BF16
+            | TensorType::F64
+            | TensorType::I8
+            | TensorType::I16
+            | TensorType::I32
+            | TensorType::I64 => 1,
+            TensorType::Q40
+            | TensorType::Q41
+            | TensorType::Q50
+            | TensorType::Q51
+            | TensorType::Q80
+            | TensorType::Q81
+            | TensorType::Iq4Nl => 32,
+            TensorType::Q2K
+            | TensorType::Q3K
+            | TensorType::Q4K
+            | TensorType::Q5K
+            | TensorType::Q6K
+            | TensorType::Q8K
+            | TensorType::Iq2Xxs
+            | TensorType::Iq2Xs
+            | TensorType::Iq3Xxs
+            | TensorType::Iq1S
+            | TensorType::Iq3S
+            | TensorType::Iq2S
+            | TensorType::Iq4Xs
+            | TensorType::Iq1M => 256,
+        }
+    }
+
+    /// Get the size in bytes of one block (one element for unquantized types)
+    pub fn type_size(&self) -> u64 {
+        match self {
+            TensorType::I8 => 1,
+            TensorType::F16 | TensorType::BF16 | TensorType::I16 => 2,
+            TensorType::F32 | TensorType::I32 => 4,
+            TensorType::F64 | TensorType::I64 => 8,
+            TensorType::Q40 | TensorType::Iq4Nl => 18,
+            TensorType::Q41 => 20,
+            TensorType::Q50 => 22,
+            TensorType::Q51 => 24,
+            TensorType::Q80 => 34,
+            TensorType::Q81 => 36,
+            TensorType::Q2K => 84,
+            TensorType::Q3K => 110,
+            TensorType::Q4K => 144,
+            TensorType::Q5K => 176,
+            TensorType::Q6K => 210,
+            TensorType::Q8K => 292,
+            TensorType::Iq2Xxs => 66,
+            TensorType::Iq2Xs => 74,
+            TensorType::Iq3Xxs => 98,
+            TensorType::Iq1S => 50,
+            TensorType::Iq3S => 110,
+            TensorType::Iq2S => 82,
+            TensorType::Iq4Xs => 136,
+            TensorType::Iq1M => 56,
+        }
+    }
+
+    /// Check if this is a block-quantized type
+    pub fn is_quantized(&self) -> bool {
+        self.block_size() > 1
+    }
+
+    /// Check if this type holds (possibly quantized) floating point values
+    pub fn is_float(&self) -> bool {
+        matches!(self, TensorType::F32 | TensorType::F16 | TensorType::BF16) || self.is_quantized()
+    }
+
+    /// Check if data of this type can be decoded by [`dequantize`](crate::quant::dequantize)
+    ///
+    /// The i-quant grid types (IQ1/IQ2/IQ3) can be loaded and copied but not decoded.
+    pub fn can_dequantize(&self) -> bool {
+        !matches!(
+            self,
+            TensorType::Iq2Xxs
+                | TensorType::Iq2Xs
+                | TensorType::Iq3Xxs
+                | TensorType::Iq1S
+                | TensorType::Iq3S
+                | TensorType::Iq2S
+                | TensorType::Iq1M
+        )
+    }
+
+    /// Check if values can be encoded as this type by [`quantize`](crate::quant::quantize)
+    pub fn can_quantize(&self) -> bool {
+        matches!(
+            self,
+            TensorType::F32
+                | TensorType::F16
+                | TensorType::BF16
+                | TensorType::Q40
+                | TensorType::Q41
+                | TensorType::Q50
+                | TensorType::Q51
+                | TensorType::Q80
+                | TensorType::Q81
+                | TensorType::Q2K
+                | TensorType::Q3K
+                | TensorType::Q4K
+                | TensorType::Q5K
+                | TensorType::Q6K
+                | TensorType::Q8K
+        )
+    }
+
+    /// Get ggml's name for this type (e.g. `"Q4_K"`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            TensorType::F32 => "F32",
+            TensorType::F16 => "F16",
+            TensorType::Q40 => "Q4_0",
+            TensorType::Q41 => "Q4_1",
+            TensorType::Q50 => "Q5_0",
+            TensorType::Q51 => "Q5_1",
+            TensorType::Q80 => "Q8_0",
+            TensorType::Q81 => "Q8_1",
+            TensorType::Q2K => "Q2_K",
+            TensorType::Q3K => "Q3_K",
+            TensorType::Q4K => "Q4_K",
+            TensorType::Q5K => "Q5_K",
+            TensorType::Q6K => "Q6_K",
+            TensorType::Q8K => "Q8_K",
+            TensorType::Iq2Xxs => "IQ2_XXS",
+            TensorType::Iq2Xs => "IQ2_XS",
+            TensorType::Iq3Xxs => "IQ3_XXS",
+            TensorType::Iq1S => "IQ1_S",
+            TensorType::Iq4Nl => "IQ4_NL",
+            TensorType::Iq3S => "IQ3_S",
+            TensorType::Iq2S => "IQ2_S",
+            TensorType::Iq4Xs => "IQ4_XS",
+            TensorType::I8 => "I8",
+            TensorType::I16 => "I16",
+            TensorType::I32 => "I32",
+            TensorType::I64 => "I64",
+            TensorType::F64 => "F64",
+            TensorType::Iq1M => "IQ1_M",
+            TensorType::BF16 => "BF16",
+        }
+    }
+
+    /// Parse a ggml type name such as `"Q4_K"` or `"bf16"` (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        (0..=30)
+            .filter_map(Self::from_u32)
+            .find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// Get the tensor data alignment from a file's metadata (`general.alignment`)
+///
+/// Falls back to [`GGUF_DEFAULT_ALIGNMENT`]. Returns `GgufError::InvalidFormat` if the
+/// alignment is not a power of two.
+pub fn tensor_data_alignment(metadata: &HashMap<String, Value>) -> Result<u64> {
+    let alignment = metadata
+        .get("general.alignment")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(GGUF_DEFAULT_ALIGNMENT);
+    if !alignment.is_power_of_two() {
+        return Err(GgufError::InvalidFormat(format!(
+            "Alignment {} is not a power of two",
+            alignment
+        )));
+    }
+    Ok(alignment)
 }
 
 /// Main interface for reading GGUF files
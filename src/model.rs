@@ -3,151 +3,500 @@
 //! This module provides higher-level abstractions for organizing GGUF tensors
 //! into structured model layers that can be easily used for inference.
 
+use crate::config::ExpertConfig;
 use crate::metadata::{GgufError, Result};
-use crate::tensors::Tensor;
+use crate::schema::{SchemaRegistry, TensorKind, TensorSchema};
+use crate::tensors::{Tensor, TensorHandle, TensorInfo, TensorLoader};
 use std::collections::HashMap;
+use std::io::{Read, Seek};
+use std::ops::Range;
 
 /// Represents the embedding layer of the model
 #[derive(Debug, Clone)]
-pub struct EmbeddingLayer {
-    /// Token embedding weights [vocab_size, embedding_dim]
-    pub token_embeddings: Tensor,
+pub struct EmbeddingLayer<T = Tensor> {
+    /// Token embedding weights [embedding_dim, vocab_size]
+    pub token_embeddings: T,
+    /// Learned position embeddings [embedding_dim, context_length] (GPT-2, BERT)
+    pub position_embeddings: Option<T>,
+    /// Token type (segment) embeddings [embedding_dim, type_count] (BERT)
+    pub token_type_embeddings: Option<T>,
+    /// Optional normalization of the summed embeddings (BERT)
+    pub embedding_norm: Option<T>,
+    pub embedding_norm_bias: Option<T>,
 }
 
-impl EmbeddingLayer {
+impl<T: TensorHandle> EmbeddingLayer<T> {
     /// Get the vocabulary size
     pub fn vocab_size(&self) -> u64 {
-        self.token_embeddings.info.dims[1]
+        self.token_embeddings.info().dims[1]
     }
 
     /// Get the embedding dimension
     pub fn embedding_dim(&self) -> u64 {
-        self.token_embeddings.info.dims[1]
+        self.token_embeddings.info().dims[0]
     }
 }
 
+/// Weights of the output projection
+#[derive(Debug, Clone)]
+pub enum OutputWeights<T = Tensor> {
+    /// A dedicated `output.weight` tensor [embedding_dim, vocab_size]
+    Separate(T),
+    /// The projection reuses the token embedding matrix (no `output.weight` in the file)
+    TiedToEmbeddings,
+}
+
 /// Represents the output/head layer of the model
 #[derive(Debug, Clone)]
-pub struct OutputLayer {
-    /// Output projection weights [embedding_dim, vocab_size]
-    pub output_weights: Tensor,
+pub struct OutputLayer<T = Tensor> {
+    /// Output projection weights
+    pub output_weights: OutputWeights<T>,
+    /// Optional output projection bias [vocab_size] (GPT-2, Phi-2)
+    pub output_bias: Option<T>,
     /// Optional output normalization
-    pub output_norm: Option<Tensor>,
+    pub output_norm: Option<T>,
+    pub output_norm_bias: Option<T>,
 }
 
-impl OutputLayer {
-    /// Get the vocabulary size
-    pub fn vocab_size(&self) -> u64 {
-        self.output_weights.info.dims[1]
+impl<T: TensorHandle> OutputLayer<T> {
+    /// Check if the output projection is tied to the token embeddings
+    pub fn is_tied(&self) -> bool {
+        matches!(self.output_weights, OutputWeights::TiedToEmbeddings)
     }
 
-    /// Get the embedding dimension
-    pub fn embedding_dim(&self) -> u64 {
-        self.output_weights.info.dims[0]
+    /// Resolve the output projection tensor, borrowing the embeddings if tied
+    pub fn weights<'a>(&'a self, embeddings: &'a EmbeddingLayer<T>) -> &'a T {
+        match &self.output_weights {
+            OutputWeights::Separate(tensor) => tensor,
+            OutputWeights::TiedToEmbeddings => &embeddings.token_embeddings,
+        }
+    }
+
+    /// Get the vocabulary size (`None` if tied, see [`Model::output_weights`])
+    pub fn vocab_size(&self) -> Option<u64> {
+        match &self.output_weights {
+            OutputWeights::Separate(tensor) => Some(tensor.info().dims[1]),
+            OutputWeights::TiedToEmbeddings => None,
+        }
+    }
+
+    /// Get the embedding dimension (`None` if tied, see [`Model::output_weights`])
+    pub fn embedding_dim(&self) -> Option<u64> {
+        match &self.output_weights {
+            OutputWeights::Separate(tensor) => Some(tensor.info().dims[0]),
+            OutputWeights::TiedToEmbeddings => None,
+        }
     }
 }
 
 /// Represents an attention layer within a transformer block
+///
+/// Q/K/V projections are either separate (`query_weights`, `key_weights`, `value_weights`)
+/// or fused into a single `qkv_weights` tensor (GPT-2, Falcon, Phi-2).
 #[derive(Debug, Clone)]
-pub struct AttentionLayer {
-    /// Query projection weights
-    pub query_weights: Tensor,
-    /// Key projection weights
-    pub key_weights: Tensor,
-    /// Value projection weights
-    pub value_weights: Tensor,
+pub struct AttentionLayer<T = Tensor> {
+    /// Query projection weights (`None` if fused)
+    pub query_weights: Option<T>,
+    /// Key projection weights (`None` if fused)
+    pub key_weights: Option<T>,
+    /// Value projection weights (`None` if fused)
+    pub value_weights: Option<T>,
+    /// Fused Q/K/V projection weights [embedding_dim, q_dim + 2 * kv_dim]
+    pub qkv_weights: Option<T>,
     /// Output projection weights
-    pub output_weights: Tensor,
+    pub output_weights: T,
+
+    /// Optional projection biases
+    pub query_bias: Option<T>,
+    pub key_bias: Option<T>,
+    pub value_bias: Option<T>,
+    pub qkv_bias: Option<T>,
+    pub output_bias: Option<T>,
 
     /// Optional normalization layers
-    pub query_norm: Option<Tensor>,
-    pub key_norm: Option<Tensor>,
-    pub attention_norm: Option<Tensor>,
+    pub query_norm: Option<T>,
+    pub key_norm: Option<T>,
+    pub attention_norm: Option<T>,
+    pub attention_norm_bias: Option<T>,
+    /// Second input normalization, for the parallel FFN (Falcon 40B's `attn_norm_2`)
+    pub attention_norm_2: Option<T>,
+    pub attention_norm_2_bias: Option<T>,
+    /// Normalization of the attention output before the residual add (Gemma 2/3)
+    pub post_attention_norm: Option<T>,
+    /// Post-attention normalization (BERT's `attn_output_norm`)
+    pub output_norm: Option<T>,
+    pub output_norm_bias: Option<T>,
 }
 
-impl AttentionLayer {
+impl<T: TensorHandle> AttentionLayer<T> {
     /// Get the model dimension (embedding size)
     pub fn model_dim(&self) -> u64 {
-        self.query_weights.info.dims[0]
+        self.output_weights.info().dims[1]
     }
 
-    /// Get the attention dimension (usually model_dim * num_heads)
+    /// Get the attention dimension (num_heads * head_dim)
     pub fn attention_dim(&self) -> u64 {
-        self.query_weights.info.dims[1]
+        self.output_weights.info().dims[0]
+    }
+
+    /// Check if the Q/K/V projections are fused into one tensor
+    pub fn is_fused(&self) -> bool {
+        self.qkv_weights.is_some()
+    }
+
+    /// Get all tensors of this layer
+    pub fn tensors(&self) -> Vec<&T> {
+        let optional = [
+            &self.query_weights,
+            &self.key_weights,
+            &self.value_weights,
+            &self.qkv_weights,
+            &self.query_bias,
+            &self.key_bias,
+            &self.value_bias,
+            &self.qkv_bias,
+            &self.output_bias,
+            &self.query_norm,
+            &self.key_norm,
+            &self.attention_norm,
+            &self.attention_norm_bias,
+            &self.attention_norm_2,
+            &self.attention_norm_2_bias,
+            &self.post_attention_norm,
+            &self.output_norm,
+            &self.output_norm_bias,
+        ];
+        let mut tensors = vec![&self.output_weights];
+        tensors.extend(optional.into_iter().flatten());
+        tensors
     }
 }
 
+impl AttentionLayer<Tensor> {
+    /// Split the fused QKV weights into `[query, key, value]`, or `None` if not fused
+    pub fn split_qkv(&self, config: &ModelConfig) -> Result<Option<[Tensor; 3]>> {
+        self.qkv_weights
+            .as_ref()
+            .map(|t| split_fused_qkv(t, config))
+            .transpose()
+    }
+
+    /// Split the fused QKV bias into `[query, key, value]`, or `None` if there is none
+    pub fn split_qkv_bias(&self, config: &ModelConfig) -> Result<Option<[Tensor; 3]>> {
+        self.qkv_bias
+            .as_ref()
+            .map(|t| split_fused_qkv(t, config))
+            .transpose()
+    }
+}
+
+/// Split a fused QKV tensor (weights or bias) into `[query, key, value]`
+///
+/// The fused tensor's outermost dimension holds the Q rows followed by the K and V rows,
+/// sized from the head counts in `config`. The returned tensors are copies.
+pub fn split_fused_qkv(fused: &Tensor, config: &ModelConfig) -> Result<[Tensor; 3]> {
+    let info = &fused.info;
+    let head_dim = config.head_dim() as u64;
+    let q_rows = config.attention_head_count as u64 * head_dim;
+    let kv_rows = config.kv_head_count() as u64 * head_dim;
+
+    let outer = info.dims.last().copied().unwrap_or(0);
+    // Head counts come from metadata: zero query rows would leave nothing to split
+    if q_rows == 0 || outer != q_rows + 2 * kv_rows {
+        return Err(GgufError::InvalidFormat(format!(
+            "Fused QKV tensor '{}' has outer dimension {}, expected {} + 2 * {}",
+            info.name, outer, q_rows, kv_rows
+        )));
+    }
+
+    let row_bytes = fused.data.len() / outer as usize;
+    let mut start = 0;
+    let mut part = |suffix: &str, rows: u64| {
+        let len = rows as usize * row_bytes;
+        let mut dims = info.dims.clone();
+        *dims.last_mut().unwrap() = rows;
+        let tensor = Tensor {
+            info: TensorInfo {
+                name: format!("{}[{}]", info.name, suffix),
+                n_dims: info.n_dims,
+                dims,
+                tensor_type: info.tensor_type,
+                offset: info.offset + start as u64,
+            },
+            data: fused.data[start..start + len].to_vec(),
+        };
+        start += len;
+        tensor
+    };
+
+    Ok([part("q", q_rows), part("k", kv_rows), part("v", kv_rows)])
+}
+
 /// Represents a feed-forward network layer within a transformer block
 #[derive(Debug, Clone)]
-pub struct FeedForwardLayer {
+pub struct FeedForwardLayer<T = Tensor> {
     /// Gate/up projection weights (for SwiGLU-style architectures)
-    pub gate_weights: Option<Tensor>,
+    pub gate_weights: Option<T>,
     /// Up projection weights
-    pub up_weights: Tensor,
+    pub up_weights: T,
     /// Down projection weights
-    pub down_weights: Tensor,
+    pub down_weights: T,
+
+    /// Optional projection biases
+    pub gate_bias: Option<T>,
+    pub up_bias: Option<T>,
+    pub down_bias: Option<T>,
+
     /// Optional normalization
-    pub ffn_norm: Option<Tensor>,
+    pub ffn_norm: Option<T>,
+    pub ffn_norm_bias: Option<T>,
+    /// Normalization of the FFN output before the residual add (Gemma 2/3)
+    pub post_ffn_norm: Option<T>,
+    /// Post-FFN normalization (BERT's `layer_output_norm`)
+    pub output_norm: Option<T>,
+    pub output_norm_bias: Option<T>,
 }
 
-impl FeedForwardLayer {
+impl<T: TensorHandle> FeedForwardLayer<T> {
+    /// Create a feed-forward layer with only projection weights
+    fn from_weights(gate_weights: Option<T>, up_weights: T, down_weights: T) -> Self {
+        Self {
+            gate_weights,
+            up_weights,
+            down_weights,
+            gate_bias: None,
+            up_bias: None,
+            down_bias: None,
+            ffn_norm: None,
+            ffn_norm_bias: None,
+            post_ffn_norm: None,
+            output_norm: None,
+            output_norm_bias: None,
+        }
+    }
+
     /// Get the model dimension (input/output size)
     pub fn model_dim(&self) -> u64 {
-        self.down_weights.info.dims[0]
+        self.down_weights.info().dims[1]
     }
 
     /// Get the intermediate dimension (hidden size)
     pub fn intermediate_dim(&self) -> u64 {
-        self.up_weights.info.dims[0]
+        self.up_weights.info().dims[1]
     }
 
     /// Check if this is a gated FFN (has gate weights)
     pub fn is_gated(&self) -> bool {
         self.gate_weights.is_some()
     }
+
+    /// Get all tensors of this layer
+    pub fn tensors(&self) -> Vec<&T> {
+        let optional = [
+            &self.gate_weights,
+            &self.gate_bias,
+            &self.up_bias,
+            &self.down_bias,
+            &self.ffn_norm,
+            &self.ffn_norm_bias,
+            &self.post_ffn_norm,
+            &self.output_norm,
+            &self.output_norm_bias,
+        ];
+        let mut tensors = vec![&self.up_weights, &self.down_weights];
+        tensors.extend(optional.into_iter().flatten());
+        tensors
+    }
+}
+
+/// Represents a mixture-of-experts feed-forward layer within a transformer block
+///
+/// Expert weights are stored stacked in 3-D tensors with the expert index as the
+/// outermost dimension, e.g. `ffn_up_exps` has dims `[embedding_dim, expert_ff_dim, expert_count]`.
+#[derive(Debug, Clone)]
+pub struct MoeFeedForwardLayer<T = Tensor> {
+    /// Router (gating network) weights [embedding_dim, expert_count]
+    pub router_weights: T,
+    /// Stacked expert gate projection weights
+    pub gate_experts: Option<T>,
+    /// Stacked expert up projection weights
+    pub up_experts: T,
+    /// Stacked expert down projection weights
+    pub down_experts: T,
+    /// Shared expert applied to every token (Qwen MoE, DeepSeek)
+    pub shared_expert: Option<FeedForwardLayer<T>>,
+    /// Sigmoid gate scaling the shared expert's output (Qwen2 MoE)
+    pub shared_expert_gate: Option<T>,
+    /// Optional normalization
+    pub ffn_norm: Option<T>,
+    /// Total number of experts
+    pub expert_count: u32,
+    /// Number of experts routed to per token
+    pub expert_used_count: u32,
+}
+
+impl<T: TensorHandle> MoeFeedForwardLayer<T> {
+    /// Get the model dimension (input/output size)
+    pub fn model_dim(&self) -> u64 {
+        self.up_experts.info().dims[0]
+    }
+
+    /// Get the hidden dimension of each expert
+    pub fn expert_dim(&self) -> u64 {
+        self.up_experts.info().dims[1]
+    }
+
+    /// Get all tensors of this layer, including the shared expert's
+    pub fn tensors(&self) -> Vec<&T> {
+        let optional = [&self.gate_experts, &self.shared_expert_gate, &self.ffn_norm];
+        let mut tensors = vec![&self.router_weights, &self.up_experts, &self.down_experts];
+        tensors.extend(optional.into_iter().flatten());
+        if let Some(shared) = &self.shared_expert {
+            tensors.extend(shared.tensors());
+        }
+        tensors
+    }
+}
+
+impl MoeFeedForwardLayer<Tensor> {
+    /// Extract the weights of a single expert as a dense feed-forward layer
+    ///
+    /// The returned tensors are 2-D copies of the expert's slice of the stacked tensors.
+    pub fn expert(&self, index: usize) -> Result<FeedForwardLayer> {
+        Ok(FeedForwardLayer::from_weights(
+            self.gate_experts
+                .as_ref()
+                .map(|t| slice_expert(t, index, self.expert_count))
+                .transpose()?,
+            slice_expert(&self.up_experts, index, self.expert_count)?,
+            slice_expert(&self.down_experts, index, self.expert_count)?,
+        ))
+    }
+}
+
+/// Slice one expert out of a stacked 3-D expert tensor
+fn slice_expert(tensor: &Tensor, index: usize, expert_count: u32) -> Result<Tensor> {
+    let info = &tensor.info;
+    if info.dims.len() != 3 || info.dims[2] != expert_count as u64 {
+        return Err(GgufError::InvalidFormat(format!(
+            "Tensor '{}' with dims {:?} is not a stack of {} experts",
+            info.name, info.dims, expert_count
+        )));
+    }
+    if index >= expert_count as usize {
+        return Err(GgufError::InvalidFormat(format!(
+            "Expert index {} out of range for {} experts",
+            index, expert_count
+        )));
+    }
+
+    // The expert index is the outermost dimension, so each expert is a contiguous chunk
+    let chunk = tensor.data.len() / expert_count as usize;
+    let start = index * chunk;
+
+    Ok(Tensor {
+        info: TensorInfo {
+            name: format!("{}[{}]", info.name, index),
+            n_dims: 2,
+            dims: info.dims[..2].to_vec(),
+            tensor_type: info.tensor_type,
+            offset: info.offset + start as u64,
+        },
+        data: tensor.data[start..start + chunk].to_vec(),
+    })
+}
+
+/// The feed-forward sublayer of a transformer block
+#[derive(Debug, Clone)]
+#[allow(clippy::large_enum_variant)]
+pub enum FeedForward<T = Tensor> {
+    /// A single dense feed-forward network
+    Dense(FeedForwardLayer<T>),
+    /// A routed mixture of experts
+    MoE(MoeFeedForwardLayer<T>),
+}
+
+impl<T: TensorHandle> FeedForward<T> {
+    /// Get the model dimension (input/output size)
+    pub fn model_dim(&self) -> u64 {
+        match self {
+            FeedForward::Dense(ffn) => ffn.model_dim(),
+            FeedForward::MoE(moe) => moe.model_dim(),
+        }
+    }
+
+    /// Check if this is a mixture-of-experts layer
+    pub fn is_moe(&self) -> bool {
+        matches!(self, FeedForward::MoE(_))
+    }
+
+    /// Get the normalization applied before the feed-forward network
+    pub fn ffn_norm(&self) -> Option<&T> {
+        match self {
+            FeedForward::Dense(ffn) => ffn.ffn_norm.as_ref(),
+            FeedForward::MoE(moe) => moe.ffn_norm.as_ref(),
+        }
+    }
+
+    /// Get all tensors of this layer
+    pub fn tensors(&self) -> Vec<&T> {
+        match self {
+            FeedForward::Dense(ffn) => ffn.tensors(),
+            FeedForward::MoE(moe) => moe.tensors(),
+        }
+    }
 }
 
 /// Represents a complete transformer block/layer
 #[derive(Debug, Clone)]
-pub struct TransformerBlock {
+pub struct TransformerBlock<T = Tensor> {
     /// Layer index (0-based)
     pub layer_index: usize,
     /// Self-attention sublayer
-    pub attention: AttentionLayer,
+    pub attention: AttentionLayer<T>,
     /// Feed-forward sublayer
-    pub feed_forward: FeedForwardLayer,
+    pub feed_forward: FeedForward<T>,
 }
 
-impl TransformerBlock {
+impl<T: TensorHandle> TransformerBlock<T> {
     /// Get the model dimension
     pub fn model_dim(&self) -> u64 {
         self.attention.model_dim()
     }
+
+    /// Get all tensors of this block
+    pub fn tensors(&self) -> Vec<&T> {
+        let mut tensors = self.attention.tensors();
+        tensors.extend(self.feed_forward.tensors());
+        tensors
+    }
 }
 
 /// Complete model structure with organized layers
 #[derive(Debug, Clone)]
-pub struct Model {
+pub struct Model<T = Tensor> {
     /// Model architecture name (e.g., "qwen3", "llama", etc.)
     pub architecture: String,
     /// Model metadata/config parameters
     pub config: ModelConfig,
     /// Token embedding layer
-    pub embeddings: EmbeddingLayer,
+    pub embeddings: EmbeddingLayer<T>,
     /// Transformer blocks/layers
-    pub transformer_blocks: Vec<TransformerBlock>,
+    pub transformer_blocks: Vec<TransformerBlock<T>>,
     /// Output/language modeling head
-    pub output_layer: OutputLayer,
+    pub output_layer: OutputLayer<T>,
+    /// Tensors the builder did not recognize (e.g. `rope_freqs.weight`, vision tower tensors)
+    pub unused_tensors: HashMap<String, T>,
 }
 
-impl Model {
+impl<T: TensorHandle> Model<T> {
     /// Get the number of transformer layers
     pub fn num_layers(&self) -> usize {
         self.transformer_blocks.len()
     }
 
     /// Get a specific transformer block by index
-    pub fn get_block(&self, index: usize) -> Option<&TransformerBlock> {
+    pub fn get_block(&self, index: usize) -> Option<&TransformerBlock<T>> {
         self.transformer_blocks.get(index)
     }
 
@@ -160,6 +509,193 @@ impl Model {
     pub fn vocab_size(&self) -> u64 {
         self.embeddings.vocab_size()
     }
+
+    /// Get the output projection tensor, which may be the token embeddings if tied
+    pub fn output_weights(&self) -> &T {
+        self.output_layer.weights(&self.embeddings)
+    }
+
+    /// Get the names of tensors left over after building, sorted
+    pub fn unused_tensor_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.unused_tensors.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        names
+    }
+}
+
+/// Selects which parts of a model to load: a range of blocks plus optionally the
+/// embeddings and output head
+///
+/// Tensor names are resolved through the model's [`TensorSchema`]. Global tensors other
+/// than the embeddings and output head (e.g. `rope_freqs.weight`) are always selected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerSelection {
+    /// Transformer blocks to load
+    pub layers: Range<usize>,
+    /// Whether to load the token embeddings
+    pub embeddings: bool,
+    /// Whether to load the output norm and projection
+    pub output: bool,
+}
+
+impl LayerSelection {
+    /// Select the whole model
+    pub fn all(block_count: u32) -> Self {
+        Self {
+            layers: 0..block_count as usize,
+            embeddings: true,
+            output: true,
+        }
+    }
+
+    /// Select only the given blocks
+    pub fn layers(layers: Range<usize>) -> Self {
+        Self {
+            layers,
+            embeddings: false,
+            output: false,
+        }
+    }
+
+    /// Also select the token embeddings
+    pub fn with_embeddings(mut self, embeddings: bool) -> Self {
+        self.embeddings = embeddings;
+        self
+    }
+
+    /// Also select the output norm and projection
+    pub fn with_output(mut self, output: bool) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Check if a tensor belongs to the selection
+    ///
+    /// This doesn't know whether the output is tied; use [`LayerSelection::filter`] when
+    /// selecting from a tensor index so that tied embeddings are included with the output.
+    pub fn selects(&self, schema: &TensorSchema, name: &str) -> bool {
+        use TensorKind::*;
+
+        if let Some(bid) = schema.block_index(name) {
+            return self.layers.contains(&bid);
+        }
+        match schema.kind_of(name) {
+            Some((TokenEmbd | TokenEmbdNorm | TokenTypes | PosEmbd, _)) => self.embeddings,
+            Some((Output | OutputNorm, _)) => self.output,
+            _ => true,
+        }
+    }
+
+    /// Select tensors from a file's tensor index
+    ///
+    /// If the output head is selected but the file has no output projection (tied
+    /// embeddings), the token embeddings are selected as well.
+    pub fn filter<'a>(
+        &self,
+        schema: &TensorSchema,
+        infos: &'a [TensorInfo],
+    ) -> Vec<&'a TensorInfo> {
+        let output = schema.tensor_name(TensorKind::Output, 0, "weight");
+        let tied = self.output && !infos.iter().any(|info| Some(&info.name) == output.as_ref());
+        infos
+            .iter()
+            .filter(|info| {
+                self.selects(schema, &info.name)
+                    || (tied
+                        && matches!(schema.kind_of(&info.name), Some((TensorKind::TokenEmbd, _))))
+            })
+            .collect()
+    }
+
+    /// Load only the selected tensors
+    ///
+    /// Tensors outside the selection are never read from the file. Unlike
+    /// [`TensorLoader::load_all_tensors`], unsupported tensors are not skipped: a fragment
+    /// missing some of its tensors fails with `GgufError::Unsupported`.
+    pub fn load_tensors<R: Read + Seek>(
+        &self,
+        schema: &TensorSchema,
+        reader: &mut R,
+        tensor_infos: &[TensorInfo],
+        tensor_data_start: u64,
+    ) -> Result<HashMap<String, Tensor>> {
+        let selected: Vec<TensorInfo> = self
+            .filter(schema, tensor_infos)
+            .into_iter()
+            .cloned()
+            .collect();
+        if let Some(info) = selected.iter().find(|info| !info.is_supported()) {
+            return Err(GgufError::Unsupported(format!(
+                "Tensor '{}' of type {:?} in the selection",
+                info.name, info.tensor_type
+            )));
+        }
+        TensorLoader::load_all_tensors(reader, &selected, tensor_data_start)
+    }
+}
+
+/// Parse the block index from a standard `blk.{N}.` tensor name
+pub(crate) fn block_index(name: &str) -> Option<usize> {
+    let rest = name.strip_prefix("blk.")?;
+    let (index, _) = rest.split_once('.')?;
+    index.parse().ok()
+}
+
+/// A part of a model holding a contiguous range of transformer blocks
+///
+/// Used for pipeline-parallel setups where each host only holds some of the layers.
+#[derive(Debug, Clone)]
+pub struct ModelFragment<T = Tensor> {
+    /// Model architecture name
+    pub architecture: String,
+    /// Configuration of the full model
+    pub config: ModelConfig,
+    /// Indices of the transformer blocks held by this fragment
+    pub layers: Range<usize>,
+    /// Token embedding layer, if selected (or needed by a tied output head)
+    pub embeddings: Option<EmbeddingLayer<T>>,
+    /// Transformer blocks, in order, for `layers`
+    pub transformer_blocks: Vec<TransformerBlock<T>>,
+    /// Output/language modeling head, if selected
+    pub output_layer: Option<OutputLayer<T>>,
+    /// Tensors the builder did not recognize
+    pub unused_tensors: HashMap<String, T>,
+}
+
+impl<T: TensorHandle> ModelFragment<T> {
+    /// Check if this fragment holds the given block
+    pub fn holds_layer(&self, index: usize) -> bool {
+        self.layers.contains(&index)
+    }
+
+    /// Get a transformer block by its index in the full model
+    pub fn get_block(&self, index: usize) -> Option<&TransformerBlock<T>> {
+        if !self.holds_layer(index) {
+            return None;
+        }
+        self.transformer_blocks.get(index - self.layers.start)
+    }
+
+    /// Check if this fragment is the first stage of a pipeline (holds the embeddings)
+    pub fn is_first(&self) -> bool {
+        self.embeddings.is_some() && self.layers.start == 0
+    }
+
+    /// Check if this fragment is the last stage of a pipeline (holds the output head)
+    pub fn is_last(&self) -> bool {
+        self.output_layer.is_some() && self.layers.end == self.config.block_count as usize
+    }
+
+    /// Get the output projection tensor, if the output head is held
+    pub fn output_weights(&self) -> Option<&T> {
+        let output_layer = self.output_layer.as_ref()?;
+        match &output_layer.output_weights {
+            OutputWeights::Separate(tensor) => Some(tensor),
+            OutputWeights::TiedToEmbeddings => {
+                self.embeddings.as_ref().map(|e| &e.token_embeddings)
+            }
+        }
+    }
 }
 
 /// Model configuration extracted from GGUF metadata
@@ -173,8 +709,8 @@ pub struct ModelConfig {
     pub context_length: u32,
     /// Embedding dimension
     pub embedding_length: u32,
-    /// Feed-forward hidden dimension
-    pub feed_forward_length: u32,
+    /// Feed-forward hidden dimension (absent for some MoE and SSM architectures)
+    pub feed_forward_length: Option<u32>,
     /// Number of attention heads
     pub attention_head_count: u32,
     /// Number of key-value heads (for GQA)
@@ -185,98 +721,314 @@ pub struct ModelConfig {
     pub layer_norm_epsilon: Option<f32>,
     /// RoPE frequency base
     pub rope_freq_base: Option<f32>,
+    /// Mixture-of-experts parameters (absent for dense models)
+    pub experts: Option<ExpertConfig>,
+}
+
+impl ModelConfig {
+    /// Get the per-head dimension (`attention.key_length`, or embedding / heads)
+    pub fn head_dim(&self) -> u32 {
+        self.attention_key_length.unwrap_or_else(|| {
+            self.embedding_length
+                .checked_div(self.attention_head_count)
+                .unwrap_or(0)
+        })
+    }
+
+    /// Get the number of key-value heads (equal to the head count without GQA)
+    pub fn kv_head_count(&self) -> u32 {
+        self.attention_head_count_kv
+            .unwrap_or(self.attention_head_count)
+    }
 }
 
 /// Builder for constructing model from flat tensor map
-pub struct ModelBuilder {
-    tensors: HashMap<String, Tensor>,
+///
+/// Tensor names are resolved through a [`TensorSchema`], by default the built-in schema
+/// for `config.architecture`.
+pub struct ModelBuilder<T = Tensor> {
+    tensors: HashMap<String, T>,
     config: ModelConfig,
+    schema: TensorSchema,
+    strict: bool,
 }
 
-impl ModelBuilder {
+impl<T: TensorHandle> ModelBuilder<T> {
     /// Create a new model builder
-    pub fn new(tensors: HashMap<String, Tensor>, config: ModelConfig) -> Self {
-        Self { tensors, config }
+    pub fn new(tensors: HashMap<String, T>, config: ModelConfig) -> Self {
+        let schema = SchemaRegistry::builtin().schema_for(&config.architecture);
+        Self {
+            tensors,
+            config,
+            schema,
+            strict: false,
+        }
+    }
+
+    /// Use the schema registered for this architecture in `registry`
+    pub fn with_registry(mut self, registry: &SchemaRegistry) -> Self {
+        self.schema = registry.schema_for(&self.config.architecture);
+        self
+    }
+
+    /// Use an explicit tensor naming schema
+    pub fn with_schema(mut self, schema: TensorSchema) -> Self {
+        self.schema = schema;
+        self
+    }
+
+    /// Fail the build if any tensors are left unused
+    ///
+    /// By default leftover tensors are kept in [`Model::unused_tensors`].
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
     }
 
     /// Build the complete model structure
-    pub fn build(mut self) -> Result<Model> {
+    pub fn build(self) -> Result<Model<T>> {
+        let selection = LayerSelection::all(self.config.block_count);
+        let fragment = self.build_fragment(&selection)?;
+
+        Ok(Model {
+            architecture: fragment.architecture,
+            config: fragment.config,
+            // Both are built when everything is selected
+            embeddings: fragment.embeddings.unwrap(),
+            transformer_blocks: fragment.transformer_blocks,
+            output_layer: fragment.output_layer.unwrap(),
+            unused_tensors: fragment.unused_tensors,
+        })
+    }
+
+    /// Build only the parts of the model chosen by `selection`
+    ///
+    /// The tensor map only needs to contain the selected tensors, e.g. those loaded with
+    /// [`LayerSelection::load_tensors`].
+    pub fn build_fragment(mut self, selection: &LayerSelection) -> Result<ModelFragment<T>> {
+        let block_count = self.config.block_count as usize;
+        if selection.layers.start > selection.layers.end || selection.layers.end > block_count {
+            return Err(GgufError::InvalidFormat(format!(
+                "Layer range {:?} is out of bounds for {} blocks",
+                selection.layers, block_count
+            )));
+        }
+
+        // Build output layer first, to know whether it needs the embeddings
+        let output_layer = if selection.output {
+            Some(self.build_output_layer()?)
+        } else {
+            None
+        };
+        let needs_embeddings = output_layer.as_ref().is_some_and(OutputLayer::is_tied);
+
         // Build embedding layer
-        let embeddings = self.build_embeddings()?;
+        let embeddings = if selection.embeddings || needs_embeddings {
+            Some(self.build_embeddings()?)
+        } else {
+            None
+        };
 
         // Build transformer blocks
-        let mut transformer_blocks = Vec::new();
-        for i in 0..self.config.block_count {
-            let block = self.build_transformer_block(i as usize)?;
+        let mut transformer_blocks = Vec::with_capacity(selection.layers.len());
+        for i in selection.layers.clone() {
+            let block = self.build_transformer_block(i)?;
             transformer_blocks.push(block);
         }
 
-        // Build output layer
-        let output_layer = self.build_output_layer()?;
+        if self.strict && !self.tensors.is_empty() {
+            let mut names: Vec<&str> = self.tensors.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            return Err(GgufError::InvalidFormat(format!(
+                "{} unrecognized tensor(s) for architecture '{}': {}",
+                names.len(),
+                self.config.architecture,
+                names.join(", ")
+            )));
+        }
 
-        Ok(Model {
+        Ok(ModelFragment {
             architecture: self.config.architecture.clone(),
             config: self.config,
+            layers: selection.layers.clone(),
             embeddings,
             transformer_blocks,
             output_layer,
+            unused_tensors: self.tensors,
         })
     }
 
-    fn build_embeddings(&mut self) -> Result<EmbeddingLayer> {
-        let token_embeddings = self.take_tensor("token_embd.weight")?;
+    fn build_embeddings(&mut self) -> Result<EmbeddingLayer<T>> {
+        let token_embeddings = self.take(TensorKind::TokenEmbd, 0)?;
+        let position_embeddings = self.try_take(TensorKind::PosEmbd, 0);
+        let token_type_embeddings = self.try_take(TensorKind::TokenTypes, 0);
+        let embedding_norm = self.try_take(TensorKind::TokenEmbdNorm, 0);
+        let embedding_norm_bias = self.try_take_bias(TensorKind::TokenEmbdNorm, 0);
 
-        Ok(EmbeddingLayer { token_embeddings })
+        Ok(EmbeddingLayer {
+            token_embeddings,
+            position_embeddings,
+            token_type_embeddings,
+            embedding_norm,
+            embedding_norm_bias,
+        })
     }
 
-    fn build_transformer_block(&mut self, layer_idx: usize) -> Result<TransformerBlock> {
-        let prefix = format!("blk.{}", layer_idx);
+    fn build_transformer_block(&mut self, bid: usize) -> Result<TransformerBlock<T>> {
+        use TensorKind::*;
 
-        // Build attention layer
+        // Build attention layer; Q/K/V are required unless the file has a fused projection
+        let qkv_weights = self.try_take(AttnQkv, bid);
+        let (query_weights, key_weights, value_weights) = if qkv_weights.is_some() {
+            (None, None, None)
+        } else {
+            (
+                Some(self.take(AttnQ, bid)?),
+                Some(self.take(AttnK, bid)?),
+                Some(self.take(AttnV, bid)?),
+            )
+        };
         let attention = AttentionLayer {
-            query_weights: self.take_tensor(&format!("{}.attn_q.weight", prefix))?,
-            key_weights: self.take_tensor(&format!("{}.attn_k.weight", prefix))?,
-            value_weights: self.take_tensor(&format!("{}.attn_v.weight", prefix))?,
-            output_weights: self.take_tensor(&format!("{}.attn_output.weight", prefix))?,
-            query_norm: self.try_take_tensor(&format!("{}.attn_q_norm.weight", prefix)),
-            key_norm: self.try_take_tensor(&format!("{}.attn_k_norm.weight", prefix)),
-            attention_norm: self.try_take_tensor(&format!("{}.attn_norm.weight", prefix)),
+            query_weights,
+            key_weights,
+            value_weights,
+            qkv_weights,
+            output_weights: self.take(AttnOut, bid)?,
+            query_bias: self.try_take_bias(AttnQ, bid),
+            key_bias: self.try_take_bias(AttnK, bid),
+            value_bias: self.try_take_bias(AttnV, bid),
+            qkv_bias: self.try_take_bias(AttnQkv, bid),
+            output_bias: self.try_take_bias(AttnOut, bid),
+            query_norm: self.try_take(AttnQNorm, bid),
+            key_norm: self.try_take(AttnKNorm, bid),
+            attention_norm: self.try_take(AttnNorm, bid),
+            attention_norm_bias: self.try_take_bias(AttnNorm, bid),
+            attention_norm_2: self.try_take(AttnNorm2, bid),
+            attention_norm_2_bias: self.try_take_bias(AttnNorm2, bid),
+            post_attention_norm: self.try_take(AttnPostNorm, bid),
+            output_norm: self.try_take(AttnOutNorm, bid),
+            output_norm_bias: self.try_take_bias(AttnOutNorm, bid),
         };
 
-        // Build feed-forward layer
-        let feed_forward = FeedForwardLayer {
-            gate_weights: self.try_take_tensor(&format!("{}.ffn_gate.weight", prefix)),
-            up_weights: self.take_tensor(&format!("{}.ffn_up.weight", prefix))?,
-            down_weights: self.take_tensor(&format!("{}.ffn_down.weight", prefix))?,
-            ffn_norm: self.try_take_tensor(&format!("{}.ffn_norm.weight", prefix)),
+        // Build feed-forward layer; MoE layers are recognized by their router weights
+        // (DeepSeek keeps its leading blocks dense, so this is decided per layer)
+        let feed_forward = if self.has(FfnGateInp, bid) {
+            FeedForward::MoE(self.build_moe_feed_forward(bid)?)
+        } else {
+            FeedForward::Dense(FeedForwardLayer {
+                gate_weights: self.try_take(FfnGate, bid),
+                up_weights: self.take(FfnUp, bid)?,
+                down_weights: self.take(FfnDown, bid)?,
+                gate_bias: self.try_take_bias(FfnGate, bid),
+                up_bias: self.try_take_bias(FfnUp, bid),
+                down_bias: self.try_take_bias(FfnDown, bid),
+                ffn_norm: self.try_take(FfnNorm, bid),
+                ffn_norm_bias: self.try_take_bias(FfnNorm, bid),
+                post_ffn_norm: self.try_take(FfnPostNorm, bid),
+                output_norm: self.try_take(LayerOutNorm, bid),
+                output_norm_bias: self.try_take_bias(LayerOutNorm, bid),
+            })
         };
 
         Ok(TransformerBlock {
-            layer_index: layer_idx,
+            layer_index: bid,
             attention,
             feed_forward,
         })
     }
 
-    fn build_output_layer(&mut self) -> Result<OutputLayer> {
-        let output_weights = self.take_tensor("output.weight")?;
-        let output_norm = self.try_take_tensor("output_norm.weight");
+    fn build_moe_feed_forward(&mut self, bid: usize) -> Result<MoeFeedForwardLayer<T>> {
+        use TensorKind::*;
+
+        let experts = self.config.experts.clone().ok_or_else(|| {
+            GgufError::InvalidFormat(format!(
+                "Block {} has expert tensors but {}.expert_count is missing",
+                bid, self.config.architecture
+            ))
+        })?;
+
+        let shared_expert = match self.try_take(FfnUpShexp, bid) {
+            Some(up_weights) => Some(FeedForwardLayer::from_weights(
+                self.try_take(FfnGateShexp, bid),
+                up_weights,
+                self.take(FfnDownShexp, bid)?,
+            )),
+            None => None,
+        };
+
+        Ok(MoeFeedForwardLayer {
+            router_weights: self.take(FfnGateInp, bid)?,
+            gate_experts: self.try_take(FfnGateExps, bid),
+            up_experts: self.take(FfnUpExps, bid)?,
+            down_experts: self.take(FfnDownExps, bid)?,
+            shared_expert,
+            shared_expert_gate: self.try_take(FfnGateInpShexp, bid),
+            ffn_norm: self.try_take(FfnNorm, bid),
+            expert_count: experts.expert_count,
+            expert_used_count: experts.expert_used_count,
+        })
+    }
+
+    fn build_output_layer(&mut self) -> Result<OutputLayer<T>> {
+        // Models with tied embeddings omit `output.weight` and reuse `token_embd.weight`
+        let output_weights = match self.try_take(TensorKind::Output, 0) {
+            Some(tensor) => OutputWeights::Separate(tensor),
+            None => OutputWeights::TiedToEmbeddings,
+        };
+        let output_bias = self.try_take_bias(TensorKind::Output, 0);
+        let output_norm = self.try_take(TensorKind::OutputNorm, 0);
+        let output_norm_bias = self.try_take_bias(TensorKind::OutputNorm, 0);
 
         Ok(OutputLayer {
             output_weights,
+            output_bias,
             output_norm,
+            output_norm_bias,
         })
     }
 
+    /// Check if the weight tensor of a kind is present
+    fn has(&self, kind: TensorKind, bid: usize) -> bool {
+        self.schema
+            .tensor_name(kind, bid, "weight")
+            .is_some_and(|name| self.tensors.contains_key(&name))
+    }
+
+    /// Take a required weight tensor from the map
+    fn take(&mut self, kind: TensorKind, bid: usize) -> Result<T> {
+        let name = self
+            .schema
+            .tensor_name(kind, bid, "weight")
+            .ok_or_else(|| {
+                GgufError::InvalidFormat(format!(
+                    "Architecture '{}' has no tensor name for {:?}",
+                    self.config.architecture, kind
+                ))
+            })?;
+        self.take_tensor(&name)
+    }
+
+    /// Try to take an optional weight tensor from the map
+    fn try_take(&mut self, kind: TensorKind, bid: usize) -> Option<T> {
+        let name = self.schema.tensor_name(kind, bid, "weight")?;
+        self.try_take_tensor(&name)
+    }
+
+    /// Try to take an optional bias tensor from the map
+    fn try_take_bias(&mut self, kind: TensorKind, bid: usize) -> Option<T> {
+        let name = self.schema.tensor_name(kind, bid, "bias")?;
+        self.try_take_tensor(&name)
+    }
+
     /// Take a required tensor from the map
-    fn take_tensor(&mut self, name: &str) -> Result<Tensor> {
+    fn take_tensor(&mut self, name: &str) -> Result<T> {
         self.tensors.remove(name).ok_or_else(|| {
             GgufError::InvalidFormat(format!("Required tensor '{}' not found", name))
         })
     }
 
     /// Try to take an optional tensor from the map
-    fn try_take_tensor(&mut self, name: &str) -> Option<Tensor> {
+    fn try_take_tensor(&mut self, name: &str) -> Option<T> {
         self.tensors.remove(name)
     }
 }
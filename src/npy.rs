@@ -0,0 +1,250 @@
+//! NumPy `.npy` and `.npz` export
+//!
+//! Writes tensors in NumPy's array format for inspection in notebooks. Shapes are
+//! row-major, so ggml dims are reversed: a `[4096, 11008]` tensor becomes a
+//! `(11008, 4096)` array. `.npz` files are zip archives of `.npy` entries, written
+//! uncompressed like `numpy.savez`.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::metadata::{GgufError, Result, TensorType};
+use crate::tensors::Tensor;
+
+/// How tensor data is represented in the exported array
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NpyDtype {
+    /// Keep the tensor's type if NumPy has it (F32, F16, F64 and integers), otherwise
+    /// (BF16 and quantized types) convert to `float32`
+    #[default]
+    Native,
+    /// Convert to `float32`, dequantizing if needed
+    Float32,
+    /// The raw bytes as `uint8`, one array row per tensor row
+    ///
+    /// Exposes quantized blocks as stored; the last axis is the row size in bytes.
+    Raw,
+}
+
+/// Get the NumPy dtype descriptor of a tensor type, if NumPy has it
+pub fn npy_descr(tensor_type: TensorType) -> Option<&'static str> {
+    match tensor_type {
+        TensorType::F32 => Some("<f4"),
+        TensorType::F16 => Some("<f2"),
+        TensorType::F64 => Some("<f8"),
+        TensorType::I8 => Some("|i1"),
+        TensorType::I16 => Some("<i2"),
+        TensorType::I32 => Some("<i4"),
+        TensorType::I64 => Some("<i8"),
+        _ => None,
+    }
+}
+
+/// Encode a tensor as `.npy` file contents
+pub fn to_npy(tensor: &Tensor, dtype: NpyDtype) -> Result<Vec<u8>> {
+    let info = &tensor.info;
+    let mut shape: Vec<u64> = info.dims.iter().rev().copied().collect();
+    let native = npy_descr(info.tensor_type).filter(|_| dtype == NpyDtype::Native);
+    let (descr, data) = match (dtype, native) {
+        (NpyDtype::Raw, _) => {
+            if let Some(row_len) = shape.last_mut() {
+                *row_len = info.row_size();
+            }
+            ("|u1", tensor.data.clone())
+        }
+        (_, Some(descr)) => (descr, tensor.data.clone()),
+        _ => {
+            let values = tensor.as_f32_vec()?;
+            ("<f4", values.iter().flat_map(|v| v.to_le_bytes()).collect())
+        }
+    };
+    if data.len() as u64 != shape.iter().product::<u64>() * descr_size(descr) {
+        return Err(GgufError::InvalidFormat(format!(
+            "Tensor '{}' has {} bytes of data for shape {:?}",
+            info.name,
+            data.len(),
+            info.dims
+        )));
+    }
+
+    let mut npy = npy_header(descr, &shape);
+    npy.extend_from_slice(&data);
+    Ok(npy)
+}
+
+fn descr_size(descr: &str) -> u64 {
+    descr[2..].parse().unwrap_or(1)
+}
+
+/// Build a version 1.0 header, padded so the data starts at a multiple of 64 bytes
+fn npy_header(descr: &str, shape: &[u64]) -> Vec<u8> {
+    let shape = match shape {
+        [len] => format!("({},)", len),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut dict = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+    // Magic (6) + version (2) + header length (2) + dict + '\n'
+    let padding = (10 + dict.len() + 1).next_multiple_of(64) - (10 + dict.len() + 1);
+    dict.extend(std::iter::repeat_n(' ', padding));
+    dict.push('\n');
+
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(dict.len() as u16).to_le_bytes());
+    header.extend_from_slice(dict.as_bytes());
+    header
+}
+
+/// Write a tensor to a `.npy` file
+pub fn export_npy<P: AsRef<Path>>(path: P, tensor: &Tensor, dtype: NpyDtype) -> Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&to_npy(tensor, dtype)?)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Write tensors to a `.npz` file, as arrays named after the tensors
+pub fn export_npz<'a, P, I>(path: P, tensors: I, dtype: NpyDtype) -> Result<()>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = &'a Tensor>,
+{
+    let mut writer = NpzWriter::new(BufWriter::new(File::create(path)?));
+    for tensor in tensors {
+        writer.write_tensor(&tensor.info.name, tensor, dtype)?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+/// Streams `.npy` entries into an uncompressed zip archive
+///
+/// Only one tensor's encoded data is held in memory at a time. ZIP64 is not supported,
+/// so entries and the archive must stay under 4 GiB.
+#[derive(Debug)]
+pub struct NpzWriter<W: Write> {
+    inner: W,
+    /// Bytes written so far
+    offset: u64,
+    /// Central directory records of the entries written
+    central_directory: Vec<u8>,
+    entry_count: u16,
+}
+
+/// DOS date of 1980-01-01, the earliest a zip entry can have
+const ZIP_DATE: u16 = (1 << 5) | 1;
+
+impl<W: Write> NpzWriter<W> {
+    /// Start an archive
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            central_directory: Vec::new(),
+            entry_count: 0,
+        }
+    }
+
+    /// Add a tensor as the array `name` (stored as `{name}.npy`)
+    pub fn write_tensor(&mut self, name: &str, tensor: &Tensor, dtype: NpyDtype) -> Result<()> {
+        let npy = to_npy(tensor, dtype)?;
+        let file_name = format!("{}.npy", name);
+        let too_large = |what: &str| GgufError::Unsupported(format!("{} in .npz (no ZIP64)", what));
+        let size = u32::try_from(npy.len()).map_err(|_| too_large("Arrays over 4 GiB"))?;
+        let offset = u32::try_from(self.offset).map_err(|_| too_large("Archives over 4 GiB"))?;
+        self.entry_count = self
+            .entry_count
+            .checked_add(1)
+            .ok_or_else(|| too_large("More than 65535 arrays"))?;
+        let crc = crc32(&npy);
+
+        // Fields shared by the local and central headers, from "version needed" on
+        let mut fields = Vec::with_capacity(26);
+        fields.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        fields.extend_from_slice(&0u16.to_le_bytes()); // flags
+        fields.extend_from_slice(&0u16.to_le_bytes()); // stored
+        fields.extend_from_slice(&0u16.to_le_bytes()); // time
+        fields.extend_from_slice(&ZIP_DATE.to_le_bytes());
+        fields.extend_from_slice(&crc.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes()); // compressed
+        fields.extend_from_slice(&size.to_le_bytes()); // uncompressed
+        fields.extend_from_slice(&(file_name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes()); // extra field length
+
+        let mut local = Vec::with_capacity(30 + file_name.len());
+        local.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        local.extend_from_slice(&fields);
+        local.extend_from_slice(file_name.as_bytes());
+        self.inner.write_all(&local)?;
+        self.inner.write_all(&npy)?;
+        self.offset += (local.len() + npy.len()) as u64;
+
+        let central = &mut self.central_directory;
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&fields);
+        central.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        central.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        central.extend_from_slice(&0u16.to_le_bytes()); // internal attributes
+        central.extend_from_slice(&0u32.to_le_bytes()); // external attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(file_name.as_bytes());
+        Ok(())
+    }
+
+    /// Write the central directory, returning the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        let offset = u32::try_from(self.offset)
+            .map_err(|_| GgufError::Unsupported("Archives over 4 GiB in .npz".to_string()))?;
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // disk number
+        end.extend_from_slice(&0u16.to_le_bytes()); // disk with the directory
+        end.extend_from_slice(&self.entry_count.to_le_bytes());
+        end.extend_from_slice(&self.entry_count.to_le_bytes());
+        end.extend_from_slice(&(self.central_directory.len() as u32).to_le_bytes());
+        end.extend_from_slice(&offset.to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // comment length
+
+        self.inner.write_all(&self.central_directory)?;
+        self.inner.write_all(&end)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// CRC-32 (IEEE) as used by zip
+fn crc32(data: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 != 0 {
+                    0xedb88320 ^ (crc >> 1)
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    !data.iter().fold(!0u32, |crc, &byte| {
+        TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,236 @@
+//! Parallel tensor loading
+//!
+//! [`ParallelLoader`] reads tensors from multiple threads using positioned reads
+//! (`pread` on Unix, `ReadFile` with an offset on Windows), so threads never contend for a
+//! shared file cursor. This helps on fast storage such as NVMe, where a single sequential
+//! reader can't saturate the device. Other targets fall back to seeking under a lock.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+
+use crate::metadata::{Result, TensorType};
+use crate::tensors::{CancellationToken, LoadProgress, READ_CHUNK_SIZE, Tensor, TensorInfo};
+
+/// Loads tensors from a GGUF file using several threads
+#[derive(Debug, Clone)]
+pub struct ParallelLoader {
+    threads: usize,
+    convert_to_f32: bool,
+    cancel: CancellationToken,
+}
+
+impl ParallelLoader {
+    /// Create a loader using one thread per available CPU
+    pub fn new() -> Self {
+        Self {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            convert_to_f32: false,
+            cancel: CancellationToken::new(),
+        }
+    }
+
+    /// Set the number of reader threads
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Convert F16 and BF16 tensors to F32 on the reader threads, right after reading them
+    pub fn convert_to_f32(mut self, convert: bool) -> Self {
+        self.convert_to_f32 = convert;
+        self
+    }
+
+    /// Stop loading with `GgufError::Cancelled` once `token` is cancelled
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    /// Load all supported tensors from `file`
+    ///
+    /// Unsupported tensor types are skipped, as in
+    /// [`TensorLoader::load_all_tensors`](crate::tensors::TensorLoader::load_all_tensors),
+    /// but read errors fail the whole load. `progress` is called from the reader threads
+    /// as data is read, in chunks for large tensors; the calls are serialized, so the
+    /// reported counts never decrease.
+    pub fn load<F>(
+        &self,
+        file: &File,
+        tensor_infos: &[TensorInfo],
+        tensor_data_start: u64,
+        progress: F,
+    ) -> Result<HashMap<String, Tensor>>
+    where
+        F: Fn(LoadProgress) + Sync,
+    {
+        // Read in file order, which keeps each thread's reads mostly sequential
+        let mut infos: Vec<&TensorInfo> = tensor_infos
+            .iter()
+            .filter(|info| {
+                if !info.is_supported() {
+                    eprintln!(
+                        "⚠️  Skipping unsupported tensor '{}' of type {:?}",
+                        info.name, info.tensor_type
+                    );
+                }
+                info.is_supported()
+            })
+            .collect();
+        infos.sort_by_key(|info| info.offset);
+
+        let tensors_total = infos.len();
+        let state = Mutex::new(LoadProgress {
+            tensors_total,
+            bytes_total: infos.iter().map(|info| info.byte_size()).sum(),
+            ..LoadProgress::default()
+        });
+
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let first_error = Mutex::new(None);
+        let loaded = Mutex::new(HashMap::with_capacity(tensors_total));
+
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(tensors_total.max(1)) {
+                scope.spawn(|| {
+                    while !failed.load(Ordering::Relaxed) {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(info) = infos.get(index) else {
+                            break;
+                        };
+
+                        // Report under the lock so that updates can't arrive out of order
+                        let on_chunk = |len: u64, tensor_finished: bool| {
+                            let mut state = state.lock().unwrap();
+                            state.bytes_done += len;
+                            state.tensors_done += tensor_finished as usize;
+                            progress(*state);
+                        };
+
+                        match self.load_one(file, info, tensor_data_start, on_chunk) {
+                            Ok(tensor) => {
+                                loaded.lock().unwrap().insert(info.name.clone(), tensor);
+                            }
+                            Err(e) => {
+                                failed.store(true, Ordering::Relaxed);
+                                first_error.lock().unwrap().get_or_insert(e);
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        match first_error.into_inner().unwrap() {
+            Some(e) => Err(e),
+            None => Ok(loaded.into_inner().unwrap()),
+        }
+    }
+
+    /// Read one tensor, calling `on_chunk(bytes, tensor_finished)` after each chunk
+    fn load_one<F>(
+        &self,
+        file: &File,
+        info: &TensorInfo,
+        tensor_data_start: u64,
+        on_chunk: F,
+    ) -> Result<Tensor>
+    where
+        F: Fn(u64, bool),
+    {
+        let mut data = vec![0u8; info.byte_size() as usize];
+        let chunk_count = data.len().div_ceil(READ_CHUNK_SIZE);
+        let mut offset = tensor_data_start + info.offset;
+        for (i, chunk) in data.chunks_mut(READ_CHUNK_SIZE).enumerate() {
+            self.cancel.check()?;
+            read_exact_at(file, chunk, offset)?;
+            offset += chunk.len() as u64;
+            on_chunk(chunk.len() as u64, i + 1 == chunk_count);
+        }
+        if chunk_count == 0 {
+            on_chunk(0, true);
+        }
+
+        let tensor = Tensor {
+            info: info.clone(),
+            data,
+        };
+        if self.convert_to_f32 && matches!(info.tensor_type, TensorType::F16 | TensorType::BF16) {
+            to_f32_tensor(&tensor)
+        } else {
+            Ok(tensor)
+        }
+    }
+}
+
+impl Default for ParallelLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Convert a tensor to an F32 tensor with the same name and shape
+fn to_f32_tensor(tensor: &Tensor) -> Result<Tensor> {
+    let data = tensor
+        .as_f32_vec()?
+        .into_iter()
+        .flat_map(f32::to_le_bytes)
+        .collect();
+
+    Ok(Tensor {
+        info: TensorInfo {
+            tensor_type: TensorType::F32,
+            ..tensor.info.clone()
+        },
+        data,
+    })
+}
+
+/// Read exactly `buf.len()` bytes at `offset` without moving the file cursor
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.read_exact_at(buf, offset)
+}
+
+/// Read exactly `buf.len()` bytes at `offset`
+#[cfg(windows)]
+pub(crate) fn read_exact_at(
+    file: &File,
+    mut buf: &mut [u8],
+    mut offset: u64,
+) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(Error::from(ErrorKind::UnexpectedEof)),
+            Ok(n) => {
+                buf = &mut buf[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Read exactly `buf.len()` bytes at `offset`
+///
+/// Without positioned reads the file cursor is shared, so reads are serialized.
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    static CURSOR: Mutex<()> = Mutex::new(());
+    let _guard = CURSOR.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
@@ -0,0 +1,1368 @@
+//! Block quantization formats
+//!
+//! Quantized GGUF tensors store each row as a sequence of fixed-size blocks (see
+//! [`TensorType::block_size`] and [`TensorType::type_size`]). This module decodes those
+//! blocks to f32 and encodes f32 values into them, following the reference
+//! implementations in ggml's `ggml-quants.c`.
+
+use crate::convert::{bf16_to_f32, f16_to_f32, f32_to_bf16, f32_to_f16};
+use crate::metadata::{GgufError, Result, TensorType};
+
+/// 4-bit non-linear codebook shared by IQ4_NL and IQ4_XS
+const IQ4NL_VALUES: [i8; 16] = [
+    -127, -104, -83, -65, -49, -35, -22, -10, 1, 13, 25, 38, 53, 69, 89, 113,
+];
+
+/// Decode whole blocks of `tensor_type` data into f32 values
+///
+/// `data` must hold a whole number of blocks, and `out` exactly the elements they encode.
+/// Integer types are converted by value.
+pub fn dequantize(tensor_type: TensorType, data: &[u8], out: &mut [f32]) -> Result<()> {
+    let type_size = tensor_type.type_size() as usize;
+    let block_size = tensor_type.block_size() as usize;
+    if !data.len().is_multiple_of(type_size) || data.len() / type_size * block_size != out.len() {
+        return Err(GgufError::InvalidFormat(format!(
+            "{} bytes of {:?} data don't decode to {} values",
+            data.len(),
+            tensor_type,
+            out.len()
+        )));
+    }
+
+    let elements = data.chunks_exact(type_size).zip(out.iter_mut());
+    match tensor_type {
+        TensorType::F32 => elements.for_each(|(b, y)| *y = f32::from_le_bytes(le(b))),
+        TensorType::F16 => elements.for_each(|(b, y)| *y = f16_to_f32(u16::from_le_bytes(le(b)))),
+        TensorType::BF16 => elements.for_each(|(b, y)| *y = bf16_to_f32(u16::from_le_bytes(le(b)))),
+        TensorType::F64 => elements.for_each(|(b, y)| *y = f64::from_le_bytes(le(b)) as f32),
+        TensorType::I8 => elements.for_each(|(b, y)| *y = b[0] as i8 as f32),
+        TensorType::I16 => elements.for_each(|(b, y)| *y = i16::from_le_bytes(le(b)) as f32),
+        TensorType::I32 => elements.for_each(|(b, y)| *y = i32::from_le_bytes(le(b)) as f32),
+        TensorType::I64 => elements.for_each(|(b, y)| *y = i64::from_le_bytes(le(b)) as f32),
+        _ => {
+            let decode = block_decoder(tensor_type).ok_or_else(|| {
+                GgufError::Unsupported(format!(
+                    "Dequantization of {:?} is not supported",
+                    tensor_type
+                ))
+            })?;
+            for (block, y) in data
+                .chunks_exact(type_size)
+                .zip(out.chunks_exact_mut(block_size))
+            {
+                decode(block, y);
+            }
+        }
+    }
+    Ok(())
+}
+
+type BlockDecoder = fn(&[u8], &mut [f32]);
+
+fn block_decoder(tensor_type: TensorType) -> Option<BlockDecoder> {
+    Some(match tensor_type {
+        TensorType::Q40 => dequantize_q4_0,
+        TensorType::Q41 => dequantize_q4_1,
+        TensorType::Q50 => dequantize_q5_0,
+        TensorType::Q51 => dequantize_q5_1,
+        TensorType::Q80 => dequantize_q8_0,
+        TensorType::Q81 => dequantize_q8_1,
+        TensorType::Q2K => dequantize_q2_k,
+        TensorType::Q3K => dequantize_q3_k,
+        TensorType::Q4K => dequantize_q4_k,
+        TensorType::Q5K => dequantize_q5_k,
+        TensorType::Q6K => dequantize_q6_k,
+        TensorType::Q8K => dequantize_q8_k,
+        TensorType::Iq4Nl => dequantize_iq4_nl,
+        TensorType::Iq4Xs => dequantize_iq4_xs,
+        _ => return None,
+    })
+}
+
+fn le<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    bytes[..N].try_into().unwrap()
+}
+
+/// Read the little-endian f16 at byte offset `i`
+fn f16_at(block: &[u8], i: usize) -> f32 {
+    f16_to_f32(u16::from_le_bytes(le(&block[i..])))
+}
+
+/// `{ d: f16, qs: [u8; 16] }`
+fn dequantize_q4_0(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let qs = &block[2..18];
+    for j in 0..16 {
+        y[j] = ((qs[j] & 0x0f) as i32 - 8) as f32 * d;
+        y[j + 16] = ((qs[j] >> 4) as i32 - 8) as f32 * d;
+    }
+}
+
+/// `{ d: f16, m: f16, qs: [u8; 16] }`
+fn dequantize_q4_1(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let m = f16_at(block, 2);
+    let qs = &block[4..20];
+    for j in 0..16 {
+        y[j] = (qs[j] & 0x0f) as f32 * d + m;
+        y[j + 16] = (qs[j] >> 4) as f32 * d + m;
+    }
+}
+
+/// `{ d: f16, qh: u32, qs: [u8; 16] }`
+fn dequantize_q5_0(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let qh = u32::from_le_bytes(le(&block[2..]));
+    let qs = &block[6..22];
+    for j in 0..16 {
+        let xh_0 = ((qh >> j) << 4) & 0x10;
+        let xh_1 = (qh >> (j + 12)) & 0x10;
+        y[j] = (((qs[j] & 0x0f) as u32 | xh_0) as i32 - 16) as f32 * d;
+        y[j + 16] = (((qs[j] >> 4) as u32 | xh_1) as i32 - 16) as f32 * d;
+    }
+}
+
+/// `{ d: f16, m: f16, qh: u32, qs: [u8; 16] }`
+fn dequantize_q5_1(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let m = f16_at(block, 2);
+    let qh = u32::from_le_bytes(le(&block[4..]));
+    let qs = &block[8..24];
+    for j in 0..16 {
+        let xh_0 = ((qh >> j) << 4) & 0x10;
+        let xh_1 = (qh >> (j + 12)) & 0x10;
+        y[j] = ((qs[j] & 0x0f) as u32 | xh_0) as f32 * d + m;
+        y[j + 16] = ((qs[j] >> 4) as u32 | xh_1) as f32 * d + m;
+    }
+}
+
+/// `{ d: f16, qs: [i8; 32] }`
+fn dequantize_q8_0(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    for (y, q) in y.iter_mut().zip(&block[2..34]) {
+        *y = *q as i8 as f32 * d;
+    }
+}
+
+/// `{ d: f16, s: f16, qs: [i8; 32] }`
+fn dequantize_q8_1(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    for (y, q) in y.iter_mut().zip(&block[4..36]) {
+        *y = *q as i8 as f32 * d;
+    }
+}
+
+/// `{ scales: [u8; 16], qs: [u8; 64], d: f16, dmin: f16 }`
+fn dequantize_q2_k(block: &[u8], y: &mut [f32]) {
+    let scales = &block[0..16];
+    let d = f16_at(block, 80);
+    let min = f16_at(block, 82);
+
+    let mut is = 0;
+    let mut out = 0;
+    for q in block[16..80].chunks_exact(32) {
+        for shift in [0, 2, 4, 6] {
+            for half in q.chunks_exact(16) {
+                let sc = scales[is];
+                is += 1;
+                let dl = d * (sc & 0x0f) as f32;
+                let ml = min * (sc >> 4) as f32;
+                for &q in half {
+                    y[out] = dl * ((q >> shift) & 3) as f32 - ml;
+                    out += 1;
+                }
+            }
+        }
+    }
+}
+
+/// `{ hmask: [u8; 32], qs: [u8; 64], scales: [u8; 12], d: f16 }`
+fn dequantize_q3_k(block: &[u8], y: &mut [f32]) {
+    const KMASK1: u32 = 0x0303_0303;
+    const KMASK2: u32 = 0x0f0f_0f0f;
+
+    let hmask = &block[0..32];
+    let d_all = f16_at(block, 108);
+
+    // Unpack sixteen 6-bit scales from 12 bytes
+    let raw = &block[96..108];
+    let mut aux = [0u32; 4];
+    for (i, a) in aux.iter_mut().take(3).enumerate() {
+        *a = u32::from_le_bytes(le(&raw[i * 4..]));
+    }
+    let tmp = aux[2];
+    aux[2] = ((aux[0] >> 4) & KMASK2) | (((tmp >> 4) & KMASK1) << 4);
+    aux[3] = ((aux[1] >> 4) & KMASK2) | (((tmp >> 6) & KMASK1) << 4);
+    aux[0] = (aux[0] & KMASK2) | ((tmp & KMASK1) << 4);
+    aux[1] = (aux[1] & KMASK2) | (((tmp >> 2) & KMASK1) << 4);
+    let scales: Vec<i8> = aux
+        .iter()
+        .flat_map(|a| a.to_le_bytes())
+        .map(|b| b as i8)
+        .collect();
+
+    let mut is = 0;
+    let mut out = 0;
+    let mut m = 1u8;
+    for q in block[32..96].chunks_exact(32) {
+        for shift in [0, 2, 4, 6] {
+            for (h, half) in [0, 16].into_iter().zip(q.chunks_exact(16)) {
+                let dl = d_all * (scales[is] as i32 - 32) as f32;
+                is += 1;
+                for (l, &q) in half.iter().enumerate() {
+                    let high = if hmask[h + l] & m != 0 { 0 } else { 4 };
+                    y[out] = dl * (((q >> shift) & 3) as i32 - high) as f32;
+                    out += 1;
+                }
+            }
+            m <<= 1;
+        }
+    }
+}
+
+/// Get the 6-bit scale and min `j` packed in the 12 bytes `q` of a Q4_K/Q5_K block
+fn scale_min_k4(j: usize, q: &[u8]) -> (f32, f32) {
+    if j < 4 {
+        ((q[j] & 63) as f32, (q[j + 4] & 63) as f32)
+    } else {
+        (
+            ((q[j + 4] & 0x0f) | ((q[j - 4] >> 6) << 4)) as f32,
+            ((q[j + 4] >> 4) | ((q[j] >> 6) << 4)) as f32,
+        )
+    }
+}
+
+/// `{ d: f16, dmin: f16, scales: [u8; 12], qs: [u8; 128] }`
+fn dequantize_q4_k(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let min = f16_at(block, 2);
+    let scales = &block[4..16];
+
+    for (j, (q, y)) in block[16..144]
+        .chunks_exact(32)
+        .zip(y.chunks_exact_mut(64))
+        .enumerate()
+    {
+        let (sc1, m1) = scale_min_k4(2 * j, scales);
+        let (sc2, m2) = scale_min_k4(2 * j + 1, scales);
+        let (d1, m1, d2, m2) = (d * sc1, min * m1, d * sc2, min * m2);
+        for l in 0..32 {
+            y[l] = d1 * (q[l] & 0x0f) as f32 - m1;
+            y[l + 32] = d2 * (q[l] >> 4) as f32 - m2;
+        }
+    }
+}
+
+/// `{ d: f16, dmin: f16, scales: [u8; 12], qh: [u8; 32], qs: [u8; 128] }`
+fn dequantize_q5_k(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let min = f16_at(block, 2);
+    let scales = &block[4..16];
+    let qh = &block[16..48];
+
+    for (j, (ql, y)) in block[48..176]
+        .chunks_exact(32)
+        .zip(y.chunks_exact_mut(64))
+        .enumerate()
+    {
+        let (sc1, m1) = scale_min_k4(2 * j, scales);
+        let (sc2, m2) = scale_min_k4(2 * j + 1, scales);
+        let (d1, m1, d2, m2) = (d * sc1, min * m1, d * sc2, min * m2);
+        let (u1, u2) = (1u8 << (2 * j), 2u8 << (2 * j));
+        for l in 0..32 {
+            let h1 = if qh[l] & u1 != 0 { 16 } else { 0 };
+            let h2 = if qh[l] & u2 != 0 { 16 } else { 0 };
+            y[l] = d1 * ((ql[l] & 0x0f) + h1) as f32 - m1;
+            y[l + 32] = d2 * ((ql[l] >> 4) + h2) as f32 - m2;
+        }
+    }
+}
+
+/// `{ ql: [u8; 128], qh: [u8; 64], scales: [i8; 16], d: f16 }`
+fn dequantize_q6_k(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 208);
+
+    for n in 0..2 {
+        let ql = &block[n * 64..n * 64 + 64];
+        let qh = &block[128 + n * 32..128 + n * 32 + 32];
+        let sc = &block[192 + n * 8..192 + n * 8 + 8];
+        let y = &mut y[n * 128..n * 128 + 128];
+        for l in 0..32 {
+            let is = l / 16;
+            let q1 = ((ql[l] & 0x0f) | ((qh[l] & 3) << 4)) as i32 - 32;
+            let q2 = ((ql[l + 32] & 0x0f) | (((qh[l] >> 2) & 3) << 4)) as i32 - 32;
+            let q3 = ((ql[l] >> 4) | (((qh[l] >> 4) & 3) << 4)) as i32 - 32;
+            let q4 = ((ql[l + 32] >> 4) | (((qh[l] >> 6) & 3) << 4)) as i32 - 32;
+            y[l] = d * (sc[is] as i8 as i32 * q1) as f32;
+            y[l + 32] = d * (sc[is + 2] as i8 as i32 * q2) as f32;
+            y[l + 64] = d * (sc[is + 4] as i8 as i32 * q3) as f32;
+            y[l + 96] = d * (sc[is + 6] as i8 as i32 * q4) as f32;
+        }
+    }
+}
+
+/// `{ d: f32, qs: [i8; 256], bsums: [i16; 16] }`
+fn dequantize_q8_k(block: &[u8], y: &mut [f32]) {
+    let d = f32::from_le_bytes(le(block));
+    for (y, q) in y.iter_mut().zip(&block[4..260]) {
+        *y = d * *q as i8 as f32;
+    }
+}
+
+/// `{ d: f16, qs: [u8; 16] }`
+fn dequantize_iq4_nl(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let qs = &block[2..18];
+    for j in 0..16 {
+        y[j] = d * IQ4NL_VALUES[(qs[j] & 0x0f) as usize] as f32;
+        y[j + 16] = d * IQ4NL_VALUES[(qs[j] >> 4) as usize] as f32;
+    }
+}
+
+/// `{ d: f16, scales_h: u16, scales_l: [u8; 4], qs: [u8; 128] }`
+fn dequantize_iq4_xs(block: &[u8], y: &mut [f32]) {
+    let d = f16_at(block, 0);
+    let scales_h = u16::from_le_bytes(le(&block[2..]));
+    let scales_l = &block[4..8];
+
+    for (ib, (qs, y)) in block[8..136]
+        .chunks_exact(16)
+        .zip(y.chunks_exact_mut(32))
+        .enumerate()
+    {
+        let ls = ((scales_l[ib / 2] >> (4 * (ib % 2))) & 0x0f) as i32
+            | ((((scales_h >> (2 * ib)) & 3) as i32) << 4);
+        let dl = d * (ls - 32) as f32;
+        for j in 0..16 {
+            y[j] = dl * IQ4NL_VALUES[(qs[j] & 0x0f) as usize] as f32;
+            y[j + 16] = dl * IQ4NL_VALUES[(qs[j] >> 4) as usize] as f32;
+        }
+    }
+}
+
+/// Encode f32 values as whole blocks of `tensor_type`
+///
+/// `values` must hold a whole number of blocks and `out` exactly the bytes they encode to.
+/// Block formats follow ggml's reference quantizers (`quantize_row_*_ref`).
+pub fn quantize(tensor_type: TensorType, values: &[f32], out: &mut [u8]) -> Result<()> {
+    let type_size = tensor_type.type_size() as usize;
+    let block_size = tensor_type.block_size() as usize;
+    if !values.len().is_multiple_of(block_size)
+        || values.len() / block_size * type_size != out.len()
+    {
+        return Err(GgufError::InvalidFormat(format!(
+            "{} values don't encode to {} bytes of {:?} data",
+            values.len(),
+            out.len(),
+            tensor_type
+        )));
+    }
+
+    let elements = values.iter().zip(out.chunks_exact_mut(type_size));
+    match tensor_type {
+        TensorType::F32 => elements.for_each(|(x, y)| y.copy_from_slice(&x.to_le_bytes())),
+        TensorType::F16 => {
+            elements.for_each(|(x, y)| y.copy_from_slice(&f32_to_f16(*x).to_le_bytes()))
+        }
+        TensorType::BF16 => {
+            elements.for_each(|(x, y)| y.copy_from_slice(&f32_to_bf16(*x).to_le_bytes()))
+        }
+        _ => {
+            let encode = block_encoder(tensor_type).ok_or_else(|| {
+                GgufError::Unsupported(format!(
+                    "Quantization to {:?} is not supported",
+                    tensor_type
+                ))
+            })?;
+            for (x, block) in values
+                .chunks_exact(block_size)
+                .zip(out.chunks_exact_mut(type_size))
+            {
+                encode(x, block);
+            }
+        }
+    }
+    Ok(())
+}
+
+type BlockEncoder = fn(&[f32], &mut [u8]);
+
+fn block_encoder(tensor_type: TensorType) -> Option<BlockEncoder> {
+    Some(match tensor_type {
+        TensorType::Q40 => quantize_q4_0,
+        TensorType::Q41 => quantize_q4_1,
+        TensorType::Q50 => quantize_q5_0,
+        TensorType::Q51 => quantize_q5_1,
+        TensorType::Q80 => quantize_q8_0,
+        TensorType::Q81 => quantize_q8_1,
+        TensorType::Q2K => quantize_q2_k,
+        TensorType::Q3K => quantize_q3_k,
+        TensorType::Q4K => quantize_q4_k,
+        TensorType::Q5K => quantize_q5_k,
+        TensorType::Q6K => quantize_q6_k,
+        TensorType::Q8K => quantize_q8_k,
+        _ => return None,
+    })
+}
+
+/// Encode rows of f32 values as `tensor_type`, weighting the quantization error of each
+/// column by its importance
+///
+/// `importance` holds one weight per column (e.g. mean squared activations from an
+/// importance matrix) and `values` whole rows of that length. This follows ggml's
+/// `quantize_*` functions with `quant_weights`; types without an importance-aware
+/// quantizer (unquantized types and 8-bit quants) are encoded as by [`quantize`].
+pub fn quantize_weighted(
+    tensor_type: TensorType,
+    values: &[f32],
+    importance: &[f32],
+    out: &mut [u8],
+) -> Result<()> {
+    let row_len = importance.len();
+    let block_size = tensor_type.block_size() as usize;
+    if row_len == 0 || !row_len.is_multiple_of(block_size) || !values.len().is_multiple_of(row_len)
+    {
+        return Err(GgufError::InvalidFormat(format!(
+            "{} values don't form rows of {} {:?} values",
+            values.len(),
+            row_len,
+            tensor_type
+        )));
+    }
+    let Some(encode) = weighted_block_encoder(tensor_type) else {
+        return quantize(tensor_type, values, out);
+    };
+
+    let type_size = tensor_type.type_size() as usize;
+    let row_size = row_len / block_size * type_size;
+    if values.len() / row_len * row_size != out.len() {
+        return Err(GgufError::InvalidFormat(format!(
+            "{} values don't encode to {} bytes of {:?} data",
+            values.len(),
+            out.len(),
+            tensor_type
+        )));
+    }
+
+    // Legacy types weight each value by `importance * sqrt(sigma² + x²)` with the row's
+    // mean square; K-quants do the same per 256-value block in their encoders
+    let legacy = matches!(
+        tensor_type,
+        TensorType::Q40 | TensorType::Q41 | TensorType::Q50 | TensorType::Q51
+    );
+    let mut weights = vec![0.0f32; if legacy { row_len } else { 0 }];
+    for (row, out) in values
+        .chunks_exact(row_len)
+        .zip(out.chunks_exact_mut(row_size))
+    {
+        let qw = if legacy {
+            let sigma2 = row.iter().fold(0.0f32, |sum, v| sum + v * v) / row_len as f32;
+            for ((w, q), v) in weights.iter_mut().zip(importance).zip(row) {
+                *w = q * (sigma2 + v * v).sqrt();
+            }
+            &weights
+        } else {
+            importance
+        };
+        for ((x, qw), block) in row
+            .chunks_exact(block_size)
+            .zip(qw.chunks_exact(block_size))
+            .zip(out.chunks_exact_mut(type_size))
+        {
+            encode(x, qw, block);
+        }
+    }
+    Ok(())
+}
+
+type WeightedBlockEncoder = fn(&[f32], &[f32], &mut [u8]);
+
+fn weighted_block_encoder(tensor_type: TensorType) -> Option<WeightedBlockEncoder> {
+    Some(match tensor_type {
+        TensorType::Q40 => quantize_q4_0_weighted,
+        TensorType::Q41 => quantize_q4_1_weighted,
+        TensorType::Q50 => quantize_q5_0_weighted,
+        TensorType::Q51 => quantize_q5_1_weighted,
+        TensorType::Q2K => quantize_q2_k_weighted,
+        TensorType::Q3K => quantize_q3_k_weighted,
+        TensorType::Q4K => quantize_q4_k_weighted,
+        TensorType::Q5K => quantize_q5_k_weighted,
+        TensorType::Q6K => quantize_q6_k_weighted,
+        _ => return None,
+    })
+}
+
+/// Threshold below which a group of values is treated as all zero
+const GROUP_MAX_EPS: f32 = 1e-15;
+
+/// Round to the nearest integer (ties to even), as ggml's `nearest_int`
+fn nearest_int(value: f32) -> i32 {
+    let biased = (value + 12582912.0).to_bits() as i32;
+    (biased & 0x007f_ffff) - 0x0040_0000
+}
+
+/// Get the value of largest magnitude, keeping its sign
+fn signed_absmax(x: &[f32]) -> f32 {
+    let mut amax = 0.0;
+    let mut max = 0.0;
+    for &v in x {
+        if amax < v.abs() {
+            amax = v.abs();
+            max = v;
+        }
+    }
+    max
+}
+
+fn min_max(x: &[f32]) -> (f32, f32) {
+    let mut min = f32::MAX;
+    let mut max = -f32::MAX;
+    for &v in x {
+        if v < min {
+            min = v;
+        }
+        if v > max {
+            max = v;
+        }
+    }
+    (min, max)
+}
+
+fn put_f16(block: &mut [u8], i: usize, value: f32) {
+    block[i..i + 2].copy_from_slice(&f32_to_f16(value).to_le_bytes());
+}
+
+/// Round a value through f16, as stored block scales are
+fn f16_round(value: f32) -> f32 {
+    f16_to_f32(f32_to_f16(value))
+}
+
+fn quantize_q4_0(x: &[f32], block: &mut [u8]) {
+    let d = signed_absmax(x) / -8.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    put_f16(block, 0, d);
+    for j in 0..16 {
+        let xi0 = ((x[j] * id + 8.5) as i8).min(15) as u8;
+        let xi1 = ((x[j + 16] * id + 8.5) as i8).min(15) as u8;
+        block[2 + j] = xi0 | (xi1 << 4);
+    }
+}
+
+fn quantize_q4_1(x: &[f32], block: &mut [u8]) {
+    let (min, max) = min_max(x);
+    let d = (max - min) / 15.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    put_f16(block, 0, d);
+    put_f16(block, 2, min);
+    for j in 0..16 {
+        let xi0 = (((x[j] - min) * id + 0.5) as i8).min(15) as u8;
+        let xi1 = (((x[j + 16] - min) * id + 0.5) as i8).min(15) as u8;
+        block[4 + j] = xi0 | (xi1 << 4);
+    }
+}
+
+fn quantize_q5_0(x: &[f32], block: &mut [u8]) {
+    let d = signed_absmax(x) / -16.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    put_f16(block, 0, d);
+    let mut qh = 0u32;
+    for j in 0..16 {
+        let xi0 = ((x[j] * id + 16.5) as i8).min(31) as u8;
+        let xi1 = ((x[j + 16] * id + 16.5) as i8).min(31) as u8;
+        block[6 + j] = (xi0 & 0x0f) | ((xi1 & 0x0f) << 4);
+        qh |= (((xi0 & 0x10) >> 4) as u32) << j;
+        qh |= (((xi1 & 0x10) >> 4) as u32) << (j + 16);
+    }
+    block[2..6].copy_from_slice(&qh.to_le_bytes());
+}
+
+fn quantize_q5_1(x: &[f32], block: &mut [u8]) {
+    let (min, max) = min_max(x);
+    let d = (max - min) / 31.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    put_f16(block, 0, d);
+    put_f16(block, 2, min);
+    let mut qh = 0u32;
+    for j in 0..16 {
+        let xi0 = ((x[j] - min) * id + 0.5) as u8;
+        let xi1 = ((x[j + 16] - min) * id + 0.5) as u8;
+        block[8 + j] = (xi0 & 0x0f) | ((xi1 & 0x0f) << 4);
+        qh |= (((xi0 & 0x10) >> 4) as u32) << j;
+        qh |= (((xi1 & 0x10) >> 4) as u32) << (j + 16);
+    }
+    block[4..8].copy_from_slice(&qh.to_le_bytes());
+}
+
+fn quantize_q8_0(x: &[f32], block: &mut [u8]) {
+    let amax = x.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    let d = amax / 127.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    put_f16(block, 0, d);
+    for (q, v) in block[2..34].iter_mut().zip(x) {
+        *q = (v * id).round() as i8 as u8;
+    }
+}
+
+fn quantize_q8_1(x: &[f32], block: &mut [u8]) {
+    let amax = x.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    let d = amax / 127.0;
+    let id = if d != 0.0 { 1.0 / d } else { 0.0 };
+    let mut sum = 0;
+    for (q, v) in block[4..36].iter_mut().zip(x) {
+        let l = (v * id).round() as i8;
+        sum += l as i32;
+        *q = l as u8;
+    }
+    put_f16(block, 0, d);
+    put_f16(block, 2, sum as f32 * d);
+}
+
+// Importance-weighted legacy quantizers (ggml's `quantize_row_*_impl`). Their weights
+// already include the row's RMS, see `quantize_weighted`.
+
+fn quantize_q4_0_weighted(x: &[f32], weights: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 32];
+    let d = make_qx_quants(8, x, weights, &mut l);
+    put_f16(block, 0, d);
+    pack_nibbles(&l, &mut block[2..18]);
+}
+
+fn quantize_q4_1_weighted(x: &[f32], weights: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 32];
+    let mut laux = [0u8; 32];
+    let (d, min) = make_qkx2_quants(15, x, weights, &mut l, &mut laux, -0.9, 0.05, 36, false);
+    put_f16(block, 0, d);
+    put_f16(block, 2, -min);
+    pack_nibbles(&l, &mut block[4..20]);
+}
+
+fn quantize_q5_0_weighted(x: &[f32], weights: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 32];
+    let d = make_qx_quants(16, x, weights, &mut l);
+    put_f16(block, 0, d);
+    let qh = pack_nibbles(&l, &mut block[6..22]);
+    block[2..6].copy_from_slice(&qh.to_le_bytes());
+}
+
+fn quantize_q5_1_weighted(x: &[f32], weights: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 32];
+    let mut laux = [0u8; 32];
+    let (d, min) = make_qkx2_quants(31, x, weights, &mut l, &mut laux, -0.9, 0.05, 36, false);
+    put_f16(block, 0, d);
+    put_f16(block, 2, -min);
+    let qh = pack_nibbles(&l, &mut block[8..24]);
+    block[4..8].copy_from_slice(&qh.to_le_bytes());
+}
+
+/// Pack the low nibbles of 32 quants into 16 bytes, returning their fifth bits
+fn pack_nibbles(l: &[u8; 32], qs: &mut [u8]) -> u32 {
+    let mut qh = 0u32;
+    for j in 0..16 {
+        qs[j] = (l[j] & 0x0f) | ((l[j + 16] & 0x0f) << 4);
+        qh |= (((l[j] & 0x10) >> 4) as u32) << j;
+        qh |= (((l[j + 16] & 0x10) >> 4) as u32) << (j + 16);
+    }
+    qh
+}
+
+/// Fit `x ≈ scale * L + min` with `L` in `0..=nmax` and `min <= 0`, minimizing the
+/// weighted error over a few candidate scales (ggml's `make_qkx2_quants`)
+///
+/// Returns the scale and the negated min.
+#[allow(clippy::too_many_arguments)]
+fn make_qkx2_quants(
+    nmax: i32,
+    x: &[f32],
+    weights: &[f32],
+    l: &mut [u8],
+    laux: &mut [u8],
+    rmin: f32,
+    rdelta: f32,
+    nstep: i32,
+    use_mad: bool,
+) -> (f32, f32) {
+    let n = x.len();
+    let mut min = x[0];
+    let mut max = x[0];
+    let mut sum_w = weights[0];
+    let mut sum_x = sum_w * x[0];
+    for i in 1..n {
+        if x[i] < min {
+            min = x[i];
+        }
+        if x[i] > max {
+            max = x[i];
+        }
+        sum_w += weights[i];
+        sum_x += weights[i] * x[i];
+    }
+    if min > 0.0 {
+        min = 0.0;
+    }
+    if max == min {
+        l.fill(0);
+        return (0.0, -min);
+    }
+
+    let error = |diff: f32| if use_mad { diff.abs() } else { diff * diff };
+
+    let mut iscale = nmax as f32 / (max - min);
+    let mut scale = 1.0 / iscale;
+    let mut best_error = 0.0;
+    for i in 0..n {
+        l[i] = nearest_int(iscale * (x[i] - min)).clamp(0, nmax) as u8;
+        best_error += weights[i] * error(scale * l[i] as f32 + min - x[i]);
+    }
+    if nstep < 1 {
+        return (scale, -min);
+    }
+
+    for is in 0..=nstep {
+        iscale = (rmin + rdelta * is as f32 + nmax as f32) / (max - min);
+        let (mut sum_l, mut sum_l2, mut sum_xl) = (0.0, 0.0, 0.0);
+        for i in 0..n {
+            let li = nearest_int(iscale * (x[i] - min)).clamp(0, nmax);
+            laux[i] = li as u8;
+            let (w, li) = (weights[i], li as f32);
+            sum_l += w * li;
+            sum_l2 += w * li * li;
+            sum_xl += w * li * x[i];
+        }
+        let det = sum_w * sum_l2 - sum_l * sum_l;
+        if det > 0.0 {
+            let mut this_scale = (sum_w * sum_xl - sum_x * sum_l) / det;
+            let mut this_min = (sum_l2 * sum_x - sum_l * sum_xl) / det;
+            if this_min > 0.0 {
+                this_min = 0.0;
+                this_scale = sum_xl / sum_l2;
+            }
+            let mut cur_error = 0.0;
+            for i in 0..n {
+                cur_error += weights[i] * error(this_scale * laux[i] as f32 + this_min - x[i]);
+            }
+            if cur_error < best_error {
+                l[..n].copy_from_slice(&laux[..n]);
+                best_error = cur_error;
+                scale = this_scale;
+                min = this_min;
+            }
+        }
+    }
+    (scale, -min)
+}
+
+/// Fit symmetric quants `L` in `-nmax..nmax` (stored offset by `nmax`), refining the
+/// scale by weighted RMSE (ggml's `make_qx_quants` with `rmse_type = 1`)
+fn make_qx_quants(nmax: i32, x: &[f32], weights: &[f32], l: &mut [u8]) -> f32 {
+    let max = signed_absmax(x);
+    if max.abs() < GROUP_MAX_EPS {
+        l.fill(0);
+        return 0.0;
+    }
+
+    let quant = |iscale: f32, v: f32| nearest_int(iscale * v).clamp(-nmax, nmax - 1);
+    let sums = |iscale: f32| {
+        let (mut sumlx, mut suml2) = (0.0f32, 0.0f32);
+        for (&v, &w) in x.iter().zip(weights) {
+            let q = quant(iscale, v) as f32;
+            sumlx += w * v * q;
+            suml2 += w * q * q;
+        }
+        (sumlx, suml2)
+    };
+
+    let mut iscale = -(nmax as f32) / max;
+    for (li, &v) in l.iter_mut().zip(x) {
+        *li = (quant(iscale, v) + nmax) as u8;
+    }
+    let (sumlx, suml2) = sums(iscale);
+    let mut scale = if suml2 != 0.0 { sumlx / suml2 } else { 0.0 };
+    let mut best = scale * sumlx;
+
+    for is in (-9..=9).filter(|&is| is != 0) {
+        iscale = -(nmax as f32 + 0.1 * is as f32) / max;
+        let (sumlx, suml2) = sums(iscale);
+        if suml2 > 0.0 && sumlx * sumlx > best * suml2 {
+            for (li, &v) in l.iter_mut().zip(x) {
+                *li = (quant(iscale, v) + nmax) as u8;
+            }
+            scale = sumlx / suml2;
+            best = scale * sumlx;
+        }
+    }
+    scale
+}
+
+/// Fit symmetric quants `L` in `-nmax..nmax` (stored offset by `nmax`), refining
+/// individual quants to minimize the `x²`-weighted error (ggml's `make_q3_quants`)
+fn make_q3_quants(nmax: i32, x: &[f32], l: &mut [u8]) -> f32 {
+    let max = signed_absmax(x);
+    if max.abs() < GROUP_MAX_EPS {
+        l.fill(0);
+        return 0.0;
+    }
+
+    let iscale = -(nmax as f32) / max;
+    let mut q = [0i32; 16];
+    let (mut sumlx, mut suml2) = (0.0f32, 0.0f32);
+    for (qi, &v) in q.iter_mut().zip(x) {
+        *qi = nearest_int(iscale * v).clamp(-nmax, nmax - 1);
+        let w = v * v;
+        sumlx += w * v * *qi as f32;
+        suml2 += w * *qi as f32 * *qi as f32;
+    }
+
+    for _ in 0..5 {
+        let mut changed = false;
+        for (qi, &v) in q.iter_mut().zip(x) {
+            let w = v * v;
+            let mut slx = sumlx - w * v * *qi as f32;
+            if slx > 0.0 {
+                let mut sl2 = suml2 - w * *qi as f32 * *qi as f32;
+                let new_l = nearest_int(v * sl2 / slx).clamp(-nmax, nmax - 1);
+                if new_l != *qi {
+                    slx += w * v * new_l as f32;
+                    sl2 += w * new_l as f32 * new_l as f32;
+                    if sl2 > 0.0 && slx * slx * suml2 > sumlx * sumlx * sl2 {
+                        *qi = new_l;
+                        sumlx = slx;
+                        suml2 = sl2;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    for (li, qi) in l.iter_mut().zip(q) {
+        *li = (qi + nmax) as u8;
+    }
+    sumlx / suml2
+}
+
+/// Fit non-negative quants `L` in `0..=nmax` to non-negative values, minimizing the
+/// weighted error over a few candidate scales, then refining individual quants
+/// (ggml's `make_qp_quants`, used for the block scales of importance-weighted K-quants)
+fn make_qp_quants(nmax: i32, x: &[f32], l: &mut [u8], weights: &[f32]) -> f32 {
+    let max = x.iter().fold(0.0f32, |m, &v| m.max(v));
+    if max < GROUP_MAX_EPS {
+        l.fill(0);
+        return 0.0;
+    }
+
+    let quant = |iscale: f32, v: f32| nearest_int(iscale * v).clamp(0, nmax);
+    let mse = |iscale: f32| {
+        let scale = 1.0 / iscale;
+        x.iter()
+            .zip(weights)
+            .map(|(&v, &w)| {
+                let diff = v - scale * quant(iscale, v) as f32;
+                w * diff * diff
+            })
+            .sum::<f32>()
+    };
+
+    let mut iscale = nmax as f32 / max;
+    let mut best_mse = mse(iscale);
+    for is in (-4..=4).filter(|&is| is != 0) {
+        let iscale_is = (0.1 * is as f32 + nmax as f32) / max;
+        let mse = mse(iscale_is);
+        if mse < best_mse {
+            best_mse = mse;
+            iscale = iscale_is;
+        }
+    }
+
+    let (mut sumlx, mut suml2) = (0.0f32, 0.0f32);
+    for ((li, &v), &w) in l.iter_mut().zip(x).zip(weights) {
+        let q = quant(iscale, v);
+        *li = q as u8;
+        sumlx += w * v * q as f32;
+        suml2 += w * (q * q) as f32;
+    }
+
+    for _ in 0..5 {
+        let mut changed = false;
+        for ((li, &v), &w) in l.iter_mut().zip(x).zip(weights) {
+            let q = *li as f32;
+            let mut slx = sumlx - w * v * q;
+            let mut sl2 = suml2 - w * q * q;
+            if slx > 0.0 && sl2 > 0.0 {
+                let new_l = nearest_int(v * sl2 / slx).clamp(0, nmax);
+                if new_l != *li as i32 {
+                    slx += w * v * new_l as f32;
+                    sl2 += w * (new_l * new_l) as f32;
+                    if slx * slx * suml2 > sumlx * sumlx * sl2 {
+                        *li = new_l as u8;
+                        sumlx = slx;
+                        suml2 = sl2;
+                        changed = true;
+                    }
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    sumlx / suml2
+}
+
+/// Pack 2-bit quants, four 32-value groups per 32 bytes (Q2_K and Q3_K layout)
+fn pack_2bit(l: &[u8], qs: &mut [u8]) {
+    for (l, qs) in l.chunks_exact(128).zip(qs.chunks_exact_mut(32)) {
+        for (i, q) in qs.iter_mut().enumerate() {
+            *q = l[i] | (l[i + 32] << 2) | (l[i + 64] << 4) | (l[i + 96] << 6);
+        }
+    }
+}
+
+fn quantize_q2_k(x: &[f32], block: &mut [u8]) {
+    const Q4SCALE: f32 = 15.0;
+
+    let mut l = [0u8; 256];
+    let mut laux = [0u8; 16];
+    let mut weights = [0.0f32; 16];
+    let mut scales = [0.0f32; 16];
+    let mut mins = [0.0f32; 16];
+
+    let (mut max_scale, mut max_min) = (0.0f32, 0.0f32);
+    for j in 0..16 {
+        let x = &x[16 * j..16 * j + 16];
+        for (w, v) in weights.iter_mut().zip(x) {
+            *w = v.abs();
+        }
+        let l = &mut l[16 * j..16 * j + 16];
+        (scales[j], mins[j]) = make_qkx2_quants(3, x, &weights, l, &mut laux, -0.5, 0.1, 15, true);
+        max_scale = max_scale.max(scales[j]);
+        max_min = max_min.max(mins[j]);
+    }
+
+    let mut sc = [0u8; 16];
+    let d = if max_scale > 0.0 {
+        let iscale = Q4SCALE / max_scale;
+        for (sc, s) in sc.iter_mut().zip(scales) {
+            *sc = nearest_int(iscale * s) as u8;
+        }
+        max_scale / Q4SCALE
+    } else {
+        0.0
+    };
+    let dmin = if max_min > 0.0 {
+        let iscale = Q4SCALE / max_min;
+        for (sc, m) in sc.iter_mut().zip(mins) {
+            *sc |= (nearest_int(iscale * m) as u8) << 4;
+        }
+        max_min / Q4SCALE
+    } else {
+        0.0
+    };
+    write_q2_k(x, &sc, d, dmin, &mut l, block);
+}
+
+fn quantize_q2_k_weighted(x: &[f32], qw: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let mut laux = [0u8; 16];
+    let mut weights = [0.0f32; 16];
+    let mut sw = [0.0f32; 16];
+    let mut scales = [0.0f32; 16];
+    let mut mins = [0.0f32; 16];
+
+    let sigma2 = x.iter().fold(0.0f32, |sum, v| sum + v * v) / 256.0;
+    for j in 0..16 {
+        let x = &x[16 * j..16 * j + 16];
+        for ((w, q), v) in weights.iter_mut().zip(&qw[16 * j..]).zip(x) {
+            *w = q * (sigma2 + v * v).sqrt();
+        }
+        sw[j] = weights.iter().sum();
+        let l = &mut l[16 * j..16 * j + 16];
+        (scales[j], mins[j]) =
+            make_qkx2_quants(3, x, &weights, l, &mut laux, -0.9, 0.05, 36, false);
+    }
+
+    let mut ls = [0u8; 16];
+    let mut lm = [0u8; 16];
+    let d = make_qp_quants(15, &scales, &mut ls, &sw);
+    let dmin = make_qp_quants(15, &mins, &mut lm, &sw);
+    let sc: [u8; 16] = std::array::from_fn(|j| ls[j] | (lm[j] << 4));
+    write_q2_k(x, &sc, d, dmin, &mut l, block);
+}
+
+/// Requantize with the final (f16-rounded) scales and store a Q2_K block
+fn write_q2_k(x: &[f32], sc: &[u8; 16], d: f32, dmin: f32, l: &mut [u8; 256], block: &mut [u8]) {
+    let (d, dmin) = (f16_round(d), f16_round(dmin));
+    for (j, sc) in sc.iter().enumerate() {
+        let dl = d * (sc & 0x0f) as f32;
+        if dl == 0.0 {
+            continue;
+        }
+        let dm = dmin * (sc >> 4) as f32;
+        for i in 16 * j..16 * j + 16 {
+            l[i] = nearest_int((x[i] + dm) / dl).clamp(0, 3) as u8;
+        }
+    }
+
+    block[0..16].copy_from_slice(sc);
+    pack_2bit(l, &mut block[16..80]);
+    put_f16(block, 80, d);
+    put_f16(block, 82, dmin);
+}
+
+fn quantize_q3_k(x: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let mut scales = [0.0f32; 16];
+
+    let (mut max_scale, mut amax) = (0.0f32, 0.0f32);
+    for j in 0..16 {
+        scales[j] = make_q3_quants(4, &x[16 * j..16 * j + 16], &mut l[16 * j..16 * j + 16]);
+        if scales[j].abs() > amax {
+            amax = scales[j].abs();
+            max_scale = scales[j];
+        }
+    }
+
+    let mut ls = [0u8; 16];
+    let d = if max_scale != 0.0 {
+        let iscale = -32.0 / max_scale;
+        for (ls, s) in ls.iter_mut().zip(scales) {
+            *ls = (nearest_int(iscale * s).clamp(-32, 31) + 32) as u8;
+        }
+        1.0 / iscale
+    } else {
+        0.0
+    };
+    write_q3_k(x, &ls, d, &mut l, block);
+}
+
+fn quantize_q3_k_weighted(x: &[f32], qw: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let mut weights = [0.0f32; 16];
+    let mut sw = [0.0f32; 16];
+    let mut scales = [0.0f32; 16];
+
+    let sigma2 = 2.0 * x.iter().fold(0.0f32, |sum, v| sum + v * v) / 256.0;
+    for j in 0..16 {
+        let x = &x[16 * j..16 * j + 16];
+        for ((w, q), v) in weights.iter_mut().zip(&qw[16 * j..]).zip(x) {
+            *w = q * (sigma2 + v * v).sqrt();
+        }
+        sw[j] = weights.iter().sum();
+        scales[j] = make_qx_quants(4, x, &weights, &mut l[16 * j..16 * j + 16]);
+    }
+
+    // Block scales are stored offset by 32, as `make_qx_quants` returns them
+    let mut ls = [0u8; 16];
+    let d = make_qx_quants(32, &scales, &sw, &mut ls);
+    write_q3_k(x, &ls, d, &mut l, block);
+}
+
+/// Pack the 6-bit scales `ls` (offset by 32), requantize with the final scales and
+/// store a Q3_K block
+fn write_q3_k(x: &[f32], ls: &[u8; 16], d: f32, l: &mut [u8; 256], block: &mut [u8]) {
+    let mut sc = [0u8; 12];
+    if d != 0.0 {
+        for (j, &ls) in ls.iter().enumerate() {
+            if j < 8 {
+                sc[j] = ls & 0x0f;
+            } else {
+                sc[j - 8] |= (ls & 0x0f) << 4;
+            }
+            sc[j % 4 + 8] |= (ls >> 4) << (2 * (j / 4));
+        }
+    }
+    let d = f16_round(d);
+
+    for j in 0..16 {
+        let low = if j < 8 { sc[j] & 0x0f } else { sc[j - 8] >> 4 };
+        let ls = (low | (((sc[8 + j % 4] >> (2 * (j / 4))) & 3) << 4)) as i32 - 32;
+        let dl = d * ls as f32;
+        if dl == 0.0 {
+            continue;
+        }
+        for i in 16 * j..16 * j + 16 {
+            l[i] = (nearest_int(x[i] / dl).clamp(-4, 3) + 4) as u8;
+        }
+    }
+
+    // The high bit of the n-th group of 32 quants goes into bit n of `hmask`
+    let mut hmask = [0u8; 32];
+    for (j, li) in l.iter_mut().enumerate() {
+        if *li > 3 {
+            hmask[j % 32] |= 1 << (j / 32);
+            *li -= 4;
+        }
+    }
+
+    block[0..32].copy_from_slice(&hmask);
+    pack_2bit(l, &mut block[32..96]);
+    block[96..108].copy_from_slice(&sc);
+    put_f16(block, 108, d);
+}
+
+/// Pack eight 6-bit scales and mins into 12 bytes (Q4_K and Q5_K layout)
+fn pack_scale_min_k4(ls: &[u8; 8], lm: &[u8; 8]) -> [u8; 12] {
+    let mut packed = [0u8; 12];
+    for j in 0..8 {
+        if j < 4 {
+            packed[j] = ls[j];
+            packed[j + 4] = lm[j];
+        } else {
+            packed[j + 4] = (ls[j] & 0x0f) | ((lm[j] & 0x0f) << 4);
+            packed[j - 4] |= (ls[j] >> 4) << 6;
+            packed[j] |= (lm[j] >> 4) << 6;
+        }
+    }
+    packed
+}
+
+/// Shared Q4_K/Q5_K quantization: fills `l` with quants in `0..=nmax` and returns
+/// `(d, dmin, packed scales)`
+fn quantize_k4_scales(
+    x: &[f32],
+    nmax: i32,
+    rmin: f32,
+    nstep: i32,
+    l: &mut [u8; 256],
+) -> (f32, f32, [u8; 12]) {
+    let mut laux = [0u8; 32];
+    let mut weights = [0.0f32; 32];
+    let mut scales = [0.0f32; 8];
+    let mut mins = [0.0f32; 8];
+
+    let (mut max_scale, mut max_min) = (0.0f32, 0.0f32);
+    for j in 0..8 {
+        let x = &x[32 * j..32 * j + 32];
+        let sum_x2: f32 = x.iter().fold(0.0, |sum, v| sum + v * v);
+        let av_x = (sum_x2 / 32.0).sqrt();
+        for (w, v) in weights.iter_mut().zip(x) {
+            *w = av_x + v.abs();
+        }
+        let l = &mut l[32 * j..32 * j + 32];
+        (scales[j], mins[j]) =
+            make_qkx2_quants(nmax, x, &weights, l, &mut laux, rmin, 0.1, nstep, false);
+        max_scale = max_scale.max(scales[j]);
+        max_min = max_min.max(mins[j]);
+    }
+
+    let inv_scale = if max_scale > 0.0 {
+        63.0 / max_scale
+    } else {
+        0.0
+    };
+    let inv_min = if max_min > 0.0 { 63.0 / max_min } else { 0.0 };
+    let mut ls = [0u8; 8];
+    let mut lm = [0u8; 8];
+    for j in 0..8 {
+        ls[j] = (nearest_int(inv_scale * scales[j]) as u8).min(63);
+        lm[j] = (nearest_int(inv_min * mins[j]) as u8).min(63);
+    }
+    requantize_k4(x, nmax, max_scale / 63.0, max_min / 63.0, &ls, &lm, l)
+}
+
+/// Importance-weighted variant of [`quantize_k4_scales`]
+fn quantize_k4_scales_weighted(
+    x: &[f32],
+    qw: &[f32],
+    nmax: i32,
+    l: &mut [u8; 256],
+) -> (f32, f32, [u8; 12]) {
+    let mut laux = [0u8; 32];
+    let mut weights = [0.0f32; 32];
+    let mut sw = [0.0f32; 8];
+    let mut scales = [0.0f32; 8];
+    let mut mins = [0.0f32; 8];
+
+    let sigma2 = 2.0 * x.iter().fold(0.0f32, |sum, v| sum + v * v) / 256.0;
+    for j in 0..8 {
+        let x = &x[32 * j..32 * j + 32];
+        for ((w, q), v) in weights.iter_mut().zip(&qw[32 * j..]).zip(x) {
+            *w = q * (sigma2 + v * v).sqrt();
+        }
+        sw[j] = weights.iter().sum();
+        let l = &mut l[32 * j..32 * j + 32];
+        (scales[j], mins[j]) =
+            make_qkx2_quants(nmax, x, &weights, l, &mut laux, -0.9, 0.05, 36, false);
+    }
+
+    let mut ls = [0u8; 8];
+    let mut lm = [0u8; 8];
+    let d = make_qp_quants(63, &scales, &mut ls, &sw);
+    let dmin = make_qp_quants(63, &mins, &mut lm, &sw);
+    requantize_k4(x, nmax, d, dmin, &ls, &lm, l)
+}
+
+/// Pack Q4_K/Q5_K scales and mins and requantize `x` with the final (f16-rounded) values
+fn requantize_k4(
+    x: &[f32],
+    nmax: i32,
+    d: f32,
+    dmin: f32,
+    ls: &[u8; 8],
+    lm: &[u8; 8],
+    l: &mut [u8; 256],
+) -> (f32, f32, [u8; 12]) {
+    let packed = pack_scale_min_k4(ls, lm);
+    let (d, dmin) = (f16_round(d), f16_round(dmin));
+
+    for j in 0..8 {
+        let (sc, m) = scale_min_k4(j, &packed);
+        let dl = d * sc;
+        if dl == 0.0 {
+            continue;
+        }
+        let dm = dmin * m;
+        for i in 32 * j..32 * j + 32 {
+            l[i] = nearest_int((x[i] + dm) / dl).clamp(0, nmax) as u8;
+        }
+    }
+    (d, dmin, packed)
+}
+
+fn quantize_q4_k(x: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let scales = quantize_k4_scales(x, 15, -1.0, 20, &mut l);
+    write_q4_k(scales, &l, block);
+}
+
+fn quantize_q4_k_weighted(x: &[f32], qw: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let scales = quantize_k4_scales_weighted(x, qw, 15, &mut l);
+    write_q4_k(scales, &l, block);
+}
+
+fn write_q4_k((d, dmin, scales): (f32, f32, [u8; 12]), l: &[u8; 256], block: &mut [u8]) {
+    put_f16(block, 0, d);
+    put_f16(block, 2, dmin);
+    block[4..16].copy_from_slice(&scales);
+    for (l, q) in l.chunks_exact(64).zip(block[16..144].chunks_exact_mut(32)) {
+        for i in 0..32 {
+            q[i] = l[i] | (l[i + 32] << 4);
+        }
+    }
+}
+
+fn quantize_q5_k(x: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let scales = quantize_k4_scales(x, 31, -0.5, 15, &mut l);
+    write_q5_k(scales, &l, block);
+}
+
+fn quantize_q5_k_weighted(x: &[f32], qw: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let scales = quantize_k4_scales_weighted(x, qw, 31, &mut l);
+    write_q5_k(scales, &l, block);
+}
+
+fn write_q5_k((d, dmin, scales): (f32, f32, [u8; 12]), l: &[u8; 256], block: &mut [u8]) {
+    put_f16(block, 0, d);
+    put_f16(block, 2, dmin);
+    block[4..16].copy_from_slice(&scales);
+    let mut qh = [0u8; 32];
+    for (n, (l, ql)) in l
+        .chunks_exact(64)
+        .zip(block[48..176].chunks_exact_mut(32))
+        .enumerate()
+    {
+        let (m1, m2) = (1u8 << (2 * n), 2u8 << (2 * n));
+        for j in 0..32 {
+            let (mut l1, mut l2) = (l[j], l[j + 32]);
+            if l1 > 15 {
+                l1 -= 16;
+                qh[j] |= m1;
+            }
+            if l2 > 15 {
+                l2 -= 16;
+                qh[j] |= m2;
+            }
+            ql[j] = l1 | (l2 << 4);
+        }
+    }
+    block[16..48].copy_from_slice(&qh);
+}
+
+fn quantize_q6_k(x: &[f32], block: &mut [u8]) {
+    let weights: [f32; 256] = std::array::from_fn(|i| x[i] * x[i]);
+    quantize_q6_k_weighted(x, &weights, block);
+}
+
+/// Q6_K weights each value directly by its importance (`x²` without an imatrix)
+fn quantize_q6_k_weighted(x: &[f32], qw: &[f32], block: &mut [u8]) {
+    let mut l = [0u8; 256];
+    let mut scales = [0.0f32; 16];
+
+    let (mut max_scale, mut max_abs_scale) = (0.0f32, 0.0f32);
+    for (j, scale) in scales.iter_mut().enumerate() {
+        let range = 16 * j..16 * j + 16;
+        *scale = make_qx_quants(32, &x[range.clone()], &qw[range.clone()], &mut l[range]);
+        if scale.abs() > max_abs_scale {
+            max_abs_scale = scale.abs();
+            max_scale = *scale;
+        }
+    }
+    if max_abs_scale < GROUP_MAX_EPS {
+        block.fill(0);
+        return;
+    }
+
+    let iscale = -128.0 / max_scale;
+    let d = f16_round(1.0 / iscale);
+    let mut sc = [0i8; 16];
+    for (sc, s) in sc.iter_mut().zip(scales) {
+        *sc = nearest_int(iscale * s).min(127) as i8;
+    }
+
+    for (j, sc) in sc.iter().enumerate() {
+        let dl = d * *sc as f32;
+        if dl == 0.0 {
+            continue;
+        }
+        for i in 16 * j..16 * j + 16 {
+            l[i] = (nearest_int(x[i] / dl).clamp(-32, 31) + 32) as u8;
+        }
+    }
+
+    let (ql, rest) = block.split_at_mut(128);
+    let (qh, rest) = rest.split_at_mut(64);
+    for ((l, ql), qh) in l
+        .chunks_exact(128)
+        .zip(ql.chunks_exact_mut(64))
+        .zip(qh.chunks_exact_mut(32))
+    {
+        for i in 0..32 {
+            ql[i] = (l[i] & 0x0f) | ((l[i + 64] & 0x0f) << 4);
+            ql[i + 32] = (l[i + 32] & 0x0f) | ((l[i + 96] & 0x0f) << 4);
+            qh[i] = (l[i] >> 4)
+                | ((l[i + 32] >> 4) << 2)
+                | ((l[i + 64] >> 4) << 4)
+                | ((l[i + 96] >> 4) << 6);
+        }
+    }
+    for (dst, s) in rest[..16].iter_mut().zip(sc) {
+        *dst = s as u8;
+    }
+    put_f16(block, 208, d);
+}
+
+fn quantize_q8_k(x: &[f32], block: &mut [u8]) {
+    let max = signed_absmax(x);
+    if max == 0.0 {
+        block.fill(0);
+        return;
+    }
+
+    let iscale = -127.0 / max;
+    for (q, v) in block[4..260].iter_mut().zip(x) {
+        *q = nearest_int(iscale * v).min(127) as i8 as u8;
+    }
+    for j in 0..16 {
+        let sum: i16 = block[4 + 16 * j..4 + 16 * j + 16]
+            .iter()
+            .map(|&q| q as i8 as i16)
+            .sum();
+        block[260 + 2 * j..262 + 2 * j].copy_from_slice(&sum.to_le_bytes());
+    }
+    block[0..4].copy_from_slice(&(1.0 / iscale).to_le_bytes());
+}
@@ -0,0 +1,592 @@
+//! Requantization of GGUF files
+//!
+//! [`requantize`] rewrites a GGUF file with tensor types chosen by a
+//! [`QuantizationPolicy`]: a default type plus rules matching tensor names and layer
+//! positions. Tensors are converted and written one at a time, so memory use stays at
+//! about the size of the largest tensor. The output's `general.file_type` and
+//! `general.quantization_version` are updated to match.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::imatrix::{Imatrix, ImatrixEntry};
+use crate::metadata::{GgufError, GgufHeader, GgufReader, Result, TensorType, Value};
+use crate::model::block_index;
+use crate::tensors::{TensorInfo, TensorLoader};
+use crate::writer::{GgufWriter, create_output};
+
+/// Version of the quantized block layouts, stored as `general.quantization_version`
+pub const QUANTIZATION_VERSION: u32 = 2;
+
+/// llama.cpp model file types, stored as `general.file_type`
+///
+/// Each names the predominant tensor type; the `S`/`M`/`L` K-quant variants differ in
+/// which tensors are kept at higher precision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum FileType {
+    F32 = 0,
+    F16 = 1,
+    Q40 = 2,
+    Q41 = 3,
+    Q80 = 7,
+    Q50 = 8,
+    Q51 = 9,
+    Q2K = 10,
+    Q3KS = 11,
+    Q3KM = 12,
+    Q3KL = 13,
+    Q4KS = 14,
+    Q4KM = 15,
+    Q5KS = 16,
+    Q5KM = 17,
+    Q6K = 18,
+    BF16 = 32,
+}
+
+impl FileType {
+    const ALL: [FileType; 17] = [
+        FileType::F32,
+        FileType::F16,
+        FileType::Q40,
+        FileType::Q41,
+        FileType::Q80,
+        FileType::Q50,
+        FileType::Q51,
+        FileType::Q2K,
+        FileType::Q3KS,
+        FileType::Q3KM,
+        FileType::Q3KL,
+        FileType::Q4KS,
+        FileType::Q4KM,
+        FileType::Q5KS,
+        FileType::Q5KM,
+        FileType::Q6K,
+        FileType::BF16,
+    ];
+
+    /// Convert a raw `general.file_type` value into a FileType
+    pub fn from_u32(value: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|t| *t as u32 == value)
+    }
+
+    /// Get llama.cpp's name for this file type (e.g. `"Q4_K_M"`)
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileType::F32 => "F32",
+            FileType::F16 => "F16",
+            FileType::Q40 => "Q4_0",
+            FileType::Q41 => "Q4_1",
+            FileType::Q80 => "Q8_0",
+            FileType::Q50 => "Q5_0",
+            FileType::Q51 => "Q5_1",
+            FileType::Q2K => "Q2_K",
+            FileType::Q3KS => "Q3_K_S",
+            FileType::Q3KM => "Q3_K_M",
+            FileType::Q3KL => "Q3_K_L",
+            FileType::Q4KS => "Q4_K_S",
+            FileType::Q4KM => "Q4_K_M",
+            FileType::Q5KS => "Q5_K_S",
+            FileType::Q5KM => "Q5_K_M",
+            FileType::Q6K => "Q6_K",
+            FileType::BF16 => "BF16",
+        }
+    }
+
+    /// Parse a file type name such as `"Q4_K_M"` (case-insensitive)
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Get the tensor type used for most tensors
+    pub fn base_type(&self) -> TensorType {
+        match self {
+            FileType::F32 => TensorType::F32,
+            FileType::F16 => TensorType::F16,
+            FileType::Q40 => TensorType::Q40,
+            FileType::Q41 => TensorType::Q41,
+            FileType::Q80 => TensorType::Q80,
+            FileType::Q50 => TensorType::Q50,
+            FileType::Q51 => TensorType::Q51,
+            FileType::Q2K => TensorType::Q2K,
+            FileType::Q3KS | FileType::Q3KM | FileType::Q3KL => TensorType::Q3K,
+            FileType::Q4KS | FileType::Q4KM => TensorType::Q4K,
+            FileType::Q5KS | FileType::Q5KM => TensorType::Q5K,
+            FileType::Q6K => TensorType::Q6K,
+            FileType::BF16 => TensorType::BF16,
+        }
+    }
+
+    /// Get the file type for a model made mostly of `tensor_type` tensors
+    ///
+    /// K-quants map to their `_M` variant.
+    pub fn for_type(tensor_type: TensorType) -> Option<Self> {
+        match tensor_type {
+            TensorType::Q3K => Some(FileType::Q3KM),
+            TensorType::Q4K => Some(FileType::Q4KM),
+            TensorType::Q5K => Some(FileType::Q5KM),
+            _ => Self::ALL.into_iter().find(|t| t.base_type() == tensor_type),
+        }
+    }
+}
+
+/// Assigns a tensor type to tensors whose names match a pattern
+///
+/// Patterns match whole names, with `*` standing for any run of characters (e.g.
+/// `"blk.*.attn_v.weight"`). A rule may be limited to the first and/or last layers of
+/// the model, in which case it only matches `blk.{N}.` tensors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeRule {
+    /// Tensor name pattern
+    pub pattern: String,
+    /// Type of the matching tensors
+    pub tensor_type: TensorType,
+    /// Number of leading layers the rule applies to
+    pub first_layers: Option<usize>,
+    /// Number of trailing layers the rule applies to
+    pub last_layers: Option<usize>,
+}
+
+impl TypeRule {
+    /// Create a rule applying to all tensors matching `pattern`
+    pub fn new(pattern: impl Into<String>, tensor_type: TensorType) -> Self {
+        Self {
+            pattern: pattern.into(),
+            tensor_type,
+            first_layers: None,
+            last_layers: None,
+        }
+    }
+
+    /// Limit the rule to the first `n` layers (combined with [`Self::last_layers`])
+    pub fn first_layers(mut self, n: usize) -> Self {
+        self.first_layers = Some(n);
+        self
+    }
+
+    /// Limit the rule to the last `n` layers (combined with [`Self::first_layers`])
+    pub fn last_layers(mut self, n: usize) -> Self {
+        self.last_layers = Some(n);
+        self
+    }
+
+    /// Check if the rule applies to a tensor in a model with `block_count` layers
+    pub fn matches(&self, name: &str, block_count: usize) -> bool {
+        if !glob_match(&self.pattern, name) {
+            return false;
+        }
+        if self.first_layers.is_none() && self.last_layers.is_none() {
+            return true;
+        }
+        block_index(name).is_some_and(|bid| {
+            self.first_layers.is_some_and(|n| bid < n)
+                || self.last_layers.is_some_and(|n| bid + n >= block_count)
+        })
+    }
+}
+
+/// Chooses the type of each tensor when requantizing
+///
+/// The first matching rule wins, otherwise the default type is used. Only tensors with
+/// at least two dimensions are quantized to block formats (1-D tensors such as norms
+/// and biases keep their type), integer and i-quant grid (IQ1/IQ2/IQ3) tensors are
+/// never converted since they can't be dequantized, and tensors whose rows aren't whole
+/// blocks of the chosen type fall back to a type with smaller blocks, as llama.cpp does.
+/// Every type the rules choose must have an encoder (see [`Self::check_types`]).
+///
+/// With an importance matrix, tensors it covers are quantized with its per-column
+/// weights.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizationPolicy {
+    /// Type of tensors not matched by any rule
+    pub default_type: TensorType,
+    /// Rules, in order of precedence
+    pub rules: Vec<TypeRule>,
+    /// Value written to `general.file_type`, if any
+    pub file_type: Option<FileType>,
+    /// Importance matrix weighting the quantization error, if any
+    pub imatrix: Option<Arc<Imatrix>>,
+}
+
+impl QuantizationPolicy {
+    /// Create a policy converting every tensor to `default_type`
+    pub fn new(default_type: TensorType) -> Self {
+        Self {
+            default_type,
+            rules: Vec::new(),
+            file_type: FileType::for_type(default_type),
+            imatrix: None,
+        }
+    }
+
+    /// Create llama.cpp's policy for a file type, for a model with `block_count` layers
+    ///
+    /// Most types keep `output.weight` at Q6_K; the `_M` and `_L` variants also use more
+    /// bits for `attn_v` and `ffn_down`, in the first and last eighth of the layers
+    /// for `_M`.
+    pub fn preset(file_type: FileType, block_count: usize) -> Self {
+        let edge = (block_count / 8).max(1);
+        let edges = |pattern: &str, tensor_type| {
+            TypeRule::new(pattern, tensor_type)
+                .first_layers(edge)
+                .last_layers(edge)
+        };
+        let output = TypeRule::new("output.weight", TensorType::Q6K);
+
+        let mut policy = Self::new(file_type.base_type()).with_file_type(file_type);
+        policy.rules = match file_type {
+            FileType::F32 | FileType::F16 | FileType::BF16 | FileType::Q80 | FileType::Q6K => {
+                Vec::new()
+            }
+            FileType::Q40
+            | FileType::Q41
+            | FileType::Q50
+            | FileType::Q51
+            | FileType::Q3KS
+            | FileType::Q4KS
+            | FileType::Q5KS => vec![output],
+            FileType::Q2K => vec![
+                output,
+                TypeRule::new("blk.*.attn_v.weight", TensorType::Q3K),
+            ],
+            FileType::Q3KM => vec![
+                output,
+                edges("blk.*.attn_v.weight", TensorType::Q5K),
+                TypeRule::new("blk.*.attn_v.weight", TensorType::Q4K),
+                TypeRule::new("blk.*.ffn_down.weight", TensorType::Q4K),
+            ],
+            FileType::Q3KL => vec![
+                output,
+                TypeRule::new("blk.*.attn_v.weight", TensorType::Q5K),
+                TypeRule::new("blk.*.ffn_down.weight", TensorType::Q5K),
+            ],
+            FileType::Q4KM | FileType::Q5KM => vec![
+                output,
+                edges("blk.*.attn_v.weight", TensorType::Q6K),
+                edges("blk.*.ffn_down.weight", TensorType::Q6K),
+            ],
+        };
+        policy
+    }
+
+    /// Add a rule, with lower precedence than the existing ones
+    pub fn with_rule(mut self, rule: TypeRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Set the value written to `general.file_type`
+    pub fn with_file_type(mut self, file_type: FileType) -> Self {
+        self.file_type = Some(file_type);
+        self
+    }
+
+    /// Quantize with the weights of an importance matrix
+    pub fn with_imatrix(mut self, imatrix: Imatrix) -> Self {
+        self.imatrix = Some(Arc::new(imatrix));
+        self
+    }
+
+    /// Check that every type the policy can choose has an encoder
+    ///
+    /// Returns `GgufError::Unsupported` naming the first type that can't be produced.
+    pub fn check_types(&self) -> Result<()> {
+        let types =
+            std::iter::once(self.default_type).chain(self.rules.iter().map(|r| r.tensor_type));
+        for tensor_type in types {
+            if !tensor_type.can_quantize() {
+                return Err(GgufError::Unsupported(format!(
+                    "Quantization to {}",
+                    tensor_type.name()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Choose the type of a tensor in a model with `block_count` layers
+    pub fn tensor_type(&self, info: &TensorInfo, block_count: usize) -> TensorType {
+        let source = info.tensor_type;
+        // Integers and i-quant grid types can't be dequantized, so they are kept
+        if !source.is_float() || !source.can_dequantize() {
+            return source;
+        }
+
+        let mut tensor_type = self
+            .rules
+            .iter()
+            .find(|rule| rule.matches(&info.name, block_count))
+            .map_or(self.default_type, |rule| rule.tensor_type);
+        if tensor_type.is_quantized() && info.dims.len() < 2 {
+            return source;
+        }
+
+        while !info.row_len().is_multiple_of(tensor_type.block_size()) {
+            tensor_type = fallback_type(tensor_type);
+        }
+        tensor_type
+    }
+}
+
+/// Type change of a single tensor during requantization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TensorChange {
+    /// Tensor name
+    pub name: String,
+    /// Type in the input file
+    pub original_type: TensorType,
+    /// Type in the output file
+    pub tensor_type: TensorType,
+    /// Data size in the input file, in bytes
+    pub original_size: u64,
+    /// Data size in the output file, in bytes
+    pub size: u64,
+    /// Whether the tensor was quantized using importance matrix data
+    pub used_imatrix: bool,
+}
+
+/// Summary of a requantization
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequantizeReport {
+    /// Every tensor, in file order
+    pub tensors: Vec<TensorChange>,
+    /// File type written to the output, if any
+    pub file_type: Option<FileType>,
+}
+
+impl RequantizeReport {
+    /// Total tensor data size of the input, in bytes
+    pub fn original_size(&self) -> u64 {
+        self.tensors.iter().map(|t| t.original_size).sum()
+    }
+
+    /// Total tensor data size of the output, in bytes
+    pub fn size(&self) -> u64 {
+        self.tensors.iter().map(|t| t.size).sum()
+    }
+
+    /// Count the output tensors of each type
+    pub fn type_counts(&self) -> HashMap<TensorType, usize> {
+        let mut counts = HashMap::new();
+        for tensor in &self.tensors {
+            *counts.entry(tensor.tensor_type).or_default() += 1;
+        }
+        counts
+    }
+}
+
+/// Requantize the GGUF file at `input` into a new file at `output`
+///
+/// `output` must not be the input file itself. The input is checked before the output is
+/// created, so a failing requantization leaves an existing output untouched.
+pub fn requantize_file<P: AsRef<Path>, Q: AsRef<Path>>(
+    input: P,
+    output: Q,
+    policy: &QuantizationPolicy,
+) -> Result<RequantizeReport> {
+    let mut reader = BufReader::new(File::open(&input)?);
+    let plan = Plan::new(&mut reader, policy)?;
+    let writer = BufWriter::new(create_output(output.as_ref(), &[input.as_ref()])?);
+    plan.write(&mut reader, writer, policy)
+}
+
+/// Requantize a GGUF file read from `reader`, writing the result to `writer`
+///
+/// Metadata is copied, with `general.quantization_version` updated and
+/// `general.file_type` set from the policy (or removed if the policy has none). Quantized
+/// input tensors are dequantized before conversion, so files can be requantized from any
+/// type this crate can dequantize. Tensors keeping their type are copied unchanged.
+///
+/// Every conversion, and the importance matrix, are checked before anything is written.
+pub fn requantize<R: Read + Seek, W: Write>(
+    reader: &mut R,
+    writer: W,
+    policy: &QuantizationPolicy,
+) -> Result<RequantizeReport> {
+    Plan::new(reader, policy)?.write(reader, writer, policy)
+}
+
+/// Output metadata and tensor types of a requantization, checked against the input
+struct Plan {
+    metadata: HashMap<String, Value>,
+    tensor_infos: Vec<TensorInfo>,
+    output_infos: Vec<TensorInfo>,
+    tensor_data_start: u64,
+}
+
+impl Plan {
+    /// Read the input's header and tensor infos and choose the output types
+    fn new<R: Read + Seek>(reader: &mut R, policy: &QuantizationPolicy) -> Result<Self> {
+        let header = GgufHeader::parse(reader)?;
+        if !header.is_version_supported() {
+            return Err(GgufError::Unsupported(format!(
+                "GGUF version {}",
+                header.version
+            )));
+        }
+        let mut metadata = GgufReader::read_metadata(reader, header.n_kv)?;
+        let tensor_infos = TensorLoader::read_tensor_info(reader, header.n_tensors)?;
+        let tensor_data_start = TensorLoader::get_aligned_tensor_data_start(reader, &metadata)?;
+
+        policy.check_types()?;
+        let block_count = block_count(&tensor_infos);
+        let output_infos: Vec<TensorInfo> = tensor_infos
+            .iter()
+            .map(|info| TensorInfo {
+                tensor_type: policy.tensor_type(info, block_count),
+                ..info.clone()
+            })
+            .collect();
+
+        match policy.file_type {
+            Some(file_type) => {
+                metadata.insert(
+                    "general.file_type".to_string(),
+                    Value::Uint32(file_type as u32),
+                );
+            }
+            // The input's file type would no longer describe the tensors
+            None => {
+                metadata.remove("general.file_type");
+            }
+        }
+        metadata.insert(
+            "general.quantization_version".to_string(),
+            Value::Uint32(QUANTIZATION_VERSION),
+        );
+
+        let plan = Self {
+            metadata,
+            tensor_infos,
+            output_infos,
+            tensor_data_start,
+        };
+        plan.check(policy)?;
+        Ok(plan)
+    }
+
+    /// Check every conversion, so a bad one fails before the output is written
+    fn check(&self, policy: &QuantizationPolicy) -> Result<()> {
+        for (info, output_info) in self.tensor_infos.iter().zip(&self.output_infos) {
+            let tensor_type = output_info.tensor_type;
+            if tensor_type != info.tensor_type
+                && !(info.is_supported()
+                    && output_info.is_well_formed()
+                    && tensor_type.can_quantize())
+            {
+                return Err(GgufError::Unsupported(format!(
+                    "Converting tensor '{}' from {} to {}",
+                    info.name,
+                    info.tensor_type.name(),
+                    tensor_type.name()
+                )));
+            }
+        }
+
+        // The importance matrix must fit every tensor it weights
+        if let Some(imatrix) = &policy.imatrix {
+            let quantized: Vec<TensorInfo> = self
+                .tensor_infos
+                .iter()
+                .zip(&self.output_infos)
+                .filter(|(info, output_info)| weighted(info, output_info))
+                .map(|(info, _)| info.clone())
+                .collect();
+            let mismatched = imatrix.coverage(&quantized).mismatched;
+            if !mismatched.is_empty() {
+                return Err(GgufError::InvalidFormat(format!(
+                    "Importance matrix entries don't fit their tensors: {}",
+                    mismatched.join(", ")
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Convert the tensors read from `reader` and write the output file to `writer`
+    fn write<R: Read + Seek, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: W,
+        policy: &QuantizationPolicy,
+    ) -> Result<RequantizeReport> {
+        let mut writer = GgufWriter::new(writer, &self.metadata, &self.output_infos)?;
+        let mut tensors = Vec::with_capacity(self.tensor_infos.len());
+        for (info, output_info) in self.tensor_infos.iter().zip(&self.output_infos) {
+            let tensor = TensorLoader::load_tensor(reader, info, self.tensor_data_start)?;
+            let tensor_type = output_info.tensor_type;
+            let importance = policy
+                .imatrix
+                .as_ref()
+                .filter(|_| weighted(info, output_info))
+                .and_then(|imatrix| imatrix.entry_for(info))
+                .map(ImatrixEntry::importance);
+            if tensor_type == info.tensor_type {
+                writer.write_tensor(&tensor)?;
+            } else if let Some(importance) = &importance {
+                writer.write_tensor(&tensor.quantize_with_importance(tensor_type, importance)?)?;
+            } else {
+                writer.write_tensor(&tensor.quantize(tensor_type)?)?;
+            }
+            tensors.push(TensorChange {
+                name: info.name.clone(),
+                original_type: info.tensor_type,
+                tensor_type: output_info.tensor_type,
+                original_size: info.byte_size(),
+                size: output_info.byte_size(),
+                used_imatrix: importance.is_some(),
+            });
+        }
+        writer.finish()?;
+
+        Ok(RequantizeReport {
+            tensors,
+            file_type: policy.file_type,
+        })
+    }
+}
+
+/// Check if a tensor is quantized to a new block type, and so weighted by an imatrix
+fn weighted(info: &TensorInfo, output_info: &TensorInfo) -> bool {
+    output_info.tensor_type != info.tensor_type && output_info.tensor_type.is_quantized()
+}
+
+/// Count the layers of a model from its `blk.{N}.` tensor names
+pub fn block_count(tensor_infos: &[TensorInfo]) -> usize {
+    tensor_infos
+        .iter()
+        .filter_map(|info| block_index(&info.name))
+        .max()
+        .map_or(0, |bid| bid + 1)
+}
+
+/// Get the type to use when rows aren't whole blocks of `tensor_type`
+fn fallback_type(tensor_type: TensorType) -> TensorType {
+    match tensor_type {
+        TensorType::Q2K | TensorType::Q3K => TensorType::Q40,
+        TensorType::Q4K => TensorType::Q50,
+        TensorType::Q5K => TensorType::Q51,
+        TensorType::Q6K | TensorType::Q8K => TensorType::Q80,
+        _ => TensorType::F16,
+    }
+}
+
+/// Match `name` against a pattern where `*` matches any run of characters
+fn glob_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let Some(tail) = name.strip_prefix(prefix) else {
+                return false;
+            };
+            (0..=tail.len())
+                .filter(|&i| tail.is_char_boundary(i))
+                .any(|i| glob_match(rest, &tail[i..]))
+        }
+    }
+}
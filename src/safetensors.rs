@@ -0,0 +1,288 @@
+//! Reading and writing safetensors files
+//!
+//! A safetensors file is a little-endian u64 header length, a JSON header mapping tensor
+//! names to their dtype, shape and byte range, then the raw tensor data. Tensors are
+//! described with [`TensorInfo`]s, so they load with [`TensorLoader::load_tensor`]; as
+//! safetensors shapes are row-major, the dims are reversed into ggml's order.
+//! [`SafetensorsWriter`] streams tensors out like [`GgufWriter`] does.
+//!
+//! [`GgufWriter`]: crate::writer::GgufWriter
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::metadata::{GgufError, Result, TensorType};
+use crate::tensors::{Tensor, TensorInfo, TensorLoader};
+
+/// Largest header accepted, as in the reference implementation
+const MAX_HEADER_SIZE: u64 = 100 << 20;
+
+/// Get the tensor type of a safetensors dtype (e.g. `"BF16"`)
+pub fn dtype_tensor_type(dtype: &str) -> Option<TensorType> {
+    match dtype {
+        "F32" => Some(TensorType::F32),
+        "F16" => Some(TensorType::F16),
+        "BF16" => Some(TensorType::BF16),
+        "F64" => Some(TensorType::F64),
+        "I8" => Some(TensorType::I8),
+        "I16" => Some(TensorType::I16),
+        "I32" => Some(TensorType::I32),
+        "I64" => Some(TensorType::I64),
+        _ => None,
+    }
+}
+
+/// Get the safetensors dtype of a tensor type, if it has one
+pub fn tensor_type_dtype(tensor_type: TensorType) -> Option<&'static str> {
+    match tensor_type {
+        TensorType::F32 => Some("F32"),
+        TensorType::F16 => Some("F16"),
+        TensorType::BF16 => Some("BF16"),
+        TensorType::F64 => Some("F64"),
+        TensorType::I8 => Some("I8"),
+        TensorType::I16 => Some("I16"),
+        TensorType::I32 => Some("I32"),
+        TensorType::I64 => Some("I64"),
+        _ => None,
+    }
+}
+
+/// Header of a safetensors file
+#[derive(Debug, Clone, PartialEq)]
+pub struct SafetensorsHeader {
+    /// Free-form string metadata (`__metadata__`)
+    pub metadata: HashMap<String, String>,
+    /// Tensors in data order; offsets are relative to `tensor_data_start`
+    pub tensor_infos: Vec<TensorInfo>,
+    /// Position of the tensor data in the file
+    pub tensor_data_start: u64,
+}
+
+impl SafetensorsHeader {
+    /// Read the header of a safetensors file
+    ///
+    /// # Errors
+    ///
+    /// Returns `GgufError::Unsupported` for dtypes without a GGUF equivalent (e.g. `U8`)
+    /// and `GgufError::InvalidFormat` if the header is malformed or a byte range doesn't
+    /// match its tensor's shape.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut len = [0u8; 8];
+        reader.read_exact(&mut len)?;
+        let len = u64::from_le_bytes(len);
+        if len > MAX_HEADER_SIZE {
+            return Err(GgufError::InvalidFormat(format!(
+                "Safetensors header of {} bytes",
+                len
+            )));
+        }
+        let mut json = vec![0u8; len as usize];
+        reader.read_exact(&mut json)?;
+        let header: serde_json::Map<String, serde_json::Value> = serde_json::from_slice(&json)
+            .map_err(|e| GgufError::InvalidFormat(format!("Invalid safetensors header: {}", e)))?;
+
+        let mut metadata = HashMap::new();
+        let mut tensor_infos = Vec::with_capacity(header.len());
+        for (name, entry) in &header {
+            if name == "__metadata__" {
+                for (key, value) in entry.as_object().into_iter().flatten() {
+                    if let Some(value) = value.as_str() {
+                        metadata.insert(key.clone(), value.to_string());
+                    }
+                }
+                continue;
+            }
+            tensor_infos.push(parse_tensor_info(name, entry)?);
+        }
+        tensor_infos.sort_by_key(|info| info.offset);
+
+        Ok(Self {
+            metadata,
+            tensor_infos,
+            tensor_data_start: 8 + len,
+        })
+    }
+}
+
+fn parse_tensor_info(name: &str, entry: &serde_json::Value) -> Result<TensorInfo> {
+    let invalid = |what: &str| {
+        GgufError::InvalidFormat(format!("Safetensors tensor '{}' has {}", name, what))
+    };
+    let dtype = entry["dtype"].as_str().ok_or_else(|| invalid("no dtype"))?;
+    let tensor_type = dtype_tensor_type(dtype).ok_or_else(|| {
+        GgufError::Unsupported(format!("Safetensors dtype {} of '{}'", dtype, name))
+    })?;
+    let mut dims = entry["shape"]
+        .as_array()
+        .ok_or_else(|| invalid("no shape"))?
+        .iter()
+        .map(|dim| dim.as_u64().ok_or_else(|| invalid("an invalid shape")))
+        .collect::<Result<Vec<u64>>>()?;
+    dims.reverse();
+    let offsets = entry["data_offsets"]
+        .as_array()
+        .and_then(|offsets| match offsets.as_slice() {
+            [begin, end] => Some((begin.as_u64()?, end.as_u64()?)),
+            _ => None,
+        })
+        .ok_or_else(|| invalid("invalid data offsets"))?;
+    // The shape is untrusted: make sure its byte size (and every partial product used to
+    // compute it) fits in a u64
+    dims.iter()
+        .filter(|&&dim| dim > 0)
+        .try_fold(tensor_type.type_size(), |size, &dim| size.checked_mul(dim))
+        .ok_or_else(|| invalid("a shape too large to address"))?;
+
+    let info = TensorInfo {
+        name: name.to_string(),
+        n_dims: dims.len() as u32,
+        dims,
+        tensor_type,
+        offset: offsets.0,
+    };
+    if offsets.1.checked_sub(offsets.0) != Some(info.byte_size()) {
+        return Err(invalid("data offsets that don't match its shape"));
+    }
+    Ok(info)
+}
+
+/// An open safetensors file
+#[derive(Debug)]
+pub struct SafetensorsFile {
+    /// The file header
+    pub header: SafetensorsHeader,
+    reader: BufReader<File>,
+}
+
+impl SafetensorsFile {
+    /// Open a file and read its header
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let header = SafetensorsHeader::read(&mut reader)?;
+        let file_len = reader.seek(SeekFrom::End(0))?;
+        if let Some(info) = header.tensor_infos.iter().find(|info| {
+            header
+                .tensor_data_start
+                .checked_add(info.offset)
+                .and_then(|start| start.checked_add(info.byte_size()))
+                .is_none_or(|end| end > file_len)
+        }) {
+            return Err(GgufError::InvalidFormat(format!(
+                "Data of safetensors tensor '{}' is past the end of the file",
+                info.name
+            )));
+        }
+        Ok(Self { header, reader })
+    }
+
+    /// Get the tensors of the file
+    pub fn tensor_infos(&self) -> &[TensorInfo] {
+        &self.header.tensor_infos
+    }
+
+    /// Load a tensor's data
+    pub fn load_tensor(&mut self, info: &TensorInfo) -> Result<Tensor> {
+        TensorLoader::load_tensor(&mut self.reader, info, self.header.tensor_data_start)
+    }
+}
+
+/// Streams a safetensors file to a writer
+///
+/// The header is written up front, so tensors must be written in the order their infos
+/// were given.
+#[derive(Debug)]
+pub struct SafetensorsWriter<W: Write> {
+    inner: W,
+    tensor_infos: Vec<TensorInfo>,
+    current: usize,
+}
+
+impl<W: Write> SafetensorsWriter<W> {
+    /// Write the header for the given tensors and string metadata
+    ///
+    /// Returns `GgufError::Unsupported` for tensor types without a safetensors dtype
+    /// (e.g. quantized types).
+    pub fn new(
+        mut inner: W,
+        metadata: &HashMap<String, String>,
+        tensor_infos: &[TensorInfo],
+    ) -> Result<Self> {
+        let mut header = serde_json::Map::new();
+        if !metadata.is_empty() {
+            header.insert("__metadata__".to_string(), serde_json::json!(metadata));
+        }
+        let mut offset = 0;
+        for info in tensor_infos {
+            let dtype = tensor_type_dtype(info.tensor_type).ok_or_else(|| {
+                GgufError::Unsupported(format!(
+                    "Tensor '{}' of type {:?} in safetensors",
+                    info.name, info.tensor_type
+                ))
+            })?;
+            let shape: Vec<u64> = info.dims.iter().rev().copied().collect();
+            let end = offset + info.byte_size();
+            header.insert(
+                info.name.clone(),
+                serde_json::json!({"dtype": dtype, "shape": shape, "data_offsets": [offset, end]}),
+            );
+            offset = end;
+        }
+
+        // The data start is aligned to 8 bytes by padding the header with spaces
+        let mut json = serde_json::to_vec(&header)
+            .map_err(|e| GgufError::InvalidFormat(format!("Safetensors header: {}", e)))?;
+        json.resize(json.len().next_multiple_of(8), b' ');
+        inner.write_all(&(json.len() as u64).to_le_bytes())?;
+        inner.write_all(&json)?;
+
+        Ok(Self {
+            inner,
+            tensor_infos: tensor_infos.to_vec(),
+            current: 0,
+        })
+    }
+
+    /// Write a tensor's data, which must be the next tensor expecting data
+    pub fn write_tensor(&mut self, tensor: &Tensor) -> Result<()> {
+        let expected = self.tensor_infos.get(self.current).ok_or_else(|| {
+            GgufError::InvalidFormat(format!(
+                "Unexpected tensor '{}': all tensors were written",
+                tensor.info.name
+            ))
+        })?;
+        if expected.name != tensor.info.name
+            || expected.tensor_type != tensor.info.tensor_type
+            || expected.dims != tensor.info.dims
+            || expected.byte_size() != tensor.data.len() as u64
+        {
+            return Err(GgufError::InvalidFormat(format!(
+                "Expected data for tensor '{}' ({:?} {:?}), got '{}' ({:?} {:?})",
+                expected.name,
+                expected.tensor_type,
+                expected.dims,
+                tensor.info.name,
+                tensor.info.tensor_type,
+                tensor.info.dims
+            )));
+        }
+        self.inner.write_all(&tensor.data)?;
+        self.current += 1;
+        Ok(())
+    }
+
+    /// Finish the file, returning the underlying writer
+    ///
+    /// Returns `GgufError::InvalidFormat` if some tensors weren't written.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(info) = self.tensor_infos.get(self.current) {
+            return Err(GgufError::InvalidFormat(format!(
+                "Missing data for tensor '{}'",
+                info.name
+            )));
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
@@ -0,0 +1,334 @@
+//! Tensor naming schemas per model architecture
+//!
+//! GGUF files name their tensors by convention (e.g. `blk.0.attn_q.weight`), but which
+//! tensors exist and how they are named depends on `general.architecture`. This module
+//! maps each architecture to its tensor names, similar to llama.cpp's `LLM_TENSOR_NAMES`,
+//! and lets callers register schemas for custom architectures.
+
+use std::collections::HashMap;
+
+/// The role of a tensor within a model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TensorKind {
+    TokenEmbd,
+    TokenEmbdNorm,
+    TokenTypes,
+    PosEmbd,
+    OutputNorm,
+    Output,
+    AttnNorm,
+    AttnNorm2,
+    AttnQ,
+    AttnK,
+    AttnV,
+    AttnQkv,
+    AttnOut,
+    AttnQNorm,
+    AttnKNorm,
+    AttnOutNorm,
+    AttnPostNorm,
+    FfnNorm,
+    FfnGate,
+    FfnUp,
+    FfnDown,
+    FfnPostNorm,
+    LayerOutNorm,
+    FfnGateInp,
+    FfnGateExps,
+    FfnUpExps,
+    FfnDownExps,
+    FfnGateShexp,
+    FfnUpShexp,
+    FfnDownShexp,
+    FfnGateInpShexp,
+}
+
+impl TensorKind {
+    /// All tensor kinds
+    pub const ALL: [TensorKind; 31] = [
+        TensorKind::TokenEmbd,
+        TensorKind::TokenEmbdNorm,
+        TensorKind::TokenTypes,
+        TensorKind::PosEmbd,
+        TensorKind::OutputNorm,
+        TensorKind::Output,
+        TensorKind::AttnNorm,
+        TensorKind::AttnNorm2,
+        TensorKind::AttnQ,
+        TensorKind::AttnK,
+        TensorKind::AttnV,
+        TensorKind::AttnQkv,
+        TensorKind::AttnOut,
+        TensorKind::AttnQNorm,
+        TensorKind::AttnKNorm,
+        TensorKind::AttnOutNorm,
+        TensorKind::AttnPostNorm,
+        TensorKind::FfnNorm,
+        TensorKind::FfnGate,
+        TensorKind::FfnUp,
+        TensorKind::FfnDown,
+        TensorKind::FfnPostNorm,
+        TensorKind::LayerOutNorm,
+        TensorKind::FfnGateInp,
+        TensorKind::FfnGateExps,
+        TensorKind::FfnUpExps,
+        TensorKind::FfnDownExps,
+        TensorKind::FfnGateShexp,
+        TensorKind::FfnUpShexp,
+        TensorKind::FfnDownShexp,
+        TensorKind::FfnGateInpShexp,
+    ];
+
+    /// Get the standard GGUF name pattern for this kind, without the `.weight`/`.bias` suffix
+    ///
+    /// Per-layer patterns contain a `{bid}` placeholder for the block index.
+    pub fn default_pattern(&self) -> &'static str {
+        match self {
+            TensorKind::TokenEmbd => "token_embd",
+            TensorKind::TokenEmbdNorm => "token_embd_norm",
+            TensorKind::TokenTypes => "token_types",
+            TensorKind::PosEmbd => "position_embd",
+            TensorKind::OutputNorm => "output_norm",
+            TensorKind::Output => "output",
+            TensorKind::AttnNorm => "blk.{bid}.attn_norm",
+            TensorKind::AttnNorm2 => "blk.{bid}.attn_norm_2",
+            TensorKind::AttnQ => "blk.{bid}.attn_q",
+            TensorKind::AttnK => "blk.{bid}.attn_k",
+            TensorKind::AttnV => "blk.{bid}.attn_v",
+            TensorKind::AttnQkv => "blk.{bid}.attn_qkv",
+            TensorKind::AttnOut => "blk.{bid}.attn_output",
+            TensorKind::AttnQNorm => "blk.{bid}.attn_q_norm",
+            TensorKind::AttnKNorm => "blk.{bid}.attn_k_norm",
+            TensorKind::AttnOutNorm => "blk.{bid}.attn_output_norm",
+            TensorKind::AttnPostNorm => "blk.{bid}.post_attention_norm",
+            TensorKind::FfnNorm => "blk.{bid}.ffn_norm",
+            TensorKind::FfnGate => "blk.{bid}.ffn_gate",
+            TensorKind::FfnUp => "blk.{bid}.ffn_up",
+            TensorKind::FfnDown => "blk.{bid}.ffn_down",
+            TensorKind::FfnPostNorm => "blk.{bid}.post_ffw_norm",
+            TensorKind::LayerOutNorm => "blk.{bid}.layer_output_norm",
+            TensorKind::FfnGateInp => "blk.{bid}.ffn_gate_inp",
+            TensorKind::FfnGateExps => "blk.{bid}.ffn_gate_exps",
+            TensorKind::FfnUpExps => "blk.{bid}.ffn_up_exps",
+            TensorKind::FfnDownExps => "blk.{bid}.ffn_down_exps",
+            TensorKind::FfnGateShexp => "blk.{bid}.ffn_gate_shexp",
+            TensorKind::FfnUpShexp => "blk.{bid}.ffn_up_shexp",
+            TensorKind::FfnDownShexp => "blk.{bid}.ffn_down_shexp",
+            TensorKind::FfnGateInpShexp => "blk.{bid}.ffn_gate_inp_shexp",
+        }
+    }
+}
+
+/// Tensor names used by one architecture
+///
+/// Kinds missing from the schema are treated as absent from the model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TensorSchema {
+    patterns: HashMap<TensorKind, String>,
+}
+
+impl TensorSchema {
+    /// Create a schema containing every kind under its standard name
+    pub fn standard() -> Self {
+        Self::from_kinds(&TensorKind::ALL)
+    }
+
+    /// Create a schema containing only the given kinds, under their standard names
+    pub fn from_kinds(kinds: &[TensorKind]) -> Self {
+        Self {
+            patterns: kinds
+                .iter()
+                .map(|kind| (*kind, kind.default_pattern().to_string()))
+                .collect(),
+        }
+    }
+
+    /// Add a kind or override its name pattern (use `{bid}` for the block index)
+    pub fn with_pattern(mut self, kind: TensorKind, pattern: &str) -> Self {
+        self.patterns.insert(kind, pattern.to_string());
+        self
+    }
+
+    /// Remove a kind from the schema
+    pub fn without(mut self, kind: TensorKind) -> Self {
+        self.patterns.remove(&kind);
+        self
+    }
+
+    /// Check if the schema contains a kind
+    pub fn contains(&self, kind: TensorKind) -> bool {
+        self.patterns.contains_key(&kind)
+    }
+
+    /// Get the full tensor name for a kind, block index and suffix (`"weight"` or `"bias"`)
+    ///
+    /// Returns `None` if the kind is not part of this schema.
+    pub fn tensor_name(&self, kind: TensorKind, bid: usize, suffix: &str) -> Option<String> {
+        self.patterns
+            .get(&kind)
+            .map(|pattern| format!("{}.{}", pattern.replace("{bid}", &bid.to_string()), suffix))
+    }
+
+    /// Get the kind of a tensor name, with the block index for per-layer tensors
+    ///
+    /// The name may have any suffix after the pattern (`.weight`, `.bias`, ...).
+    pub fn kind_of(&self, name: &str) -> Option<(TensorKind, Option<usize>)> {
+        self.patterns.iter().find_map(|(kind, pattern)| {
+            let (stem, _) = name.rsplit_once('.')?;
+            match pattern.split_once("{bid}") {
+                Some((prefix, suffix)) => {
+                    let index = stem.strip_prefix(prefix)?.strip_suffix(suffix)?;
+                    let bid = index.parse().ok()?;
+                    Some((*kind, Some(bid)))
+                }
+                None => (stem == pattern).then_some((*kind, None)),
+            }
+        })
+    }
+
+    /// Get the block index of a per-layer tensor name, e.g. 3 for `blk.3.attn_q.weight`
+    ///
+    /// Names under a block prefix of the schema (the part of a pattern before `{bid}`) are
+    /// assigned to their block even if the schema doesn't know their kind.
+    pub fn block_index(&self, name: &str) -> Option<usize> {
+        self.patterns.values().find_map(|pattern| {
+            let (prefix, _) = pattern.split_once("{bid}")?;
+            let (index, _) = name.strip_prefix(prefix)?.split_once('.')?;
+            index.parse().ok()
+        })
+    }
+}
+
+/// Registry mapping `general.architecture` names to tensor schemas
+#[derive(Debug, Clone)]
+pub struct SchemaRegistry {
+    schemas: HashMap<String, TensorSchema>,
+}
+
+impl SchemaRegistry {
+    /// Create an empty registry
+    pub fn new() -> Self {
+        Self {
+            schemas: HashMap::new(),
+        }
+    }
+
+    /// Create a registry with the built-in architectures
+    pub fn builtin() -> Self {
+        use TensorKind::*;
+
+        const LLAMA: &[TensorKind] = &[
+            TokenEmbd,
+            OutputNorm,
+            Output,
+            AttnNorm,
+            AttnQ,
+            AttnK,
+            AttnV,
+            AttnOut,
+            FfnNorm,
+            FfnGate,
+            FfnUp,
+            FfnDown,
+            FfnGateInp,
+            FfnGateExps,
+            FfnUpExps,
+            FfnDownExps,
+        ];
+        const SHARED_EXPERTS: &[TensorKind] =
+            &[FfnGateShexp, FfnUpShexp, FfnDownShexp, FfnGateInpShexp];
+        const QK_NORM: &[TensorKind] = &[AttnQNorm, AttnKNorm];
+        const GEMMA: &[TensorKind] = &[
+            TokenEmbd,
+            OutputNorm,
+            AttnNorm,
+            AttnQ,
+            AttnK,
+            AttnV,
+            AttnOut,
+            AttnQNorm,
+            AttnKNorm,
+            AttnPostNorm,
+            FfnNorm,
+            FfnGate,
+            FfnUp,
+            FfnDown,
+            FfnPostNorm,
+        ];
+        const FUSED: &[TensorKind] = &[
+            TokenEmbd, OutputNorm, Output, AttnNorm, AttnQkv, AttnOut, FfnNorm, FfnUp, FfnDown,
+        ];
+        const SEPARATE_QKV: &[TensorKind] = &[AttnQ, AttnK, AttnV];
+        const BERT: &[TensorKind] = &[
+            TokenEmbd,
+            TokenEmbdNorm,
+            TokenTypes,
+            PosEmbd,
+            AttnQ,
+            AttnK,
+            AttnV,
+            AttnOut,
+            AttnOutNorm,
+            FfnUp,
+            FfnDown,
+            LayerOutNorm,
+        ];
+
+        let llama = TensorSchema::from_kinds(LLAMA);
+        let qwen2 = TensorSchema::from_kinds(&[LLAMA, SHARED_EXPERTS].concat());
+        let qwen3 = TensorSchema::from_kinds(&[LLAMA, SHARED_EXPERTS, QK_NORM].concat());
+        // Some conversions write the Q/K/V projections separately
+        let fused_or_separate = TensorSchema::from_kinds(&[FUSED, SEPARATE_QKV].concat());
+
+        let mut registry = Self::new();
+        for arch in ["llama", "mistral", "mixtral"] {
+            registry.register(arch, llama.clone());
+        }
+        for arch in ["qwen2", "qwen2moe"] {
+            registry.register(arch, qwen2.clone());
+        }
+        for arch in ["qwen3", "qwen3moe"] {
+            registry.register(arch, qwen3.clone());
+        }
+        for arch in ["gemma2", "gemma3"] {
+            registry.register(arch, TensorSchema::from_kinds(GEMMA));
+        }
+        registry.register("phi3", fused_or_separate.clone());
+        registry.register(
+            "gpt2",
+            TensorSchema::from_kinds(&[FUSED, &[PosEmbd]].concat()),
+        );
+        // Parallel attention/FFN blocks have no separate FFN norm; Falcon 40B normalizes
+        // the FFN input with `attn_norm_2` instead
+        registry.register("phi2", fused_or_separate.without(FfnNorm));
+        registry.register(
+            "falcon",
+            TensorSchema::from_kinds(&[FUSED, &[AttnNorm2]].concat()).without(FfnNorm),
+        );
+        registry.register("bert", TensorSchema::from_kinds(BERT));
+        registry
+    }
+
+    /// Register (or replace) the schema for an architecture
+    pub fn register(&mut self, architecture: &str, schema: TensorSchema) {
+        self.schemas.insert(architecture.to_string(), schema);
+    }
+
+    /// Get the schema registered for an architecture
+    pub fn get(&self, architecture: &str) -> Option<&TensorSchema> {
+        self.schemas.get(architecture)
+    }
+
+    /// Get the schema for an architecture, falling back to the standard schema if unknown
+    pub fn schema_for(&self, architecture: &str) -> TensorSchema {
+        self.get(architecture)
+            .cloned()
+            .unwrap_or_else(TensorSchema::standard)
+    }
+}
+
+impl Default for SchemaRegistry {
+    fn default() -> Self {
+        Self::builtin()
+    }
+}
@@ -0,0 +1,484 @@
+//! Split (sharded) GGUF files
+//!
+//! Large models are distributed as several files named `{prefix}-00001-of-00005.gguf`.
+//! Every shard carries `split.no` (0-based), `split.count` and `split.tensors.count`
+//! (the total over all shards); only the first shard holds the model metadata. A
+//! [`ShardedGguf`] opens all shards and indexes their tensors as if they were one file.
+//!
+//! [`split_model`] and [`merge_model`] write shards and merge them back, streaming tensor
+//! data from file to file without holding whole tensors in memory.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::lazy::{LazyTensor, TensorSource};
+use crate::metadata::{GgufError, GgufHeader, GgufReader, Result, Value, tensor_data_alignment};
+use crate::tensors::{READ_CHUNK_SIZE, Tensor, TensorInfo, TensorLoader};
+use crate::writer::{GgufWriter, check_output, create_output};
+
+/// Metadata key of the 0-based shard index
+pub const SPLIT_NO: &str = "split.no";
+/// Metadata key of the number of shards
+pub const SPLIT_COUNT: &str = "split.count";
+/// Metadata key of the number of tensors over all shards
+pub const SPLIT_TENSORS_COUNT: &str = "split.tensors.count";
+
+/// Split metadata of one shard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SplitInfo {
+    /// 0-based index of the shard
+    pub no: u16,
+    /// Number of shards
+    pub count: u16,
+    /// Number of tensors over all shards
+    pub tensors_count: u64,
+}
+
+impl SplitInfo {
+    /// Read the split keys of a shard's metadata, or `None` if the file isn't split
+    pub fn from_metadata(metadata: &HashMap<String, Value>) -> Result<Option<Self>> {
+        let Some(count) = get_count(metadata, SPLIT_COUNT)? else {
+            return Ok(None);
+        };
+        let no = get_count(metadata, SPLIT_NO)?.unwrap_or(0);
+        let tensors_count = get_count(metadata, SPLIT_TENSORS_COUNT)?.ok_or_else(|| {
+            GgufError::InvalidFormat(format!("Missing '{}' key", SPLIT_TENSORS_COUNT))
+        })?;
+        if count == 0 || count > u16::MAX as u64 || no >= count {
+            return Err(GgufError::InvalidFormat(format!(
+                "Invalid split {} of {}",
+                no, count
+            )));
+        }
+        Ok(Some(Self {
+            no: no as u16,
+            count: count as u16,
+            tensors_count,
+        }))
+    }
+
+    /// Get the metadata entries describing this split
+    pub fn to_metadata(&self) -> [(String, Value); 3] {
+        [
+            (SPLIT_NO.to_string(), Value::Uint16(self.no)),
+            (SPLIT_COUNT.to_string(), Value::Uint16(self.count)),
+            (
+                SPLIT_TENSORS_COUNT.to_string(),
+                Value::Int32(self.tensors_count as i32),
+            ),
+        ]
+    }
+}
+
+/// Get the path of a shard, as llama.cpp names them (`{prefix}-00001-of-00005.gguf`)
+///
+/// `no` is 0-based.
+pub fn split_path<P: AsRef<Path>>(prefix: P, no: u16, count: u16) -> PathBuf {
+    let mut path = prefix.as_ref().as_os_str().to_owned();
+    path.push(format!("-{:05}-of-{:05}.gguf", no as u32 + 1, count));
+    PathBuf::from(path)
+}
+
+/// Parse a shard path into its prefix, 0-based index and shard count
+pub fn parse_split_path<P: AsRef<Path>>(path: P) -> Option<(PathBuf, u16, u16)> {
+    let path = path.as_ref();
+    let stem = path.to_str()?.strip_suffix(".gguf")?;
+    let (rest, count) = stem.rsplit_once("-of-")?;
+    let (prefix, no) = rest.rsplit_once('-')?;
+    if no.len() != 5 || count.len() != 5 {
+        return None;
+    }
+    let no: u16 = no.parse().ok()?;
+    let count: u16 = count.parse().ok()?;
+    if no == 0 || no > count {
+        return None;
+    }
+    Some((PathBuf::from(prefix), no - 1, count))
+}
+
+/// One file of a split model
+#[derive(Debug)]
+pub struct Shard {
+    /// Path of the file
+    pub path: PathBuf,
+    /// Split metadata, `None` for an unsplit file
+    pub split: Option<SplitInfo>,
+    /// Metadata of the file (the model metadata is only in the first shard)
+    pub metadata: HashMap<String, Value>,
+    /// Tensors stored in this file
+    pub tensor_infos: Vec<TensorInfo>,
+    source: Arc<TensorSource>,
+}
+
+impl Shard {
+    /// Open a file and read its metadata and tensor index
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        let header = GgufHeader::parse(&mut reader)?;
+        if !header.is_version_supported() {
+            return Err(GgufError::Unsupported(format!(
+                "GGUF version {}",
+                header.version
+            )));
+        }
+        let metadata = GgufReader::read_metadata(&mut reader, header.n_kv)?;
+        let tensor_infos = TensorLoader::read_tensor_info(&mut reader, header.n_tensors)?;
+        let tensor_data_start =
+            TensorLoader::get_aligned_tensor_data_start(&mut reader, &metadata)?;
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            split: SplitInfo::from_metadata(&metadata)?,
+            metadata,
+            tensor_infos,
+            source: Arc::new(TensorSource::new(reader, tensor_data_start)),
+        })
+    }
+
+    /// Get the source this shard's tensor data is read from
+    pub fn source(&self) -> &Arc<TensorSource> {
+        &self.source
+    }
+}
+
+/// A model stored in one or more GGUF files, read as a single one
+#[derive(Debug)]
+pub struct ShardedGguf {
+    /// Shards in order
+    pub shards: Vec<Shard>,
+    /// Shard and position within it of each tensor
+    index: HashMap<String, (usize, usize)>,
+}
+
+impl ShardedGguf {
+    /// Open a model from any of its shards, or from an unsplit file
+    ///
+    /// The other shards are looked up next to `path` by name.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GgufError::InvalidFormat` if a shard is missing or the split metadata of
+    /// the shards is inconsistent.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let shard = Shard::open(path)?;
+        let Some(split) = shard.split.filter(|split| split.count > 1) else {
+            return Self::from_shards(vec![shard]);
+        };
+        let (prefix, _, _) = parse_split_path(path).ok_or_else(|| {
+            GgufError::InvalidFormat(format!(
+                "{} is shard {} of {}, but isn't named like one",
+                path.display(),
+                split.no + 1,
+                split.count
+            ))
+        })?;
+
+        let mut shards = Vec::with_capacity(split.count as usize);
+        let mut opened = Some(shard);
+        for no in 0..split.count {
+            if no == split.no {
+                shards.extend(opened.take());
+                continue;
+            }
+            let shard_path = split_path(&prefix, no, split.count);
+            if !shard_path.exists() {
+                return Err(GgufError::InvalidFormat(format!(
+                    "Missing shard {}",
+                    shard_path.display()
+                )));
+            }
+            shards.push(Shard::open(shard_path)?);
+        }
+        Self::from_shards(shards)
+    }
+
+    /// Open the given shard files, in order
+    pub fn open_paths<P: AsRef<Path>>(paths: &[P]) -> Result<Self> {
+        let shards = paths.iter().map(Shard::open).collect::<Result<Vec<_>>>()?;
+        Self::from_shards(shards)
+    }
+
+    /// Validate the split metadata of the shards and index their tensors
+    pub fn from_shards(shards: Vec<Shard>) -> Result<Self> {
+        let Some(first) = shards.first() else {
+            return Err(GgufError::InvalidFormat("No shards given".to_string()));
+        };
+        let count = shards.len();
+        let tensors_count: u64 = shards.iter().map(|s| s.tensor_infos.len() as u64).sum();
+        let expected = first.split.map_or((1, tensors_count), |split| {
+            (split.count as usize, split.tensors_count)
+        });
+        if expected != (count, tensors_count) {
+            return Err(GgufError::InvalidFormat(format!(
+                "Expected {} shards with {} tensors, got {} with {}",
+                expected.0, expected.1, count, tensors_count
+            )));
+        }
+
+        let mut index = HashMap::with_capacity(tensors_count as usize);
+        for (no, shard) in shards.iter().enumerate() {
+            let split = shard.split.unwrap_or(SplitInfo {
+                no: 0,
+                count: 1,
+                tensors_count,
+            });
+            if split.no as usize != no
+                || split.count as usize != count
+                || split.tensors_count != tensors_count
+            {
+                return Err(GgufError::InvalidFormat(format!(
+                    "{} has split {} of {} ({} tensors), expected {} of {} ({} tensors)",
+                    shard.path.display(),
+                    split.no + 1,
+                    split.count,
+                    split.tensors_count,
+                    no + 1,
+                    count,
+                    tensors_count
+                )));
+            }
+            for (i, info) in shard.tensor_infos.iter().enumerate() {
+                if index.insert(info.name.clone(), (no, i)).is_some() {
+                    return Err(GgufError::InvalidFormat(format!(
+                        "Tensor '{}' is stored in more than one shard",
+                        info.name
+                    )));
+                }
+            }
+        }
+
+        Ok(Self { shards, index })
+    }
+
+    /// Get the model metadata, which is stored in the first shard
+    pub fn metadata(&self) -> &HashMap<String, Value> {
+        &self.shards[0].metadata
+    }
+
+    /// Get the number of tensors over all shards
+    pub fn tensor_count(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Iterate over the infos of all tensors, shard after shard
+    pub fn tensor_infos(&self) -> impl Iterator<Item = &TensorInfo> {
+        self.shards.iter().flat_map(|shard| &shard.tensor_infos)
+    }
+
+    /// Get a tensor's info and the index of the shard holding it
+    pub fn tensor_info(&self, name: &str) -> Option<(usize, &TensorInfo)> {
+        let &(shard, i) = self.index.get(name)?;
+        Some((shard, &self.shards[shard].tensor_infos[i]))
+    }
+
+    /// Load a tensor from the shard holding it
+    pub fn load_tensor(&self, name: &str) -> Result<Tensor> {
+        let (shard, info) = self.tensor_info(name).ok_or_else(|| {
+            GgufError::InvalidFormat(format!("Tensor '{}' not found in any shard", name))
+        })?;
+        self.shards[shard].source.load(info)
+    }
+
+    /// Load the tensors of all shards
+    ///
+    /// Unlike [`TensorLoader::load_all_tensors`], any error fails the load.
+    pub fn load_all_tensors(&self) -> Result<HashMap<String, Tensor>> {
+        let mut tensors = HashMap::with_capacity(self.tensor_count());
+        for shard in &self.shards {
+            for info in &shard.tensor_infos {
+                tensors.insert(info.name.clone(), shard.source.load(info)?);
+            }
+        }
+        Ok(tensors)
+    }
+
+    /// Create lazy handles for the tensors of all shards
+    pub fn lazy_tensors(&self) -> HashMap<String, LazyTensor> {
+        self.shards
+            .iter()
+            .flat_map(|shard| {
+                shard.tensor_infos.iter().map(|info| {
+                    (
+                        info.name.clone(),
+                        LazyTensor::new(info.clone(), Arc::clone(&shard.source)),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Limit on the contents of each shard written by [`split_model`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitLimit {
+    /// Maximum number of tensors per shard
+    MaxTensors(usize),
+    /// Maximum tensor data size per shard in bytes
+    ///
+    /// A tensor larger than the limit gets a shard of its own.
+    MaxSize(u64),
+}
+
+/// Partition tensors into consecutive ranges, one per shard
+pub fn plan_split(tensor_infos: &[&TensorInfo], limit: SplitLimit) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+    for (i, info) in tensor_infos.iter().enumerate() {
+        let tensor_size = info.byte_size();
+        let full = match limit {
+            SplitLimit::MaxTensors(n) => i - start >= n.max(1),
+            SplitLimit::MaxSize(max) => i > start && size + tensor_size > max,
+        };
+        if full {
+            ranges.push(start..i);
+            start = i;
+            size = 0;
+        }
+        size += tensor_size;
+    }
+    if start < tensor_infos.len() || ranges.is_empty() {
+        ranges.push(start..tensor_infos.len());
+    }
+    ranges
+}
+
+/// Write a model as shards named `{prefix}-0000N-of-0000M.gguf`
+///
+/// The first shard holds the model metadata; every shard holds the `split.*` keys (and
+/// `general.alignment`, if set). Existing `split.*` keys of the input are replaced, so
+/// split models can be resplit, though not over their own shards. Returns the paths
+/// written.
+pub fn split_model<P: AsRef<Path>>(
+    model: &ShardedGguf,
+    prefix: P,
+    limit: SplitLimit,
+) -> Result<Vec<PathBuf>> {
+    let tensor_infos: Vec<&TensorInfo> = model.tensor_infos().collect();
+    let ranges = plan_split(&tensor_infos, limit);
+    let count = u16::try_from(ranges.len())
+        .map_err(|_| GgufError::Unsupported(format!("Splitting into {} shards", ranges.len())))?;
+
+    let model_metadata = without_split_keys(model.metadata());
+    let alignment = tensor_data_alignment(&model_metadata)?;
+    // Check every shard path before writing any, so resplitting in place fails cleanly
+    for no in 0..count {
+        check_output(&split_path(prefix.as_ref(), no, count), &model.paths())?;
+    }
+
+    let mut readers = model.open_readers()?;
+    let mut paths = Vec::with_capacity(ranges.len());
+    for (no, range) in ranges.into_iter().enumerate() {
+        let split = SplitInfo {
+            no: no as u16,
+            count,
+            tensors_count: tensor_infos.len() as u64,
+        };
+        let mut metadata = if no == 0 {
+            model_metadata.clone()
+        } else if model_metadata.contains_key("general.alignment") {
+            HashMap::from([(
+                "general.alignment".to_string(),
+                Value::Uint32(alignment as u32),
+            )])
+        } else {
+            HashMap::new()
+        };
+        metadata.extend(split.to_metadata());
+
+        let path = split_path(prefix.as_ref(), split.no, split.count);
+        let infos: Vec<TensorInfo> = tensor_infos[range].iter().map(|&i| i.clone()).collect();
+        model.write_file(&path, &metadata, &infos, &mut readers)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Merge the shards of a model into a single file without `split.*` keys
+///
+/// `output` must not be one of the shards.
+pub fn merge_model<P: AsRef<Path>>(model: &ShardedGguf, output: P) -> Result<()> {
+    let infos: Vec<TensorInfo> = model.tensor_infos().cloned().collect();
+    let mut readers = model.open_readers()?;
+    model.write_file(
+        output.as_ref(),
+        &without_split_keys(model.metadata()),
+        &infos,
+        &mut readers,
+    )
+}
+
+impl ShardedGguf {
+    /// Get the path of each shard
+    fn paths(&self) -> Vec<&Path> {
+        self.shards
+            .iter()
+            .map(|shard| shard.path.as_path())
+            .collect()
+    }
+
+    /// Open a reader on each shard, for streaming tensor data
+    fn open_readers(&self) -> Result<Vec<BufReader<File>>> {
+        self.shards
+            .iter()
+            .map(|shard| Ok(BufReader::new(File::open(&shard.path)?)))
+            .collect()
+    }
+
+    /// Write the given tensors of this model to a new file, copying their data in chunks
+    fn write_file(
+        &self,
+        path: &Path,
+        metadata: &HashMap<String, Value>,
+        tensor_infos: &[TensorInfo],
+        readers: &mut [BufReader<File>],
+    ) -> Result<()> {
+        let file = BufWriter::new(create_output(path, &self.paths())?);
+        let mut writer = GgufWriter::new(file, metadata, tensor_infos)?;
+        let mut buf = vec![0u8; READ_CHUNK_SIZE];
+        for info in tensor_infos {
+            let (shard, info) = self.tensor_info(&info.name).ok_or_else(|| {
+                GgufError::InvalidFormat(format!("Tensor '{}' not found in any shard", info.name))
+            })?;
+            let reader = &mut readers[shard];
+            let start = self.shards[shard].source.tensor_data_start() + info.offset;
+            reader.seek(SeekFrom::Start(start))?;
+
+            let mut remaining = info.byte_size();
+            while remaining > 0 {
+                let len = remaining.min(buf.len() as u64) as usize;
+                reader.read_exact(&mut buf[..len])?;
+                writer.write_tensor_data(&buf[..len])?;
+                remaining -= len as u64;
+            }
+        }
+        writer.finish()?;
+        Ok(())
+    }
+}
+
+fn without_split_keys(metadata: &HashMap<String, Value>) -> HashMap<String, Value> {
+    metadata
+        .iter()
+        .filter(|(key, _)| ![SPLIT_NO, SPLIT_COUNT, SPLIT_TENSORS_COUNT].contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+/// Read a non-negative integer key of any integer type
+fn get_count(metadata: &HashMap<String, Value>, key: &str) -> Result<Option<u64>> {
+    let Some(value) = metadata.get(key) else {
+        return Ok(None);
+    };
+    value
+        .as_u64()
+        .or_else(|| value.as_i64().and_then(|v| u64::try_from(v).ok()))
+        .map(Some)
+        .ok_or_else(|| GgufError::InvalidFormat(format!("Invalid '{}' value: {:?}", key, value)))
+}
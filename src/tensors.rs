@@ -1,12 +1,20 @@
 //! GGUF tensor loading functionality
 //!
 //! This module provides functionality to read tensor metadata and load tensor data
-//! from GGUF files. Currently focuses on FP16 (half-precision) models without quantization.
+//! from GGUF files. Tensors of any type can be loaded as raw data; F32, F16 and BF16 data and
+//! the common quantization formats can also be converted to f32.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use crate::metadata::{GgufError, Result, TensorType};
+use crate::convert::{convert_bf16_to_f32, convert_f16_to_f32};
+use crate::lazy::{LazyTensor, TensorSource};
+use crate::metadata::{GgufError, Result, TensorType, Value, tensor_data_alignment};
+use crate::quant::{dequantize, quantize, quantize_weighted};
 
 /// Information about a single tensor in the GGUF file
 #[derive(Debug, Clone, PartialEq)]
@@ -29,43 +37,65 @@ impl TensorInfo {
         self.dims.iter().product()
     }
 
+    /// Get the number of elements along the fastest-varying dimension (ggml's `ne[0]`)
+    pub fn row_len(&self) -> u64 {
+        self.dims.first().copied().unwrap_or(1)
+    }
+
+    /// Get the number of rows, i.e. the product of all dimensions but the first
+    pub fn row_count(&self) -> u64 {
+        self.dims.iter().skip(1).product()
+    }
+
+    /// Calculate the size in bytes of one row
+    pub fn row_size(&self) -> u64 {
+        self.row_len() / self.tensor_type.block_size() * self.tensor_type.type_size()
+    }
+
+    /// Get the byte stride of each dimension (ggml's `nb`)
+    ///
+    /// `nb[0]` is the size of a block (an element for unquantized types), `nb[1]` the size
+    /// of a row, and `nb[i] = nb[i - 1] * ne[i - 1]` beyond that.
+    pub fn byte_strides(&self) -> Vec<u64> {
+        let mut strides = Vec::with_capacity(self.dims.len());
+        for (i, _) in self.dims.iter().enumerate() {
+            strides.push(match i {
+                0 => self.tensor_type.type_size(),
+                1 => self.row_size(),
+                _ => strides[i - 1] * self.dims[i - 1],
+            });
+        }
+        strides
+    }
+
     /// Calculate the size in bytes of this tensor's data
+    ///
+    /// Returns 0 if the tensor is not [well formed](Self::is_well_formed).
     pub fn byte_size(&self) -> u64 {
-        let element_count = self.element_count();
-        let element_size = match self.tensor_type {
-            TensorType::F32 => 4,
-            TensorType::F16 => 2,
-            TensorType::I32 => 4,
-            TensorType::I16 => 2,
-            TensorType::I8 => 1,
-            TensorType::F64 => 8,
-            TensorType::I64 => 8,
-            _ => {
-                // For quantized types, we'll need more complex calculations
-                // For now, return 0 to indicate unsupported
-                return 0;
-            }
-        };
-        element_count * element_size
+        if !self.is_well_formed() {
+            return 0;
+        }
+        self.row_size() * self.row_count()
     }
 
-    /// Check if this tensor type is supported for loading
+    /// Check if this tensor's data layout is known, so it can be read as raw bytes
+    ///
+    /// Quantized tensors must have rows made of whole blocks.
+    pub fn is_well_formed(&self) -> bool {
+        self.row_len().is_multiple_of(self.tensor_type.block_size())
+    }
+
+    /// Check if this tensor can be loaded and decoded
+    ///
+    /// This requires a [well-formed](Self::is_well_formed) tensor of a type that
+    /// [`TensorType::can_dequantize`] accepts.
     pub fn is_supported(&self) -> bool {
-        matches!(
-            self.tensor_type,
-            TensorType::F32
-                | TensorType::F16
-                | TensorType::I32
-                | TensorType::I16
-                | TensorType::I8
-                | TensorType::F64
-                | TensorType::I64
-        )
+        self.is_well_formed() && self.tensor_type.can_dequantize()
     }
 }
 
 /// A loaded tensor with its data
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Tensor {
     /// Tensor metadata
     pub info: TensorInfo,
@@ -74,36 +104,29 @@ pub struct Tensor {
 }
 
 impl Tensor {
-    /// Convert the raw bytes to f32 values (assumes F16 or F32 data)
+    /// Convert the raw bytes to f32 values
+    ///
+    /// Integer, F64 and quantized data are converted with [`dequantize`]; types it can't
+    /// decode (see [`TensorType::can_dequantize`]) are `GgufError::Unsupported`.
     pub fn as_f32_vec(&self) -> Result<Vec<f32>> {
         match self.info.tensor_type {
-            TensorType::F32 => {
-                if self.data.len() % 4 != 0 {
-                    return Err(GgufError::InvalidFormat(
-                        "F32 tensor data length not divisible by 4".to_string(),
-                    ));
-                }
-
-                let mut result = Vec::with_capacity(self.data.len() / 4);
-                for chunk in self.data.chunks_exact(4) {
-                    let bytes: [u8; 4] = chunk.try_into().unwrap();
-                    result.push(f32::from_le_bytes(bytes));
-                }
+            TensorType::F32 => Ok(self.as_f32_slice()?.into_owned()),
+            TensorType::F16 => {
+                let half = self.as_f16_slice()?;
+                let mut result = vec![0.0; half.len()];
+                convert_f16_to_f32(&half, &mut result);
                 Ok(result)
             }
-            TensorType::F16 => {
-                if self.data.len() % 2 != 0 {
-                    return Err(GgufError::InvalidFormat(
-                        "F16 tensor data length not divisible by 2".to_string(),
-                    ));
-                }
-
-                let mut result = Vec::with_capacity(self.data.len() / 2);
-                for chunk in self.data.chunks_exact(2) {
-                    let bytes: [u8; 2] = chunk.try_into().unwrap();
-                    let f16_bits = u16::from_le_bytes(bytes);
-                    result.push(f16_to_f32(f16_bits));
-                }
+            TensorType::BF16 => {
+                let half = self.as_bf16_slice()?;
+                let mut result = vec![0.0; half.len()];
+                convert_bf16_to_f32(&half, &mut result);
+                Ok(result)
+            }
+            tensor_type if tensor_type.can_dequantize() => {
+                self.check_data_len()?;
+                let mut result = vec![0.0; self.info.element_count() as usize];
+                dequantize(tensor_type, &self.data, &mut result)?;
                 Ok(result)
             }
             _ => Err(GgufError::Unsupported(format!(
@@ -113,6 +136,290 @@ impl Tensor {
         }
     }
 
+    /// Convert to another tensor type, quantizing if it's a block format
+    ///
+    /// The data is first converted to f32, so quantized tensors can be requantized.
+    pub fn quantize(&self, tensor_type: TensorType) -> Result<Tensor> {
+        let info = TensorInfo {
+            tensor_type,
+            ..self.info.clone()
+        };
+        if !info.is_well_formed() {
+            return Err(GgufError::Unsupported(format!(
+                "Tensor '{}' has rows of {} elements, not whole {:?} blocks",
+                info.name,
+                info.row_len(),
+                tensor_type
+            )));
+        }
+
+        let mut data = vec![0u8; info.byte_size() as usize];
+        quantize(tensor_type, &self.as_f32_vec()?, &mut data)?;
+        Ok(Tensor { info, data })
+    }
+
+    /// Convert to another tensor type, weighting the quantization error of each column by
+    /// its importance (see [`quantize_weighted`])
+    ///
+    /// `importance` holds one weight per column (`dims[0]`), shared by all rows, or one
+    /// set of weights per matrix of a 3-D tensor (e.g. per expert), as in an imatrix.
+    pub fn quantize_with_importance(
+        &self,
+        tensor_type: TensorType,
+        importance: &[f32],
+    ) -> Result<Tensor> {
+        let row_len = self.info.row_len() as usize;
+        let rows_per_matrix = self.info.dims.get(1).copied().unwrap_or(1) as usize;
+        let matrix_count = self.info.dims.iter().skip(2).product::<u64>() as usize;
+        if row_len == 0
+            || (importance.len() != row_len && importance.len() != row_len * matrix_count)
+        {
+            return Err(GgufError::InvalidFormat(format!(
+                "Importance of {} values doesn't fit tensor '{}' of shape {:?}",
+                importance.len(),
+                self.info.name,
+                self.info.dims
+            )));
+        }
+
+        let info = TensorInfo {
+            tensor_type,
+            ..self.info.clone()
+        };
+        if !info.is_well_formed() {
+            return Err(GgufError::Unsupported(format!(
+                "Tensor '{}' has rows of {} elements, not whole {:?} blocks",
+                info.name,
+                info.row_len(),
+                tensor_type
+            )));
+        }
+
+        let values = self.as_f32_vec()?;
+        let mut data = vec![0u8; info.byte_size() as usize];
+        let matrix_len = (row_len * rows_per_matrix).max(1);
+        let matrix_size = (info.row_size() as usize * rows_per_matrix).max(1);
+        for ((values, out), importance) in values
+            .chunks(matrix_len)
+            .zip(data.chunks_mut(matrix_size))
+            .zip(importance.chunks(row_len).cycle())
+        {
+            quantize_weighted(tensor_type, values, importance, out)?;
+        }
+        Ok(Tensor { info, data })
+    }
+
+    /// Get the raw bytes of row `row`
+    ///
+    /// Rows run along the fastest-varying dimension `dims[0]`, so e.g. row `i` of
+    /// `token_embd.weight` (`[n_embd, n_vocab]`) is the embedding of token `i`.
+    pub fn row_bytes(&self, row: u64) -> Result<&[u8]> {
+        self.check_data_len()?;
+        if row >= self.info.row_count() {
+            return Err(GgufError::InvalidFormat(format!(
+                "Row {} out of range for tensor '{}' with {} rows",
+                row,
+                self.info.name,
+                self.info.row_count()
+            )));
+        }
+        let row_size = self.info.row_size() as usize;
+        let start = row as usize * row_size;
+        Ok(&self.data[start..start + row_size])
+    }
+
+    /// Get row `row` as f32 values, dequantizing only that row's blocks
+    pub fn row(&self, row: u64) -> Result<Vec<f32>> {
+        let mut result = vec![0.0; self.info.row_len() as usize];
+        dequantize(self.info.tensor_type, self.row_bytes(row)?, &mut result)?;
+        Ok(result)
+    }
+
+    /// Get a single element as f32, dequantizing only the block that contains it
+    ///
+    /// `index` uses ggml's dimension order: `index[0]` is the position along `dims[0]`,
+    /// the fastest-varying dimension, so element `[i0, i1, ...]` is stored at
+    /// `i0 + dims[0] * (i1 + dims[1] * (...))`.
+    pub fn get(&self, index: &[u64]) -> Result<f32> {
+        let info = &self.info;
+        if index.len() != info.dims.len() || index.iter().zip(&info.dims).any(|(i, d)| i >= d) {
+            return Err(GgufError::InvalidFormat(format!(
+                "Index {:?} out of range for tensor '{}' with dims {:?}",
+                index, info.name, info.dims
+            )));
+        }
+
+        let row = index
+            .iter()
+            .zip(&info.dims)
+            .skip(1)
+            .rev()
+            .fold(0, |row, (i, d)| row * d + i);
+        let block_size = info.tensor_type.block_size();
+        let type_size = info.tensor_type.type_size() as usize;
+        let start = (index[0] / block_size) as usize * type_size;
+        let block = &self.row_bytes(row)?[start..start + type_size];
+
+        let mut values = vec![0.0; block_size as usize];
+        dequantize(info.tensor_type, block, &mut values)?;
+        Ok(values[(index[0] % block_size) as usize])
+    }
+
+    /// Copy out the sub-tensor covering `range` along dimension `axis`
+    ///
+    /// The result keeps the tensor type. Slicing quantized tensors along `dims[0]`
+    /// requires the range to fall on block boundaries.
+    pub fn slice(&self, axis: usize, range: Range<u64>) -> Result<Tensor> {
+        self.check_data_len()?;
+        let info = &self.info;
+        let dim = info.dims.get(axis).copied().ok_or_else(|| {
+            GgufError::InvalidFormat(format!(
+                "Axis {} out of range for tensor '{}' with {} dimensions",
+                axis,
+                info.name,
+                info.dims.len()
+            ))
+        })?;
+        if range.start > range.end || range.end > dim {
+            return Err(GgufError::InvalidFormat(format!(
+                "Range {:?} out of bounds for dimension {} of size {} in tensor '{}'",
+                range, axis, dim, info.name
+            )));
+        }
+
+        // Byte length of one step along `axis` (a block for quantized rows), and the
+        // number of steps
+        let (unit, steps, start, end) = if axis == 0 {
+            let block_size = info.tensor_type.block_size();
+            if !range.start.is_multiple_of(block_size) || !range.end.is_multiple_of(block_size) {
+                return Err(GgufError::InvalidFormat(format!(
+                    "Range {:?} of tensor '{}' doesn't fall on {:?} block boundaries",
+                    range, info.name, info.tensor_type
+                )));
+            }
+            (
+                info.tensor_type.type_size(),
+                dim / block_size,
+                range.start / block_size,
+                range.end / block_size,
+            )
+        } else {
+            (info.byte_strides()[axis], dim, range.start, range.end)
+        };
+
+        // The data is a sequence of `unit * steps` byte chunks, one per index of the outer
+        // dimensions, each contributing one contiguous run
+        let chunk_len = (unit * steps) as usize;
+        let mut data = Vec::new();
+        if let Some(chunk_count) = self.data.len().checked_div(chunk_len) {
+            data.reserve(chunk_count * ((end - start) * unit) as usize);
+            for chunk in self.data.chunks_exact(chunk_len) {
+                data.extend_from_slice(&chunk[(start * unit) as usize..(end * unit) as usize]);
+            }
+        }
+
+        let mut dims = info.dims.clone();
+        dims[axis] = range.end - range.start;
+        Ok(Tensor {
+            info: TensorInfo {
+                name: format!("{}[{}:{}..{}]", info.name, axis, range.start, range.end),
+                n_dims: info.n_dims,
+                dims,
+                tensor_type: info.tensor_type,
+                offset: info.offset + start * unit,
+            },
+            data,
+        })
+    }
+
+    /// Check that the data length matches the tensor's dims and type
+    fn check_data_len(&self) -> Result<()> {
+        if self.data.len() as u64 != self.info.byte_size() {
+            return Err(GgufError::InvalidFormat(format!(
+                "Tensor '{}' has {} bytes of data, expected {}",
+                self.info.name,
+                self.data.len(),
+                self.info.byte_size()
+            )));
+        }
+        Ok(())
+    }
+
+    /// View F32 data as a slice, borrowing it when it's suitably aligned
+    pub fn as_f32_slice(&self) -> Result<Cow<'_, [f32]>> {
+        self.view(TensorType::F32)
+    }
+
+    /// View F16 data as a slice of raw half precision bits
+    ///
+    /// See [`convert`](crate::convert) for converting them.
+    pub fn as_f16_slice(&self) -> Result<Cow<'_, [u16]>> {
+        self.view(TensorType::F16)
+    }
+
+    /// View BF16 data as a slice of raw bfloat16 bits
+    pub fn as_bf16_slice(&self) -> Result<Cow<'_, [u16]>> {
+        self.view(TensorType::BF16)
+    }
+
+    /// View F64 data as a slice
+    pub fn as_f64_slice(&self) -> Result<Cow<'_, [f64]>> {
+        self.view(TensorType::F64)
+    }
+
+    /// View I8 data as a slice
+    pub fn as_i8_slice(&self) -> Result<Cow<'_, [i8]>> {
+        self.view(TensorType::I8)
+    }
+
+    /// View I16 data as a slice
+    pub fn as_i16_slice(&self) -> Result<Cow<'_, [i16]>> {
+        self.view(TensorType::I16)
+    }
+
+    /// View I32 data as a slice
+    pub fn as_i32_slice(&self) -> Result<Cow<'_, [i32]>> {
+        self.view(TensorType::I32)
+    }
+
+    /// View I64 data as a slice
+    pub fn as_i64_slice(&self) -> Result<Cow<'_, [i64]>> {
+        self.view(TensorType::I64)
+    }
+
+    /// Reinterpret the data as `E`s without copying if possible
+    ///
+    /// Borrows on little-endian targets when the data is aligned for `E`, and decodes a
+    /// copy otherwise.
+    fn view<E: Element>(&self, tensor_type: TensorType) -> Result<Cow<'_, [E]>> {
+        if self.info.tensor_type != tensor_type {
+            return Err(GgufError::Unsupported(format!(
+                "Cannot view tensor type {:?} as {:?}",
+                self.info.tensor_type, tensor_type
+            )));
+        }
+        if !self.data.len().is_multiple_of(E::SIZE) {
+            return Err(GgufError::InvalidFormat(format!(
+                "{:?} tensor data length not divisible by {}",
+                tensor_type,
+                E::SIZE
+            )));
+        }
+
+        #[cfg(target_endian = "little")]
+        {
+            // SAFETY: `Element` is only implemented for primitive numeric types, for which
+            // every bit pattern is valid
+            let (prefix, values, suffix) = unsafe { self.data.align_to::<E>() };
+            if prefix.is_empty() && suffix.is_empty() {
+                return Ok(Cow::Borrowed(values));
+            }
+        }
+        Ok(Cow::Owned(
+            self.data.chunks_exact(E::SIZE).map(E::from_le).collect(),
+        ))
+    }
+
     /// Get the tensor data as a shaped array (returns flattened data and shape)
     pub fn as_shaped_f32(&self) -> Result<(Vec<f32>, Vec<u64>)> {
         let data = self.as_f32_vec()?;
@@ -120,6 +427,107 @@ impl Tensor {
     }
 }
 
+/// Primitive numeric types stored little-endian in tensor data
+trait Element: Copy {
+    const SIZE: usize;
+
+    fn from_le(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_element {
+    ($($t:ty),*) => {
+        $(
+            impl Element for $t {
+                const SIZE: usize = size_of::<$t>();
+
+                fn from_le(bytes: &[u8]) -> Self {
+                    <$t>::from_le_bytes(bytes.try_into().unwrap())
+                }
+            }
+        )*
+    };
+}
+
+impl_element!(i8, i16, i32, i64, u16, f32, f64);
+
+/// Progress of a multi-tensor load, reported to progress callbacks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoadProgress {
+    /// Number of tensors loaded so far
+    pub tensors_done: usize,
+    /// Number of tensors to load
+    pub tensors_total: usize,
+    /// Number of tensor data bytes read so far
+    pub bytes_done: u64,
+    /// Number of tensor data bytes to read
+    pub bytes_total: u64,
+}
+
+impl LoadProgress {
+    /// Get the fraction of bytes read, between 0.0 and 1.0
+    pub fn fraction(&self) -> f64 {
+        if self.bytes_total == 0 {
+            1.0
+        } else {
+            self.bytes_done as f64 / self.bytes_total as f64
+        }
+    }
+}
+
+/// Size of the chunks long tensor reads are split into, so progress and cancellation
+/// are handled within a tensor
+pub(crate) const READ_CHUNK_SIZE: usize = 8 << 20;
+
+/// Token for cancelling a load from another thread
+///
+/// Clones share the same state, so one clone can be handed to the loader while another
+/// is kept by e.g. a UI's cancel button.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Create a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request cancellation of every operation using this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Check if cancellation was requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Return `GgufError::Cancelled` if cancellation was requested
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(GgufError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Common interface of tensors whose data may or may not be loaded yet
+///
+/// The model layer structs are generic over this trait, so they can hold either loaded
+/// [`Tensor`]s or lazy handles.
+pub trait TensorHandle {
+    /// Get the tensor metadata
+    fn info(&self) -> &TensorInfo;
+}
+
+impl TensorHandle for Tensor {
+    fn info(&self) -> &TensorInfo {
+        &self.info
+    }
+}
+
 /// Main interface for loading tensors from GGUF files
 pub struct TensorLoader;
 
@@ -208,9 +616,11 @@ impl TensorLoader {
         tensor_info: &TensorInfo,
         tensor_data_start: u64,
     ) -> Result<Tensor> {
-        if !tensor_info.is_supported() {
+        if !tensor_info.is_well_formed() {
             return Err(GgufError::Unsupported(format!(
-                "Tensor type {:?} is not supported for loading",
+                "Tensor '{}' has rows of {} elements, not whole {:?} blocks",
+                tensor_info.name,
+                tensor_info.row_len(),
                 tensor_info.tensor_type
             )));
         }
@@ -240,7 +650,7 @@ impl TensorLoader {
     /// Load all tensors from the GGUF file
     ///
     /// Returns a HashMap mapping tensor names to loaded tensors.
-    /// Only loads supported tensor types (FP32, FP16, etc.).
+    /// Only loads [supported](TensorInfo::is_supported) tensors; others are skipped.
     pub fn load_all_tensors<R: Read + Seek>(
         reader: &mut R,
         tensor_infos: &[TensorInfo],
@@ -271,6 +681,90 @@ impl TensorLoader {
         Ok(tensors)
     }
 
+    /// Load all supported tensors, reporting progress and honoring a cancellation token
+    ///
+    /// Tensors are read in chunks; `progress` is called after each chunk and the token is
+    /// checked before each one, so cancellation takes effect within large tensors too.
+    /// Unsupported tensor types are skipped as in [`Self::load_all_tensors`], but read
+    /// errors fail the load. Returns `GgufError::Cancelled` if the token was cancelled.
+    pub fn load_all_tensors_with_progress<R, F>(
+        reader: &mut R,
+        tensor_infos: &[TensorInfo],
+        tensor_data_start: u64,
+        mut progress: F,
+        cancel: &CancellationToken,
+    ) -> Result<HashMap<String, Tensor>>
+    where
+        R: Read + Seek,
+        F: FnMut(LoadProgress),
+    {
+        let supported: Vec<&TensorInfo> = tensor_infos
+            .iter()
+            .filter(|info| {
+                if !info.is_supported() {
+                    eprintln!(
+                        "⚠️  Skipping unsupported tensor '{}' of type {:?}",
+                        info.name, info.tensor_type
+                    );
+                }
+                info.is_supported()
+            })
+            .collect();
+
+        let mut state = LoadProgress {
+            tensors_total: supported.len(),
+            bytes_total: supported.iter().map(|info| info.byte_size()).sum(),
+            ..LoadProgress::default()
+        };
+        progress(state);
+
+        let mut tensors = HashMap::with_capacity(supported.len());
+        for tensor_info in supported {
+            cancel.check()?;
+            reader.seek(SeekFrom::Start(tensor_data_start + tensor_info.offset))?;
+
+            let mut data = vec![0u8; tensor_info.byte_size() as usize];
+            for chunk in data.chunks_mut(READ_CHUNK_SIZE) {
+                cancel.check()?;
+                reader.read_exact(chunk)?;
+                state.bytes_done += chunk.len() as u64;
+                progress(state);
+            }
+
+            state.tensors_done += 1;
+            progress(state);
+            tensors.insert(
+                tensor_info.name.clone(),
+                Tensor {
+                    info: tensor_info.clone(),
+                    data,
+                },
+            );
+        }
+
+        Ok(tensors)
+    }
+
+    /// Create lazy handles for all supported tensors, reading no tensor data
+    ///
+    /// The returned map can be passed to [`ModelBuilder`](crate::model::ModelBuilder)
+    /// like the result of [`TensorLoader::load_all_tensors`].
+    pub fn lazy_tensors(
+        source: &Arc<TensorSource>,
+        tensor_infos: &[TensorInfo],
+    ) -> HashMap<String, LazyTensor> {
+        tensor_infos
+            .iter()
+            .filter(|info| info.is_supported())
+            .map(|info| {
+                (
+                    info.name.clone(),
+                    LazyTensor::new(info.clone(), Arc::clone(source)),
+                )
+            })
+            .collect()
+    }
+
     /// Calculate the starting position of the tensor data section
     ///
     /// This is called after reading the header, metadata, and tensor info blocks.
@@ -278,46 +772,18 @@ impl TensorLoader {
     pub fn get_tensor_data_start<R: Seek>(reader: &mut R) -> Result<u64> {
         Ok(reader.stream_position()?)
     }
-}
 
-/// Convert IEEE 754 half-precision (f16) to single-precision (f32)
-fn f16_to_f32(f16_bits: u16) -> f32 {
-    // Extract components of f16
-    let sign = (f16_bits >> 15) & 0x1;
-    let exponent = (f16_bits >> 10) & 0x1f;
-    let mantissa = f16_bits & 0x3ff;
-
-    // Handle special cases
-    if exponent == 0 {
-        if mantissa == 0 {
-            // Zero
-            return if sign == 1 { -0.0 } else { 0.0 };
-        } else {
-            // Subnormal number
-            let mut value = (mantissa as f32) / 1024.0; // 2^10
-            value *= 2f32.powi(-14); // 2^(1-15)
-            return if sign == 1 { -value } else { value };
-        }
-    } else if exponent == 31 {
-        // Infinity or NaN
-        if mantissa == 0 {
-            return if sign == 1 {
-                f32::NEG_INFINITY
-            } else {
-                f32::INFINITY
-            };
-        } else {
-            return f32::NAN;
-        }
+    /// Calculate the start of the tensor data section, honoring the file's alignment
+    ///
+    /// Like [`Self::get_tensor_data_start`], but rounds the position up to
+    /// `general.alignment` (32 bytes by default), as GGUF writers pad the tensor infos.
+    pub fn get_aligned_tensor_data_start<R: Seek>(
+        reader: &mut R,
+        metadata: &HashMap<String, Value>,
+    ) -> Result<u64> {
+        let alignment = tensor_data_alignment(metadata)?;
+        Ok(reader.stream_position()?.next_multiple_of(alignment))
     }
-
-    // Normal number
-    let f32_exponent = (exponent as i32) - 15 + 127; // Adjust bias from 15 to 127
-    let f32_mantissa = (mantissa as u32) << 13; // Shift mantissa to f32 position
-
-    // Construct f32 bits
-    let f32_bits = ((sign as u32) << 31) | ((f32_exponent as u32) << 23) | f32_mantissa;
-    f32::from_bits(f32_bits)
 }
 
 // Helper functions for reading primitive types
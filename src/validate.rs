@@ -0,0 +1,209 @@
+//! Shape validation of built models
+//!
+//! This module cross-checks the dimensions of every tensor in a [`Model`] against the
+//! values in its [`ModelConfig`]. Shapes use ggml's dimension order, where `dims[0]`
+//! is the fastest-varying (row length) dimension.
+
+use std::fmt;
+
+use crate::model::{
+    AttentionLayer, FeedForward, FeedForwardLayer, Model, ModelConfig, MoeFeedForwardLayer,
+    OutputWeights,
+};
+use crate::tensors::TensorHandle;
+
+/// A tensor (or layer count) whose shape disagrees with the model configuration
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShapeMismatch {
+    /// Name of the mismatched tensor, or `"transformer_blocks"` for the layer count
+    pub tensor: String,
+    /// Shape implied by the configuration
+    pub expected: Vec<u64>,
+    /// Shape found in the model
+    pub actual: Vec<u64>,
+}
+
+impl fmt::Display for ShapeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: expected {:?}, got {:?}",
+            self.tensor, self.expected, self.actual
+        )
+    }
+}
+
+impl<T: TensorHandle> Model<T> {
+    /// Check every tensor's shape against the model configuration
+    ///
+    /// Returns all mismatches found; an empty list means the model is consistent.
+    /// Dimensions the configuration doesn't specify (e.g. vocabulary size, or the
+    /// feed-forward length when absent) are taken from the tensors themselves.
+    pub fn validate_shapes(&self) -> Vec<ShapeMismatch> {
+        let mut checker = ShapeChecker::new(&self.config);
+        let embd = Some(checker.embd);
+
+        if self.transformer_blocks.len() as u64 != self.config.block_count as u64 {
+            checker.mismatches.push(ShapeMismatch {
+                tensor: "transformer_blocks".to_string(),
+                expected: vec![self.config.block_count as u64],
+                actual: vec![self.transformer_blocks.len() as u64],
+            });
+        }
+
+        let token_embeddings = &self.embeddings.token_embeddings;
+        checker.check(token_embeddings, &[embd, None]);
+        let vocab = token_embeddings.info().dims.get(1).copied();
+        checker.check_opt(&self.embeddings.position_embeddings, &[embd, None]);
+        checker.check_opt(&self.embeddings.token_type_embeddings, &[embd, None]);
+        checker.check_opt(&self.embeddings.embedding_norm, &[embd]);
+        checker.check_opt(&self.embeddings.embedding_norm_bias, &[embd]);
+
+        if let OutputWeights::Separate(output) = &self.output_layer.output_weights {
+            checker.check(output, &[embd, vocab]);
+        }
+        checker.check_opt(&self.output_layer.output_bias, &[vocab]);
+        checker.check_opt(&self.output_layer.output_norm, &[embd]);
+        checker.check_opt(&self.output_layer.output_norm_bias, &[embd]);
+
+        for block in &self.transformer_blocks {
+            checker.check_attention(&block.attention);
+            match &block.feed_forward {
+                FeedForward::Dense(ffn) => {
+                    checker.check_feed_forward(ffn, self.config.feed_forward_length)
+                }
+                FeedForward::MoE(moe) => checker.check_moe(moe),
+            }
+        }
+
+        checker.mismatches
+    }
+}
+
+struct ShapeChecker<'a> {
+    config: &'a ModelConfig,
+    embd: u64,
+    mismatches: Vec<ShapeMismatch>,
+}
+
+impl<'a> ShapeChecker<'a> {
+    fn new(config: &'a ModelConfig) -> Self {
+        Self {
+            config,
+            embd: config.embedding_length as u64,
+            mismatches: Vec::new(),
+        }
+    }
+
+    /// Compare a tensor's dims with `expected`, where `None` matches any size
+    fn check<T: TensorHandle>(&mut self, tensor: &T, expected: &[Option<u64>]) {
+        let actual = &tensor.info().dims;
+        let matches = actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| e.is_none_or(|e| e == *a));
+
+        if !matches {
+            self.mismatches.push(ShapeMismatch {
+                tensor: tensor.info().name.clone(),
+                expected: expected
+                    .iter()
+                    .enumerate()
+                    .map(|(i, e)| e.or(actual.get(i).copied()).unwrap_or(0))
+                    .collect(),
+                actual: actual.clone(),
+            });
+        }
+    }
+
+    fn check_opt<T: TensorHandle>(&mut self, tensor: &Option<T>, expected: &[Option<u64>]) {
+        if let Some(tensor) = tensor {
+            self.check(tensor, expected);
+        }
+    }
+
+    fn check_attention<T: TensorHandle>(&mut self, attention: &AttentionLayer<T>) {
+        let embd = Some(self.embd);
+        let head_dim = self.config.head_dim() as u64;
+        let q_dim = self.config.attention_head_count as u64 * head_dim;
+        let kv_dim = self.config.kv_head_count() as u64 * head_dim;
+        let (q, kv, qkv) = (Some(q_dim), Some(kv_dim), Some(q_dim + 2 * kv_dim));
+
+        self.check_opt(&attention.query_weights, &[embd, q]);
+        self.check_opt(&attention.key_weights, &[embd, kv]);
+        self.check_opt(&attention.value_weights, &[embd, kv]);
+        self.check_opt(&attention.qkv_weights, &[embd, qkv]);
+        self.check(&attention.output_weights, &[q, embd]);
+
+        self.check_opt(&attention.query_bias, &[q]);
+        self.check_opt(&attention.key_bias, &[kv]);
+        self.check_opt(&attention.value_bias, &[kv]);
+        self.check_opt(&attention.qkv_bias, &[qkv]);
+        self.check_opt(&attention.output_bias, &[embd]);
+
+        self.check_opt(&attention.query_norm, &[Some(head_dim)]);
+        self.check_opt(&attention.key_norm, &[Some(head_dim)]);
+        self.check_opt(&attention.attention_norm, &[embd]);
+        self.check_opt(&attention.attention_norm_bias, &[embd]);
+        self.check_opt(&attention.attention_norm_2, &[embd]);
+        self.check_opt(&attention.attention_norm_2_bias, &[embd]);
+        self.check_opt(&attention.post_attention_norm, &[embd]);
+        self.check_opt(&attention.output_norm, &[embd]);
+        self.check_opt(&attention.output_norm_bias, &[embd]);
+    }
+
+    fn check_feed_forward<T: TensorHandle>(
+        &mut self,
+        ffn: &FeedForwardLayer<T>,
+        ff_length: Option<u32>,
+    ) {
+        let embd = Some(self.embd);
+        let ff = ff_length.map(|n| n as u64);
+
+        // Phi-3 fuses gate and up into a single `ffn_up` of twice the hidden size
+        let fused_gate_up = ffn.gate_weights.is_none()
+            && ff.is_some_and(|ff| ffn.up_weights.info().dims.get(1) == Some(&(2 * ff)));
+        let up = if fused_gate_up {
+            ff.map(|ff| 2 * ff)
+        } else {
+            ff
+        };
+
+        self.check_opt(&ffn.gate_weights, &[embd, ff]);
+        self.check(&ffn.up_weights, &[embd, up]);
+        self.check(&ffn.down_weights, &[ff, embd]);
+
+        self.check_opt(&ffn.gate_bias, &[ff]);
+        self.check_opt(&ffn.up_bias, &[up]);
+        self.check_opt(&ffn.down_bias, &[embd]);
+
+        self.check_opt(&ffn.ffn_norm, &[embd]);
+        self.check_opt(&ffn.ffn_norm_bias, &[embd]);
+        self.check_opt(&ffn.post_ffn_norm, &[embd]);
+        self.check_opt(&ffn.output_norm, &[embd]);
+        self.check_opt(&ffn.output_norm_bias, &[embd]);
+    }
+
+    fn check_moe<T: TensorHandle>(&mut self, moe: &MoeFeedForwardLayer<T>) {
+        let embd = Some(self.embd);
+        let experts = self.config.experts.as_ref();
+        let n_expert = experts.map(|e| e.expert_count as u64);
+        let ff = experts
+            .and_then(|e| e.expert_feed_forward_length)
+            .or(self.config.feed_forward_length)
+            .map(|n| n as u64);
+
+        self.check(&moe.router_weights, &[embd, n_expert]);
+        self.check_opt(&moe.gate_experts, &[embd, ff, n_expert]);
+        self.check(&moe.up_experts, &[embd, ff, n_expert]);
+        self.check(&moe.down_experts, &[ff, embd, n_expert]);
+        self.check_opt(&moe.ffn_norm, &[embd]);
+        self.check_opt(&moe.shared_expert_gate, &[embd]);
+
+        if let Some(shared) = &moe.shared_expert {
+            let shared_ff = experts.and_then(|e| e.expert_shared_feed_forward_length);
+            self.check_feed_forward(shared, shared_ff);
+        }
+    }
+}
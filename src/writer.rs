@@ -0,0 +1,256 @@
+//! Writing GGUF files
+//!
+//! [`GgufWriter`] writes the header, metadata and tensor infos up front, then streams
+//! tensor data in the order the infos were given. Offsets are assigned when the writer
+//! is created, so tensors never have to be held in memory all at once and large tensors
+//! can be written in chunks.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::metadata::{GGUF_MAGIC, GgufError, Result, Value, tensor_data_alignment};
+use crate::tensors::{Tensor, TensorInfo};
+
+/// GGUF version produced by [`GgufWriter`]
+pub const GGUF_VERSION: u32 = 3;
+
+/// Streams a GGUF file to a writer
+#[derive(Debug)]
+pub struct GgufWriter<W: Write> {
+    inner: W,
+    alignment: u64,
+    tensor_infos: Vec<TensorInfo>,
+    /// Index of the tensor whose data is being written
+    current: usize,
+    /// Bytes of the current tensor written so far
+    written: u64,
+}
+
+impl<W: Write> GgufWriter<W> {
+    /// Write the header, metadata and tensor infos, ready for the tensor data
+    ///
+    /// Metadata is written in key order. Tensor offsets are assigned in the order of
+    /// `tensor_infos` using `general.alignment` (32 bytes by default); the offsets of
+    /// the given infos are ignored.
+    ///
+    /// # Errors
+    ///
+    /// Returns `GgufError::Unsupported` if a tensor's rows aren't made of whole blocks,
+    /// and `GgufError::InvalidFormat` if the alignment is not a power of two.
+    pub fn new(
+        mut inner: W,
+        metadata: &HashMap<String, Value>,
+        tensor_infos: &[TensorInfo],
+    ) -> Result<Self> {
+        let alignment = tensor_data_alignment(metadata)?;
+
+        let mut buf = Vec::new();
+        buf.extend_from_slice(&GGUF_MAGIC.to_le_bytes());
+        buf.extend_from_slice(&GGUF_VERSION.to_le_bytes());
+        buf.extend_from_slice(&(tensor_infos.len() as u64).to_le_bytes());
+        buf.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+
+        let mut keys: Vec<&String> = metadata.keys().collect();
+        keys.sort();
+        for key in keys {
+            let value = &metadata[key];
+            write_string(&mut buf, key);
+            buf.extend_from_slice(&(value.value_type() as u32).to_le_bytes());
+            write_value(&mut buf, value)?;
+        }
+
+        let mut infos = Vec::with_capacity(tensor_infos.len());
+        let mut offset = 0;
+        for info in tensor_infos {
+            if !info.is_well_formed() {
+                return Err(GgufError::Unsupported(format!(
+                    "Tensor '{}' has rows of {} elements, not whole {:?} blocks",
+                    info.name,
+                    info.row_len(),
+                    info.tensor_type
+                )));
+            }
+            let info = TensorInfo {
+                n_dims: info.dims.len() as u32,
+                offset,
+                ..info.clone()
+            };
+            write_string(&mut buf, &info.name);
+            buf.extend_from_slice(&info.n_dims.to_le_bytes());
+            for dim in &info.dims {
+                buf.extend_from_slice(&dim.to_le_bytes());
+            }
+            buf.extend_from_slice(&(info.tensor_type as u32).to_le_bytes());
+            buf.extend_from_slice(&info.offset.to_le_bytes());
+            offset = (offset + info.byte_size()).next_multiple_of(alignment);
+            infos.push(info);
+        }
+
+        buf.resize((buf.len() as u64).next_multiple_of(alignment) as usize, 0);
+        inner.write_all(&buf)?;
+
+        let mut writer = Self {
+            inner,
+            alignment,
+            tensor_infos: infos,
+            current: 0,
+            written: 0,
+        };
+        writer.finish_tensors()?;
+        Ok(writer)
+    }
+
+    /// Get the tensor infos as written, with their assigned offsets
+    pub fn tensor_infos(&self) -> &[TensorInfo] {
+        &self.tensor_infos
+    }
+
+    /// Get the info of the next tensor expecting data, if any
+    pub fn next_tensor(&self) -> Option<&TensorInfo> {
+        self.tensor_infos.get(self.current)
+    }
+
+    /// Write a tensor's data, which must be the next tensor expecting data
+    pub fn write_tensor(&mut self, tensor: &Tensor) -> Result<()> {
+        let expected = self.next_tensor().ok_or_else(|| {
+            GgufError::InvalidFormat(format!(
+                "Unexpected tensor '{}': all tensors were written",
+                tensor.info.name
+            ))
+        })?;
+        if self.written != 0
+            || expected.name != tensor.info.name
+            || expected.tensor_type != tensor.info.tensor_type
+            || expected.dims != tensor.info.dims
+        {
+            return Err(GgufError::InvalidFormat(format!(
+                "Expected data for tensor '{}' ({:?} {:?}), got '{}' ({:?} {:?})",
+                expected.name,
+                expected.tensor_type,
+                expected.dims,
+                tensor.info.name,
+                tensor.info.tensor_type,
+                tensor.info.dims
+            )));
+        }
+        self.write_tensor_data(&tensor.data)
+    }
+
+    /// Write raw tensor data
+    ///
+    /// Data fills the tensors in order and may be given in chunks of any size; padding
+    /// is inserted after each tensor is complete.
+    pub fn write_tensor_data(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let info = self.tensor_infos.get(self.current).ok_or_else(|| {
+                GgufError::InvalidFormat(format!(
+                    "{} bytes of tensor data beyond the last tensor",
+                    data.len()
+                ))
+            })?;
+            let len = ((info.byte_size() - self.written) as usize).min(data.len());
+            self.inner.write_all(&data[..len])?;
+            self.written += len as u64;
+            data = &data[len..];
+            self.finish_tensors()?;
+        }
+        Ok(())
+    }
+
+    /// Finish the file, returning the underlying writer
+    ///
+    /// Returns `GgufError::InvalidFormat` if some tensor data is missing.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(info) = self.next_tensor() {
+            return Err(GgufError::InvalidFormat(format!(
+                "Missing data for tensor '{}' ({} of {} bytes written)",
+                info.name,
+                self.written,
+                info.byte_size()
+            )));
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Pad and move past every complete tensor, including empty ones
+    fn finish_tensors(&mut self) -> Result<()> {
+        while let Some(info) = self.tensor_infos.get(self.current) {
+            let size = info.byte_size();
+            if self.written < size {
+                break;
+            }
+            let padding = size.next_multiple_of(self.alignment) - size;
+            self.inner.write_all(&vec![0u8; padding as usize])?;
+            self.current += 1;
+            self.written = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Create the file at `path` for writing, refusing to overwrite any of `inputs`
+///
+/// Creating a file truncates it, so an output that is also an input would be destroyed
+/// before it is read. See [`check_output`].
+pub(crate) fn create_output(path: &Path, inputs: &[&Path]) -> Result<File> {
+    check_output(path, inputs)?;
+    Ok(File::create(path)?)
+}
+
+/// Check that `path` is none of `inputs`, returning an `InvalidInput` I/O error if it is
+pub(crate) fn check_output(path: &Path, inputs: &[&Path]) -> Result<()> {
+    let Ok(output) = path.canonicalize() else {
+        // A file that doesn't exist yet can't be an input
+        return Ok(());
+    };
+    for input in inputs {
+        if input.canonicalize()? == output {
+            return Err(GgufError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Output '{}' is also an input", path.display()),
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn write_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Serialize a value without its type tag (the inverse of `GgufReader::read_value`)
+fn write_value(buf: &mut Vec<u8>, value: &Value) -> Result<()> {
+    match value {
+        Value::Uint8(v) => buf.push(*v),
+        Value::Int8(v) => buf.push(*v as u8),
+        Value::Uint16(v) => buf.extend_from_slice(&v.to_le_bytes()),
+        Value::Int16(v) => buf.extend_from_slice(&v.to_le_bytes()),
+        Value::Uint32(v) => buf.extend_from_slice(&v.to_le_bytes()),
+        Value::Int32(v) => buf.extend_from_slice(&v.to_le_bytes()),
+        Value::Float32(v) => buf.extend_from_slice(&v.to_le_bytes()),
+        Value::Bool(v) => buf.push(*v as u8),
+        Value::String(s) => write_string(buf, s),
+        Value::Array(element_type, elements) => {
+            buf.extend_from_slice(&(*element_type as u32).to_le_bytes());
+            buf.extend_from_slice(&(elements.len() as u64).to_le_bytes());
+            for element in elements {
+                if element.value_type() != *element_type {
+                    return Err(GgufError::InvalidFormat(format!(
+                        "Array of {:?} contains a {:?} value",
+                        element_type,
+                        element.value_type()
+                    )));
+                }
+                write_value(buf, element)?;
+            }
+        }
+        Value::Uint64(v) => buf.extend_from_slice(&v.to_le_bytes()),
+        Value::Int64(v) => buf.extend_from_slice(&v.to_le_bytes()),
+        Value::Float64(v) => buf.extend_from_slice(&v.to_le_bytes()),
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use gguf_llms::{ModelConfig, Tensor, TensorInfo, TensorType};
+
+pub const EMBD: u64 = 4;
+pub const FF: u64 = 6;
+pub const VOCAB: u64 = 10;
+
+/// Build an F32 tensor whose elements are `0, 1, 2, ...`
+pub fn tensor(name: &str, dims: &[u64]) -> Tensor {
+    let count: u64 = dims.iter().product();
+    let data = (0..count)
+        .flat_map(|i| (i as f32).to_le_bytes())
+        .collect::<Vec<u8>>();
+    Tensor {
+        info: TensorInfo {
+            name: name.to_string(),
+            n_dims: dims.len() as u32,
+            dims: dims.to_vec(),
+            tensor_type: TensorType::F32,
+            offset: 0,
+        },
+        data,
+    }
+}
+
+pub fn config(block_count: u32) -> ModelConfig {
+    ModelConfig {
+        architecture: "llama".to_string(),
+        block_count,
+        context_length: 128,
+        embedding_length: EMBD as u32,
+        feed_forward_length: Some(FF as u32),
+        attention_head_count: 2,
+        attention_head_count_kv: Some(2),
+        attention_key_length: None,
+        layer_norm_epsilon: Some(1e-5),
+        rope_freq_base: None,
+        experts: None,
+    }
+}
+
+pub fn insert(map: &mut HashMap<String, Tensor>, name: &str, dims: &[u64]) {
+    map.insert(name.to_string(), tensor(name, dims));
+}
+
+/// Tensors shared by every test model: embeddings, output head and attention
+pub fn base_tensors(block_count: u32) -> HashMap<String, Tensor> {
+    let mut map = HashMap::new();
+    insert(&mut map, "token_embd.weight", &[EMBD, VOCAB]);
+    insert(&mut map, "output_norm.weight", &[EMBD]);
+    insert(&mut map, "output.weight", &[EMBD, VOCAB]);
+    for i in 0..block_count {
+        for name in ["attn_q", "attn_k", "attn_v", "attn_output"] {
+            insert(
+                &mut map,
+                &format!("blk.{}.{}.weight", i, name),
+                &[EMBD, EMBD],
+            );
+        }
+        insert(&mut map, &format!("blk.{}.attn_norm.weight", i), &[EMBD]);
+        insert(&mut map, &format!("blk.{}.ffn_norm.weight", i), &[EMBD]);
+    }
+    map
+}
+
+pub fn insert_dense_ffn(map: &mut HashMap<String, Tensor>, layer: u32) {
+    insert(map, &format!("blk.{}.ffn_gate.weight", layer), &[EMBD, FF]);
+    insert(map, &format!("blk.{}.ffn_up.weight", layer), &[EMBD, FF]);
+    insert(map, &format!("blk.{}.ffn_down.weight", layer), &[FF, EMBD]);
+}
+
+/// Lay out tensors in a tensor data section, updating their offsets
+///
+/// Tensors are placed in name order with GGUF's default 32-byte alignment.
+pub fn tensor_data_section(tensors: &mut HashMap<String, Tensor>) -> Vec<u8> {
+    let mut names: Vec<String> = tensors.keys().cloned().collect();
+    names.sort();
+
+    let mut section = Vec::new();
+    for name in names {
+        let tensor = tensors.get_mut(&name).unwrap();
+        section.resize(section.len().next_multiple_of(32), 0);
+        tensor.info.offset = section.len() as u64;
+        section.extend_from_slice(&tensor.data);
+    }
+    section
+}
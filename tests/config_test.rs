@@ -0,0 +1,151 @@
+//! Tests for extracting model configuration from GGUF metadata
+
+use std::collections::HashMap;
+
+use gguf_llms::config::RopeScalingType;
+use gguf_llms::{
+    ArchitectureConfig, Value, extract_architecture_config, extract_model_config,
+    extract_rope_config,
+};
+
+fn metadata(entries: &[(&str, Value)]) -> HashMap<String, Value> {
+    entries
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.clone()))
+        .collect()
+}
+
+fn base_entries(arch: &str) -> Vec<(String, Value)> {
+    vec![
+        ("general.architecture".into(), Value::String(arch.into())),
+        (format!("{}.block_count", arch), Value::Uint32(2)),
+        (format!("{}.context_length", arch), Value::Uint32(4096)),
+        (format!("{}.embedding_length", arch), Value::Uint32(256)),
+        (format!("{}.attention.head_count", arch), Value::Uint32(8)),
+    ]
+}
+
+fn with(arch: &str, extra: &[(&str, Value)]) -> HashMap<String, Value> {
+    let mut map: HashMap<String, Value> = base_entries(arch).into_iter().collect();
+    map.extend(metadata(extra));
+    map
+}
+
+#[test]
+fn feed_forward_length_is_optional() {
+    let config = extract_model_config(&with("qwen3moe", &[])).unwrap();
+    assert_eq!(config.feed_forward_length, None);
+    assert_eq!(config.block_count, 2);
+}
+
+#[test]
+fn mixtral_exposes_experts() {
+    let config = extract_architecture_config(&with(
+        "llama",
+        &[
+            ("llama.expert_count", Value::Uint32(8)),
+            ("llama.expert_used_count", Value::Uint32(2)),
+        ],
+    ))
+    .unwrap();
+
+    let experts = config.experts().expect("expert config");
+    assert_eq!(experts.expert_count, 8);
+    assert_eq!(experts.expert_used_count, 2);
+    assert!(matches!(config, ArchitectureConfig::Llama(_)));
+}
+
+#[test]
+fn gemma2_exposes_softcapping_and_sliding_window() {
+    let config = extract_architecture_config(&with(
+        "gemma2",
+        &[
+            ("gemma2.attention.sliding_window", Value::Uint32(4096)),
+            ("gemma2.attn_logit_softcapping", Value::Float32(50.0)),
+            ("gemma2.final_logit_softcapping", Value::Float32(30.0)),
+        ],
+    ))
+    .unwrap();
+
+    let ArchitectureConfig::Gemma(gemma) = config else {
+        panic!("expected gemma config");
+    };
+    assert_eq!(gemma.sliding_window, Some(4096));
+    assert_eq!(gemma.attn_logit_softcapping, Some(50.0));
+    assert_eq!(gemma.final_logit_softcapping, Some(30.0));
+}
+
+#[test]
+fn mamba_does_not_require_attention_keys() {
+    let map = metadata(&[
+        ("general.architecture", Value::String("mamba".into())),
+        ("mamba.block_count", Value::Uint32(24)),
+        ("mamba.context_length", Value::Uint32(1048576)),
+        ("mamba.embedding_length", Value::Uint32(768)),
+        ("mamba.ssm.conv_kernel", Value::Uint32(4)),
+        ("mamba.ssm.inner_size", Value::Uint32(1536)),
+        ("mamba.ssm.state_size", Value::Uint32(16)),
+        ("mamba.ssm.time_step_rank", Value::Uint32(48)),
+    ]);
+
+    let ArchitectureConfig::Mamba(mamba) = extract_architecture_config(&map).unwrap() else {
+        panic!("expected mamba config");
+    };
+    assert_eq!(mamba.ssm_state_size, 16);
+    assert_eq!(mamba.base.attention_head_count, 0);
+}
+
+#[test]
+fn unknown_architecture_is_generic() {
+    let config = extract_architecture_config(&with("starcoder2", &[])).unwrap();
+    assert!(matches!(config, ArchitectureConfig::Generic(_)));
+    assert_eq!(config.architecture(), "starcoder2");
+}
+
+#[test]
+fn yarn_rope_scaling_with_defaults() {
+    let rope = extract_rope_config(&with(
+        "qwen3",
+        &[
+            ("qwen3.rope.freq_base", Value::Float32(1_000_000.0)),
+            ("qwen3.rope.scaling.type", Value::String("yarn".into())),
+            ("qwen3.rope.scaling.factor", Value::Float32(4.0)),
+            (
+                "qwen3.rope.scaling.original_context_length",
+                Value::Uint32(32768),
+            ),
+        ],
+    ))
+    .unwrap();
+
+    let scaling = rope.scaling.expect("rope scaling");
+    assert_eq!(scaling.scaling_type, RopeScalingType::Yarn);
+    assert_eq!(scaling.freq_scale(), 0.25);
+    assert_eq!(scaling.original_context_length, Some(32768));
+    let yarn = scaling.yarn.expect("yarn parameters");
+    assert_eq!(yarn.beta_fast, 32.0);
+    assert_eq!(yarn.beta_slow, 1.0);
+}
+
+#[test]
+fn legacy_scale_linear_is_linear_scaling() {
+    let rope = extract_rope_config(&with(
+        "llama",
+        &[("llama.rope.scale_linear", Value::Float32(2.0))],
+    ))
+    .unwrap();
+
+    let scaling = rope.scaling.expect("rope scaling");
+    assert_eq!(scaling.scaling_type, RopeScalingType::Linear);
+    assert_eq!(scaling.factor, 2.0);
+}
+
+#[test]
+fn unscaled_rope_has_no_scaling() {
+    let rope = extract_rope_config(&with(
+        "llama",
+        &[("llama.rope.scaling.type", Value::String("none".into()))],
+    ))
+    .unwrap();
+    assert!(rope.scaling.is_none());
+}
@@ -0,0 +1,100 @@
+//! Tests for F16/BF16/F32 conversions
+
+use gguf_llms::convert::{
+    bf16_to_f32, convert_bf16_to_f32, convert_f16_to_f32, convert_f32_to_f16, f16_to_f32,
+    f32_to_f16,
+};
+use gguf_llms::{Tensor, TensorInfo, TensorType};
+
+fn all_u16() -> Vec<u16> {
+    (0..=u16::MAX).collect()
+}
+
+/// Exact value of a non-NaN half precision number
+fn f16_reference(bits: u16) -> f64 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f64;
+    sign * match exponent {
+        0 => mantissa * 2f64.powi(-24),
+        31 => f64::INFINITY,
+        _ => (1.0 + mantissa / 1024.0) * 2f64.powi(exponent - 15),
+    }
+}
+
+#[test]
+fn f16_bulk_conversion_is_bit_exact_for_all_inputs() {
+    let src = all_u16();
+    // Odd length exercises the scalar tail after the vector blocks
+    let mut dst = vec![0.0; src.len() - 1];
+    convert_f16_to_f32(&src[1..], &mut dst);
+
+    for (&bits, value) in src[1..].iter().zip(&dst) {
+        let scalar = f16_to_f32(bits);
+        assert_eq!(value.to_bits(), scalar.to_bits(), "input {:#06x}", bits);
+        if scalar.is_nan() {
+            assert_eq!(bits & 0x7c00, 0x7c00);
+        } else {
+            assert_eq!(scalar as f64, f16_reference(bits), "input {:#06x}", bits);
+        }
+    }
+}
+
+#[test]
+fn f32_to_f16_round_trips_and_rounds_to_nearest_even() {
+    for bits in all_u16() {
+        let expected = if f16_to_f32(bits).is_nan() {
+            bits | 0x0200
+        } else {
+            bits
+        };
+        assert_eq!(f32_to_f16(f16_to_f32(bits)), expected);
+    }
+
+    assert_eq!(f32_to_f16(65504.0), 0x7bff);
+    assert_eq!(f32_to_f16(65520.0), 0x7c00);
+    assert_eq!(f32_to_f16(1.0 + 2f32.powi(-11)), 0x3c00);
+    assert_eq!(f32_to_f16(1.0 + 3.0 * 2f32.powi(-11)), 0x3c02);
+    assert_eq!(f32_to_f16(2f32.powi(-25)), 0x0000);
+    assert_eq!(f32_to_f16(-2f32.powi(-25) * 1.5), 0x8001);
+    assert_eq!(f32_to_f16(f32::MIN_POSITIVE / 2.0), 0x0000);
+}
+
+#[test]
+fn f32_to_f16_bulk_conversion_matches_scalar() {
+    let src: Vec<f32> = (0..=u32::MAX).step_by(4099).map(f32::from_bits).collect();
+    let mut dst = vec![0; src.len()];
+    convert_f32_to_f16(&src, &mut dst);
+
+    for (value, half) in src.iter().zip(&dst) {
+        assert_eq!(*half, f32_to_f16(*value), "input {:#010x}", value.to_bits());
+    }
+}
+
+#[test]
+fn bf16_bulk_conversion_is_bit_exact_for_all_inputs() {
+    let src = all_u16();
+    let mut dst = vec![0.0; src.len()];
+    convert_bf16_to_f32(&src, &mut dst);
+
+    for (&bits, value) in src.iter().zip(&dst) {
+        assert_eq!(value.to_bits(), bf16_to_f32(bits).to_bits());
+        assert_eq!(value.to_bits() >> 16, bits as u32);
+    }
+}
+
+#[test]
+fn converts_bf16_tensors() {
+    let tensor = Tensor {
+        info: TensorInfo {
+            name: "bf16.weight".to_string(),
+            n_dims: 1,
+            dims: vec![3],
+            tensor_type: TensorType::BF16,
+            offset: 0,
+        },
+        data: vec![0x80, 0x3f, 0x00, 0xc0, 0x00, 0x3f],
+    };
+    assert_eq!(tensor.info.byte_size(), 6);
+    assert_eq!(tensor.as_f32_vec().unwrap(), vec![1.0, -2.0, 0.5]);
+}
@@ -7,14 +7,15 @@ use std::fs::File;
 use std::io::BufReader;
 
 // Explicitly declare the crate dependency used for testing
-extern crate gguf_rs;
-use gguf_rs::{
+extern crate gguf_llms;
+use gguf_llms::{
     GGUF_MAGIC, GgufHeader, GgufReader, ModelBuilder, TensorLoader, extract_model_config,
 };
 
 const MODEL_PATH: &str = "tests/data/Qwen3-0.6B-F16.gguf";
 
 #[test]
+#[ignore = "needs tests/data/Qwen3-0.6B-F16.gguf"]
 fn load_qwen_model() -> Result<(), Box<dyn std::error::Error>> {
     // Open model file using buffered I/O
     let file = File::open(MODEL_PATH)?;
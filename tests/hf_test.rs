@@ -0,0 +1,448 @@
+//! Tests for converting Hugging Face checkpoints to GGUF and back
+
+mod common;
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use common::tensor;
+use gguf_llms::hf::{
+    config_to_metadata, gguf_tensor_name, hf_tensor_name, llama3_rope_factors, metadata_to_config,
+    permute_qk, unpermute_qk,
+};
+use gguf_llms::safetensors::{SafetensorsFile, SafetensorsWriter};
+use gguf_llms::{
+    GgufError, GgufWriter, ModelBuilder, ShardedGguf, Tensor, TensorKind, TensorType, Value,
+    convert_hf_to_gguf, export_gguf_to_hf, extract_model_config,
+};
+use serde_json::json;
+
+/// Write F32 tensors (given with ggml dims) to a safetensors file
+fn write_safetensors(path: &Path, tensors: &[Tensor]) {
+    let mut header = serde_json::Map::new();
+    let mut offset = 0;
+    for tensor in tensors {
+        let shape: Vec<u64> = tensor.info.dims.iter().rev().copied().collect();
+        let end = offset + tensor.data.len();
+        header.insert(
+            tensor.info.name.clone(),
+            json!({"dtype": "F32", "shape": shape, "data_offsets": [offset, end]}),
+        );
+        offset = end;
+    }
+    header.insert("__metadata__".to_string(), json!({"format": "pt"}));
+    let header = serde_json::to_vec(&header).unwrap();
+
+    let mut file = File::create(path).unwrap();
+    file.write_all(&(header.len() as u64).to_le_bytes())
+        .unwrap();
+    file.write_all(&header).unwrap();
+    for tensor in tensors {
+        file.write_all(&tensor.data).unwrap();
+    }
+}
+
+fn config() -> serde_json::Value {
+    json!({
+        "architectures": ["LlamaForCausalLM"],
+        "hidden_size": 8,
+        "intermediate_size": 6,
+        "max_position_embeddings": 128,
+        "num_attention_heads": 2,
+        "num_hidden_layers": 1,
+        "num_key_value_heads": 1,
+        "rms_norm_eps": 1e-5,
+        "rope_theta": 500000.0,
+        "vocab_size": 10,
+        "torch_dtype": "bfloat16"
+    })
+}
+
+/// A one-layer llama checkpoint, split over two safetensors files
+fn write_checkpoint(dir: &Path, config: &serde_json::Value) {
+    std::fs::write(dir.join("config.json"), config.to_string()).unwrap();
+    write_safetensors(
+        &dir.join("model-00001-of-00002.safetensors"),
+        &[
+            tensor("model.embed_tokens.weight", &[8, 10]),
+            tensor("model.layers.0.input_layernorm.weight", &[8]),
+            tensor("model.layers.0.self_attn.q_proj.weight", &[8, 8]),
+            tensor("model.layers.0.self_attn.k_proj.weight", &[8, 4]),
+            tensor("model.layers.0.self_attn.v_proj.weight", &[8, 4]),
+            tensor("model.layers.0.self_attn.o_proj.weight", &[8, 8]),
+        ],
+    );
+    write_safetensors(
+        &dir.join("model-00002-of-00002.safetensors"),
+        &[
+            tensor("model.layers.0.self_attn.rotary_emb.inv_freq", &[2]),
+            tensor("model.layers.0.post_attention_layernorm.weight", &[8]),
+            tensor("model.layers.0.mlp.gate_proj.weight", &[8, 6]),
+            tensor("model.layers.0.mlp.up_proj.weight", &[8, 6]),
+            tensor("model.layers.0.mlp.down_proj.weight", &[6, 8]),
+            tensor("model.norm.weight", &[8]),
+            tensor("lm_head.weight", &[8, 10]),
+        ],
+    );
+}
+
+#[test]
+fn maps_tensor_names() {
+    for (hf, gguf, kind) in [
+        (
+            "model.embed_tokens.weight",
+            "token_embd.weight",
+            TensorKind::TokenEmbd,
+        ),
+        ("lm_head.weight", "output.weight", TensorKind::Output),
+        (
+            "model.layers.12.self_attn.q_proj.bias",
+            "blk.12.attn_q.bias",
+            TensorKind::AttnQ,
+        ),
+        (
+            "model.layers.3.post_attention_layernorm.weight",
+            "blk.3.ffn_norm.weight",
+            TensorKind::FfnNorm,
+        ),
+        (
+            "model.layers.0.mlp.down_proj.weight",
+            "blk.0.ffn_down.weight",
+            TensorKind::FfnDown,
+        ),
+    ] {
+        assert_eq!(gguf_tensor_name(hf), Some((gguf.to_string(), kind)));
+        assert_eq!(hf_tensor_name(gguf), Some((hf.to_string(), kind)));
+    }
+    assert_eq!(gguf_tensor_name("model.layers.0.mlp.experts.weight"), None);
+    assert_eq!(hf_tensor_name("blk.0.ffn_gate_exps.weight"), None);
+}
+
+#[test]
+fn permutes_qk_rows() {
+    // One head of 4 rows: HF halves (0, 1 | 2, 3) become pairs (0, 2), (1, 3)
+    let q = tensor("q", &[2, 4]);
+    let permuted = permute_qk(&q, 1).unwrap();
+    assert_eq!(
+        permuted.as_f32_vec().unwrap(),
+        [0.0, 1.0, 4.0, 5.0, 2.0, 3.0, 6.0, 7.0]
+    );
+    assert_eq!(unpermute_qk(&permuted, 1).unwrap().data, q.data);
+
+    let bias = tensor("b", &[8]);
+    let permuted = permute_qk(&bias, 2).unwrap();
+    assert_eq!(
+        permuted.as_f32_vec().unwrap(),
+        [0.0, 2.0, 1.0, 3.0, 4.0, 6.0, 5.0, 7.0]
+    );
+    assert_eq!(unpermute_qk(&permuted, 2).unwrap().data, bias.data);
+    assert!(permute_qk(&bias, 3).is_err());
+}
+
+#[test]
+fn converts_config() {
+    let (arch, metadata) = config_to_metadata(&config()).unwrap();
+    assert_eq!(arch.gguf_name(), "llama");
+    assert_eq!(metadata["llama.rope.dimension_count"], Value::Uint32(4));
+    assert_eq!(metadata["llama.vocab_size"], Value::Uint32(10));
+
+    let model_config = extract_model_config(&metadata).unwrap();
+    assert_eq!(model_config.block_count, 1);
+    assert_eq!(model_config.embedding_length, 8);
+    assert_eq!(model_config.feed_forward_length, Some(6));
+    assert_eq!(model_config.attention_head_count_kv, Some(1));
+    assert_eq!(model_config.layer_norm_epsilon, Some(1e-5));
+    assert_eq!(model_config.rope_freq_base, Some(500000.0));
+
+    let mut config = config();
+    config["rope_scaling"] = json!({"type": "linear", "factor": 4.0});
+    let (_, metadata) = config_to_metadata(&config).unwrap();
+    assert_eq!(metadata["llama.rope.scaling.factor"], Value::Float32(4.0));
+
+    config["rope_scaling"] = json!({"type": "dynamic", "factor": 4.0});
+    assert!(matches!(
+        config_to_metadata(&config),
+        Err(GgufError::Unsupported(_))
+    ));
+    config["architectures"] = json!(["GPT2LMHeadModel"]);
+    assert!(config_to_metadata(&config).is_err());
+}
+
+#[test]
+fn computes_llama3_rope_factors() {
+    let mut config = config();
+    assert_eq!(llama3_rope_factors(&config), None);
+    config["rope_scaling"] = json!({
+        "rope_type": "llama3",
+        "factor": 8.0,
+        "low_freq_factor": 1.0,
+        "high_freq_factor": 4.0,
+        "original_max_position_embeddings": 32
+    });
+    config["rope_theta"] = json!(10000.0);
+    // Wavelengths 2π and 200π: the first is below 32 / 4, the second above 32 / 1
+    assert_eq!(llama3_rope_factors(&config), Some(vec![1.0, 8.0]));
+}
+
+#[test]
+fn converts_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    write_checkpoint(dir.path(), &config());
+    let output = dir.path().join("model.gguf");
+    let infos = convert_hf_to_gguf(dir.path(), &output, Some(TensorType::F16)).unwrap();
+    assert_eq!(infos.len(), 12);
+    assert_eq!(infos[0].name, "token_embd.weight");
+    assert_eq!(infos[1].name, "blk.0.attn_k.weight");
+
+    let gguf = ShardedGguf::open(&output).unwrap();
+    assert_eq!(gguf.metadata()["general.file_type"], Value::Uint32(1));
+    let tensors = gguf.load_all_tensors().unwrap();
+    assert_eq!(
+        tensors["blk.0.attn_norm.weight"].info.tensor_type,
+        TensorType::F32
+    );
+    assert_eq!(
+        tensors["blk.0.ffn_down.weight"].info.tensor_type,
+        TensorType::F16
+    );
+    assert_eq!(tensors["blk.0.ffn_down.weight"].info.dims, [6, 8]);
+
+    // Q and K are permuted with their own head counts; V is copied as is
+    let q = tensor("model.layers.0.self_attn.q_proj.weight", &[8, 8]);
+    let expected = permute_qk(&q, 2).unwrap().as_f32_vec().unwrap();
+    assert_eq!(
+        tensors["blk.0.attn_q.weight"].as_f32_vec().unwrap(),
+        expected
+    );
+    let k = tensor("model.layers.0.self_attn.k_proj.weight", &[8, 4]);
+    let expected = permute_qk(&k, 1).unwrap().as_f32_vec().unwrap();
+    assert_eq!(
+        tensors["blk.0.attn_k.weight"].as_f32_vec().unwrap(),
+        expected
+    );
+    let v = tensor("v", &[8, 4]);
+    assert_eq!(
+        tensors["blk.0.attn_v.weight"].as_f32_vec().unwrap(),
+        v.as_f32_vec().unwrap()
+    );
+
+    let config = extract_model_config(gguf.metadata()).unwrap();
+    let model = ModelBuilder::new(tensors, config).build().unwrap();
+    assert_eq!(model.num_layers(), 1);
+    assert!(model.validate_shapes().is_empty());
+}
+
+#[test]
+fn writes_llama3_rope_factors() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = config();
+    config["rope_scaling"] = json!({"rope_type": "llama3", "factor": 8.0});
+    write_checkpoint(dir.path(), &config);
+    let output = dir.path().join("model.gguf");
+    convert_hf_to_gguf(dir.path(), &output, None).unwrap();
+
+    let gguf = ShardedGguf::open(&output).unwrap();
+    assert!(!gguf.metadata().contains_key("general.file_type"));
+    let factors = gguf.load_tensor("rope_freqs.weight").unwrap();
+    assert_eq!(factors.info.dims, [2]);
+    // Weights keep their original type
+    let (_, info) = gguf.tensor_info("blk.0.attn_q.weight").unwrap();
+    assert_eq!(info.tensor_type, TensorType::F32);
+}
+
+#[test]
+fn rejects_unknown_tensors() {
+    let dir = tempfile::tempdir().unwrap();
+    write_checkpoint(dir.path(), &config());
+    write_safetensors(
+        &dir.path().join("model-extra.safetensors"),
+        &[tensor("model.vision_tower.weight", &[4])],
+    );
+    let err = convert_hf_to_gguf(dir.path(), dir.path().join("out.gguf"), None).unwrap_err();
+    assert!(matches!(err, GgufError::Unsupported(_)), "{}", err);
+}
+
+#[test]
+fn reads_only_model_weight_files() {
+    let dir = tempfile::tempdir().unwrap();
+    write_checkpoint(dir.path(), &config());
+    // Mistral checkpoints also ship the weights under their original names
+    write_safetensors(
+        &dir.path().join("consolidated.safetensors"),
+        &[tensor("tok_embeddings.weight", &[8, 10])],
+    );
+    let output = dir.path().join("model.gguf");
+    assert_eq!(
+        convert_hf_to_gguf(dir.path(), &output, None).unwrap().len(),
+        12
+    );
+
+    // With an index, only the files it lists are read
+    write_safetensors(
+        &dir.path().join("model-old.safetensors"),
+        &[tensor("model.vision_tower.weight", &[4])],
+    );
+    let index = json!({
+        "metadata": {"total_size": 0},
+        "weight_map": {
+            "model.embed_tokens.weight": "model-00001-of-00002.safetensors",
+            "lm_head.weight": "model-00002-of-00002.safetensors",
+        },
+    });
+    let index_path = dir.path().join("model.safetensors.index.json");
+    std::fs::write(&index_path, index.to_string()).unwrap();
+    assert_eq!(
+        convert_hf_to_gguf(dir.path(), &output, None).unwrap().len(),
+        12
+    );
+
+    let index = json!({"weight_map": {"lm_head.weight": "../model-00002-of-00002.safetensors"}});
+    std::fs::write(&index_path, index.to_string()).unwrap();
+    let err = convert_hf_to_gguf(dir.path(), &output, None).unwrap_err();
+    assert!(matches!(err, GgufError::InvalidFormat(_)), "{}", err);
+}
+
+#[test]
+fn rejects_overflowing_safetensors_headers() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("bad.safetensors");
+    let headers = [
+        // Shape whose byte size overflows
+        json!({"t": {"dtype": "F32", "shape": [u64::MAX / 2, 4], "data_offsets": [0, 0]}}),
+        // Offsets that overflow once added to the data start
+        json!({"t": {"dtype": "F32", "shape": [1], "data_offsets": [u64::MAX - 2, u64::MAX]}}),
+        json!({"t": {"dtype": "F32", "shape": [0, u64::MAX, 2], "data_offsets": [0, 0]}}),
+    ];
+    for header in headers {
+        let header = header.to_string();
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend_from_slice(header.as_bytes());
+        std::fs::write(&path, file).unwrap();
+        let err = SafetensorsFile::open(&path).unwrap_err();
+        assert!(matches!(err, GgufError::InvalidFormat(_)), "{}", err);
+    }
+}
+
+#[test]
+fn writes_safetensors() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tensors.safetensors");
+    let half = tensor("b", &[3, 2]).quantize(TensorType::BF16).unwrap();
+    let tensors = [tensor("a", &[5]), half];
+    let infos: Vec<_> = tensors.iter().map(|t| t.info.clone()).collect();
+    let metadata = [("format".to_string(), "pt".to_string())].into();
+
+    let mut writer =
+        SafetensorsWriter::new(File::create(&path).unwrap(), &metadata, &infos).unwrap();
+    assert!(writer.write_tensor(&tensors[1]).is_err());
+    writer.write_tensor(&tensors[0]).unwrap();
+    writer.write_tensor(&tensors[1]).unwrap();
+    writer.finish().unwrap();
+
+    let mut file = SafetensorsFile::open(&path).unwrap();
+    assert_eq!(file.header.metadata, metadata);
+    assert_eq!(file.header.tensor_data_start % 8, 0);
+    let read_infos = file.tensor_infos().to_vec();
+    assert_eq!(read_infos[1].dims, [3, 2]);
+    assert_eq!(read_infos[1].tensor_type, TensorType::BF16);
+    for (tensor, info) in tensors.iter().zip(&read_infos) {
+        assert_eq!(file.load_tensor(info).unwrap().data, tensor.data);
+    }
+
+    // Quantized tensors have no safetensors dtype
+    let q8 = tensor("q", &[32]).quantize(TensorType::Q80).unwrap();
+    assert!(SafetensorsWriter::new(Vec::new(), &metadata, &[q8.info]).is_err());
+}
+
+#[test]
+fn generates_config() {
+    let mut config = config();
+    config["rope_scaling"] =
+        json!({"rope_type": "yarn", "factor": 4.0, "original_max_position_embeddings": 32});
+    config["head_dim"] = json!(4);
+    let (_, metadata) = config_to_metadata(&config).unwrap();
+    let (arch, generated) = metadata_to_config(&metadata).unwrap();
+    assert_eq!(arch.hf_name(), "LlamaForCausalLM");
+    for key in [
+        "architectures",
+        "hidden_size",
+        "intermediate_size",
+        "max_position_embeddings",
+        "num_attention_heads",
+        "num_hidden_layers",
+        "num_key_value_heads",
+        "rms_norm_eps",
+        "rope_theta",
+        "vocab_size",
+        "head_dim",
+        "rope_scaling",
+    ] {
+        assert_eq!(generated[key], config[key], "{}", key);
+    }
+    assert_eq!(generated["model_type"], "llama");
+}
+
+#[test]
+fn exports_checkpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    write_checkpoint(dir.path(), &config());
+    let gguf_path = dir.path().join("model.gguf");
+    convert_hf_to_gguf(dir.path(), &gguf_path, None).unwrap();
+
+    let export_dir = dir.path().join("export");
+    let model = ShardedGguf::open(&gguf_path).unwrap();
+    let infos = export_gguf_to_hf(&model, &export_dir, TensorType::F32).unwrap();
+    assert_eq!(infos.len(), 12);
+
+    // Every tensor matches the original checkpoint, including the unpermuted Q and K
+    let mut originals = std::collections::HashMap::new();
+    for name in ["model-00001-of-00002", "model-00002-of-00002"] {
+        let mut file =
+            SafetensorsFile::open(dir.path().join(format!("{}.safetensors", name))).unwrap();
+        for info in file.tensor_infos().to_vec() {
+            originals.insert(info.name.clone(), file.load_tensor(&info).unwrap());
+        }
+    }
+    let mut exported = SafetensorsFile::open(export_dir.join("model.safetensors")).unwrap();
+    for info in exported.tensor_infos().to_vec() {
+        let tensor = exported.load_tensor(&info).unwrap();
+        let original = &originals[&info.name];
+        assert_eq!(tensor.info.dims, original.info.dims, "{}", info.name);
+        assert_eq!(tensor.data, original.data, "{}", info.name);
+    }
+
+    let config: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(export_dir.join("config.json")).unwrap()).unwrap();
+    assert_eq!(config["tie_word_embeddings"], false);
+    assert_eq!(config["torch_dtype"], "float32");
+    assert_eq!(config["vocab_size"], 10);
+
+    // Other types convert on the way out; quantized types can't be written
+    let infos = export_gguf_to_hf(&model, &export_dir, TensorType::BF16).unwrap();
+    assert!(infos.iter().all(|i| i.tensor_type == TensorType::BF16));
+    assert!(export_gguf_to_hf(&model, &export_dir, TensorType::Q80).is_err());
+
+    // Tensors that can't be dequantized are rejected before anything is written
+    let mut grid = tensor("blk.0.ffn_up.weight", &[256, 1]);
+    grid.info.tensor_type = TensorType::Iq2Xxs;
+    grid.data = vec![0; 66];
+    let grid_path = dir.path().join("grid.gguf");
+    let mut writer = GgufWriter::new(
+        File::create(&grid_path).unwrap(),
+        model.metadata(),
+        std::slice::from_ref(&grid.info),
+    )
+    .unwrap();
+    writer.write_tensor(&grid).unwrap();
+    writer.finish().unwrap();
+    let grid_dir = dir.path().join("grid");
+    let err = export_gguf_to_hf(
+        &ShardedGguf::open(&grid_path).unwrap(),
+        &grid_dir,
+        TensorType::F16,
+    )
+    .unwrap_err();
+    assert!(matches!(&err, GgufError::Unsupported(msg) if msg.contains("blk.0.ffn_up.weight")));
+    assert!(!grid_dir.join("model.safetensors").exists());
+}
@@ -0,0 +1,364 @@
+//! Tests for importance matrices and importance-weighted quantization
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+
+use common::tensor;
+use gguf_llms::quant::{dequantize, quantize, quantize_weighted};
+use gguf_llms::requantize::requantize;
+use gguf_llms::{
+    GgufError, GgufWriter, Imatrix, QuantizationPolicy, Tensor, TensorInfo, TensorType, TypeRule,
+    Value, ValueType,
+};
+
+const ROW: usize = 512;
+
+/// Deterministic pseudo-random values with a roughly normal distribution
+fn values(count: usize, seed: u64) -> Vec<f32> {
+    let mut state = seed;
+    let mut uniform = move || {
+        state = state
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (state >> 40) as f32 / (1u64 << 24) as f32
+    };
+    (0..count)
+        .map(|_| (0..4).map(|_| uniform()).sum::<f32>() - 2.0)
+        .collect()
+}
+
+/// Importance concentrated on a few columns, as in real activation statistics
+fn importance() -> Vec<f32> {
+    (0..ROW)
+        .map(|j| if j % 7 == 0 { 50.0 } else { 0.5 })
+        .collect()
+}
+
+fn weighted_error(tensor_type: TensorType, x: &[f32], data: &[u8], importance: &[f32]) -> f32 {
+    let mut y = vec![0.0; x.len()];
+    dequantize(tensor_type, data, &mut y).unwrap();
+    x.iter()
+        .zip(&y)
+        .enumerate()
+        .map(|(i, (a, b))| importance[i % ROW] * (a - b) * (a - b))
+        .sum()
+}
+
+#[test]
+fn importance_reduces_weighted_error() {
+    let x = values(8 * ROW, 7);
+    let importance = importance();
+    for tensor_type in [
+        TensorType::Q40,
+        TensorType::Q41,
+        TensorType::Q50,
+        TensorType::Q51,
+        TensorType::Q2K,
+        TensorType::Q3K,
+        TensorType::Q4K,
+        TensorType::Q5K,
+        TensorType::Q6K,
+    ] {
+        let size = x.len() / tensor_type.block_size() as usize * tensor_type.type_size() as usize;
+        let mut plain = vec![0u8; size];
+        quantize(tensor_type, &x, &mut plain).unwrap();
+        let mut weighted = vec![0u8; size];
+        quantize_weighted(tensor_type, &x, &importance, &mut weighted).unwrap();
+
+        let plain_error = weighted_error(tensor_type, &x, &plain, &importance);
+        let weighted_error = weighted_error(tensor_type, &x, &weighted, &importance);
+        assert!(
+            weighted_error < plain_error,
+            "{:?}: {} >= {}",
+            tensor_type,
+            weighted_error,
+            plain_error
+        );
+    }
+
+    // 8-bit quants ignore the importance
+    let mut plain = vec![0u8; x.len() / 32 * 34];
+    let mut weighted = plain.clone();
+    quantize(TensorType::Q80, &x, &mut plain).unwrap();
+    quantize_weighted(TensorType::Q80, &x, &importance, &mut weighted).unwrap();
+    assert_eq!(plain, weighted);
+    assert!(quantize_weighted(TensorType::Q4K, &x[..100], &importance, &mut weighted).is_err());
+}
+
+#[test]
+fn quantizes_experts_with_their_own_importance() {
+    let mut experts = tensor("blk.0.ffn_up_exps.weight", &[256, 2, 3]);
+    experts.data = values(256 * 6, 3)
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let importance = values(256 * 3, 5)
+        .iter()
+        .map(|v| v.abs())
+        .collect::<Vec<_>>();
+
+    let quantized = experts
+        .quantize_with_importance(TensorType::Q4K, &importance)
+        .unwrap();
+    // Each expert matches quantizing it alone with its slice of the importance
+    let values = experts.as_f32_vec().unwrap();
+    for expert in 0..3 {
+        let mut expected = vec![0u8; 2 * 144];
+        quantize_weighted(
+            TensorType::Q4K,
+            &values[512 * expert..512 * (expert + 1)],
+            &importance[256 * expert..256 * (expert + 1)],
+            &mut expected,
+        )
+        .unwrap();
+        assert_eq!(&quantized.data[288 * expert..288 * (expert + 1)], expected);
+    }
+
+    // A single set of weights is shared by all experts; other lengths are rejected
+    assert!(
+        experts
+            .quantize_with_importance(TensorType::Q4K, &importance[..256])
+            .is_ok()
+    );
+    assert!(
+        experts
+            .quantize_with_importance(TensorType::Q4K, &importance[..512])
+            .is_err()
+    );
+}
+
+fn imatrix_gguf() -> Vec<u8> {
+    let mut sums = tensor("blk.0.ffn_down_exps.weight.in_sum2", &[4, 2]);
+    sums.data = [2.0f32, 4.0, 6.0, 8.0, 1.0, 1.0, 1.0, 1.0]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let mut counts = tensor("blk.0.ffn_down_exps.weight.counts", &[1, 2]);
+    // The second expert never saw any input
+    counts.data = [2.0f32, 0.0].iter().flat_map(|v| v.to_le_bytes()).collect();
+
+    let metadata = HashMap::from([
+        (
+            "general.type".to_string(),
+            Value::String("imatrix".to_string()),
+        ),
+        (
+            "imatrix.datasets".to_string(),
+            Value::Array(
+                ValueType::String,
+                vec![Value::String("wiki.train.raw".to_string())],
+            ),
+        ),
+        ("imatrix.chunk_count".to_string(), Value::Uint32(100)),
+        ("imatrix.chunk_size".to_string(), Value::Uint32(512)),
+    ]);
+    let tensors = [sums, counts];
+    let infos: Vec<TensorInfo> = tensors.iter().map(|t| t.info.clone()).collect();
+    let mut writer = GgufWriter::new(Vec::new(), &metadata, &infos).unwrap();
+    for tensor in &tensors {
+        writer.write_tensor(tensor).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+#[test]
+fn reads_gguf_imatrix() {
+    let imatrix = Imatrix::read_gguf(&mut Cursor::new(imatrix_gguf())).unwrap();
+    assert_eq!(imatrix.datasets, vec!["wiki.train.raw"]);
+    assert_eq!(imatrix.chunk_count, 100);
+    assert_eq!(imatrix.chunk_size, Some(512));
+
+    let entry = &imatrix.entries["blk.0.ffn_down_exps.weight"];
+    assert_eq!((entry.matrix_count(), entry.column_count()), (2, 4));
+    assert_eq!(
+        imatrix.importance("blk.0.ffn_down_exps.weight").unwrap(),
+        vec![1.0, 2.0, 3.0, 4.0, 1.0, 1.0, 1.0, 1.0]
+    );
+    assert!(imatrix.importance("blk.0.ffn_up_exps.weight").is_none());
+}
+
+#[test]
+fn reads_legacy_imatrix() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("imatrix.dat");
+    let mut file = std::fs::File::create(&path).unwrap();
+    let name = "blk.0.attn_q.weight";
+    file.write_all(&1i32.to_le_bytes()).unwrap();
+    file.write_all(&(name.len() as i32).to_le_bytes()).unwrap();
+    file.write_all(name.as_bytes()).unwrap();
+    file.write_all(&4i32.to_le_bytes()).unwrap(); // ncall
+    file.write_all(&2i32.to_le_bytes()).unwrap(); // nval
+    for v in [8.0f32, 2.0] {
+        file.write_all(&v.to_le_bytes()).unwrap();
+    }
+    file.write_all(&10i32.to_le_bytes()).unwrap(); // chunk count
+    file.write_all(&(4i32).to_le_bytes()).unwrap();
+    file.write_all(b"wiki").unwrap();
+    drop(file);
+
+    let imatrix = Imatrix::open(&path).unwrap();
+    assert_eq!(imatrix.importance(name).unwrap(), vec![2.0, 0.5]);
+    assert_eq!(imatrix.chunk_count, 10);
+    assert_eq!(imatrix.datasets, vec!["wiki"]);
+
+    // Files written as GGUF are detected by their magic
+    let gguf_path = dir.path().join("imatrix.gguf");
+    std::fs::write(&gguf_path, imatrix_gguf()).unwrap();
+    assert_eq!(Imatrix::open(&gguf_path).unwrap().entries.len(), 1);
+}
+
+#[test]
+fn requantizes_with_imatrix() {
+    let mut weight = tensor("blk.0.attn_q.weight", &[ROW as u64, 4]);
+    weight.data = values(ROW * 4, 11)
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    let tensors: [Tensor; 2] = [weight, tensor("blk.0.attn_k.weight", &[ROW as u64, 4])];
+    let infos: Vec<TensorInfo> = tensors.iter().map(|t| t.info.clone()).collect();
+    let mut writer = GgufWriter::new(Vec::new(), &HashMap::new(), &infos).unwrap();
+    for tensor in &tensors {
+        writer.write_tensor(tensor).unwrap();
+    }
+    let file = writer.finish().unwrap();
+
+    let mut imatrix = Imatrix::default();
+    imatrix.entries.insert(
+        "blk.0.attn_q.weight".to_string(),
+        gguf_llms::ImatrixEntry {
+            sums: importance(),
+            counts: vec![1.0],
+        },
+    );
+    let policy = QuantizationPolicy::new(TensorType::Q3K).with_imatrix(imatrix);
+    let report = requantize(&mut Cursor::new(file.clone()), Vec::new(), &policy).unwrap();
+    assert!(report.tensors[0].used_imatrix);
+    assert!(!report.tensors[1].used_imatrix);
+
+    // Entries that don't fit the tensor are an error, before anything is written
+    let mut imatrix = (*policy.imatrix.unwrap()).clone();
+    imatrix
+        .entries
+        .get_mut("blk.0.attn_q.weight")
+        .unwrap()
+        .sums
+        .truncate(256);
+    let policy = QuantizationPolicy::new(TensorType::Q3K).with_imatrix(imatrix);
+    let mut output = Vec::new();
+    let err = requantize(&mut Cursor::new(file.clone()), &mut output, &policy).unwrap_err();
+    assert!(matches!(err, GgufError::InvalidFormat(msg) if msg.contains("blk.0.attn_q.weight")));
+    assert!(output.is_empty());
+
+    // They don't matter for tensors that aren't quantized
+    let policy = QuantizationPolicy {
+        rules: vec![TypeRule::new("blk.0.attn_q.weight", TensorType::F32)],
+        ..policy
+    };
+    let report = requantize(&mut Cursor::new(file), Vec::new(), &policy).unwrap();
+    assert!(!report.tensors[0].used_imatrix);
+}
+
+fn entry(sums: &[f32], counts: &[f32]) -> gguf_llms::ImatrixEntry {
+    gguf_llms::ImatrixEntry {
+        sums: sums.to_vec(),
+        counts: counts.to_vec(),
+    }
+}
+
+#[test]
+fn reports_entry_and_layer_stats() {
+    let mut imatrix = Imatrix::default();
+    imatrix.entries.insert(
+        "blk.1.attn_q.weight".to_string(),
+        entry(&[2.0, 6.0, 0.0, 8.0], &[2.0]),
+    );
+    imatrix.entries.insert(
+        "blk.0.ffn_up_exps.weight".to_string(),
+        entry(&[1.0, 3.0, 5.0, 5.0], &[1.0, 0.0]),
+    );
+    imatrix
+        .entries
+        .insert("output.weight".to_string(), entry(&[9.0, 9.0], &[1.0]));
+
+    let stats = imatrix.entry_stats(2);
+    let names: Vec<&str> = stats.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "output.weight",
+            "blk.0.ffn_up_exps.weight",
+            "blk.1.attn_q.weight"
+        ]
+    );
+
+    // Experts that saw no activations are left out
+    let experts = &stats[1];
+    assert_eq!((experts.layer, experts.active_matrices), (Some(0), 1));
+    assert_eq!((experts.sum, experts.mean), (4.0, 2.0));
+    assert_eq!((experts.min, experts.max), (1.0, 3.0));
+
+    let attn_q = &stats[2];
+    assert_eq!(attn_q.sum, 8.0);
+    assert_eq!(
+        (attn_q.mean, attn_q.median, attn_q.std_dev),
+        (2.0, 2.0, 2.5f32.sqrt())
+    );
+    assert_eq!(attn_q.zero_fraction, 0.25);
+    let top: Vec<(usize, f32)> = attn_q
+        .top_columns
+        .iter()
+        .map(|c| (c.column, c.importance))
+        .collect();
+    assert_eq!(top, [(3, 4.0), (1, 3.0)]);
+
+    let layers = imatrix.layer_stats();
+    assert_eq!(layers.len(), 2);
+    assert_eq!(
+        (layers[0].layer, layers[0].sum, layers[0].share),
+        (0, 4.0, 4.0 / 12.0)
+    );
+    assert_eq!(
+        (layers[1].layer, layers[1].sum, layers[1].max_mean),
+        (1, 8.0, 2.0)
+    );
+}
+
+#[test]
+fn matches_entries_to_tensors() {
+    let mut imatrix = Imatrix::default();
+    imatrix
+        .entries
+        .insert("blk.0.attn_q.weight".to_string(), entry(&[1.0; 32], &[1.0]));
+    imatrix.entries.insert(
+        "blk.0.ffn_up_exps.weight".to_string(),
+        entry(&[1.0; 64], &[1.0, 1.0]),
+    );
+    imatrix
+        .entries
+        .insert("blk.0.attn_k.weight".to_string(), entry(&[1.0; 16], &[1.0]));
+    imatrix
+        .entries
+        .insert("blk.9.attn_q.weight".to_string(), entry(&[1.0; 32], &[1.0]));
+
+    let infos = [
+        tensor("token_embd.weight", &[32, 100]).info,
+        tensor("blk.0.attn_norm.weight", &[32]).info,
+        tensor("blk.0.attn_q.weight", &[32, 32]).info,
+        tensor("blk.0.attn_k.weight", &[32, 8]).info,
+        tensor("blk.0.attn_v.weight", &[32, 8]).info,
+        tensor("blk.0.ffn_up_exps.weight", &[32, 64, 2]).info,
+    ];
+    let coverage = imatrix.coverage(&infos);
+    assert_eq!(
+        coverage.matched,
+        ["blk.0.attn_q.weight", "blk.0.ffn_up_exps.weight"]
+    );
+    assert_eq!(coverage.mismatched, ["blk.0.attn_k.weight"]);
+    assert_eq!(coverage.missing, ["blk.0.attn_v.weight"]);
+    assert_eq!(coverage.unused, ["blk.9.attn_q.weight"]);
+
+    assert!(imatrix.entry_for(&infos[2]).is_some());
+    assert!(imatrix.entry_for(&infos[3]).is_none());
+}
@@ -0,0 +1,49 @@
+//! Tests for lazily loaded tensors
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use common::{base_tensors, config, insert_dense_ffn, tensor_data_section};
+use gguf_llms::{LazyTensor, ModelBuilder, TensorInfo, TensorLoader, TensorSource};
+
+#[test]
+fn builds_model_from_lazy_tensors_and_walks_layers() {
+    let mut tensors = base_tensors(2);
+    insert_dense_ffn(&mut tensors, 0);
+    insert_dense_ffn(&mut tensors, 1);
+
+    // Data section preceded by some unrelated bytes
+    let data_start = 100;
+    let mut file = vec![0u8; data_start];
+    file.extend(tensor_data_section(&mut tensors));
+
+    let infos: Vec<TensorInfo> = tensors.values().map(|t| t.info.clone()).collect();
+    let source = Arc::new(TensorSource::new(Cursor::new(file), data_start as u64));
+    let lazy: HashMap<String, LazyTensor> = TensorLoader::lazy_tensors(&source, &infos);
+
+    let model = ModelBuilder::new(lazy, config(2)).build().unwrap();
+    assert!(model.validate_shapes().is_empty());
+
+    let block = model.get_block(1).unwrap();
+    assert!(block.tensors().iter().all(|t| !t.is_loaded()));
+
+    block.prefetch().unwrap();
+    assert!(block.tensors().iter().all(|t| t.is_loaded()));
+    assert!(!model.get_block(0).unwrap().tensors()[0].is_loaded());
+
+    let down = block.feed_forward.tensors()[1].load().unwrap();
+    assert_eq!(down, tensors["blk.1.ffn_down.weight"]);
+
+    block.evict();
+    assert!(block.tensors().iter().all(|t| !t.is_loaded()));
+
+    // Data is read again on demand after eviction
+    let embeddings = &model.embeddings.token_embeddings;
+    assert_eq!(
+        *embeddings.data().unwrap(),
+        tensors["token_embd.weight"].data
+    );
+}
@@ -0,0 +1,443 @@
+//! Tests for organizing flat tensor maps into model structures
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use common::{
+    EMBD, FF, VOCAB, base_tensors, config, insert, insert_dense_ffn, tensor, tensor_data_section,
+};
+use gguf_llms::config::ExpertConfig;
+use gguf_llms::model::{ModelConfig, split_fused_qkv};
+use gguf_llms::{
+    FeedForward, GgufError, LayerSelection, ModelBuilder, OutputWeights, SchemaRegistry, Tensor,
+    TensorInfo, TensorKind, TensorSchema, TensorType,
+};
+
+#[test]
+fn builds_dense_model() {
+    let mut tensors = base_tensors(2);
+    insert_dense_ffn(&mut tensors, 0);
+    insert_dense_ffn(&mut tensors, 1);
+
+    let model = ModelBuilder::new(tensors, config(2)).build().unwrap();
+    assert_eq!(model.num_layers(), 2);
+    assert!(!model.get_block(1).unwrap().feed_forward.is_moe());
+    assert!(model.validate_shapes().is_empty());
+}
+
+#[test]
+fn builds_moe_layers_and_slices_experts() {
+    const EXPERTS: u64 = 3;
+    let mut tensors = base_tensors(2);
+    // A leading dense block followed by an MoE block, as in DeepSeek
+    insert_dense_ffn(&mut tensors, 0);
+    insert(&mut tensors, "blk.1.ffn_gate_inp.weight", &[EMBD, EXPERTS]);
+    insert(
+        &mut tensors,
+        "blk.1.ffn_gate_exps.weight",
+        &[EMBD, FF, EXPERTS],
+    );
+    insert(
+        &mut tensors,
+        "blk.1.ffn_up_exps.weight",
+        &[EMBD, FF, EXPERTS],
+    );
+    insert(
+        &mut tensors,
+        "blk.1.ffn_down_exps.weight",
+        &[FF, EMBD, EXPERTS],
+    );
+    insert(&mut tensors, "blk.1.ffn_up_shexp.weight", &[EMBD, FF]);
+    insert(&mut tensors, "blk.1.ffn_down_shexp.weight", &[FF, EMBD]);
+
+    let mut config = config(2);
+    config.architecture = "qwen2moe".to_string();
+    config.experts = Some(ExpertConfig {
+        expert_count: EXPERTS as u32,
+        expert_used_count: 2,
+        expert_feed_forward_length: Some(FF as u32),
+        expert_shared_feed_forward_length: None,
+    });
+
+    let model = ModelBuilder::new(tensors, config).build().unwrap();
+    assert!(!model.get_block(0).unwrap().feed_forward.is_moe());
+
+    let FeedForward::MoE(moe) = &model.get_block(1).unwrap().feed_forward else {
+        panic!("expected an MoE layer");
+    };
+    assert_eq!(moe.expert_count, 3);
+    assert_eq!(moe.expert_used_count, 2);
+    assert_eq!(moe.expert_dim(), FF);
+    assert!(moe.shared_expert.is_some());
+
+    assert!(model.validate_shapes().is_empty());
+
+    let expert = moe.expert(2).unwrap();
+    assert_eq!(expert.up_weights.info.dims, vec![EMBD, FF]);
+    let values = expert.up_weights.as_f32_vec().unwrap();
+    assert_eq!(values[0], (2 * EMBD * FF) as f32);
+    assert!(moe.expert(3).is_err());
+}
+
+#[test]
+fn moe_tensors_require_expert_count() {
+    let mut tensors = base_tensors(1);
+    insert(&mut tensors, "blk.0.ffn_gate_inp.weight", &[EMBD, 2]);
+    insert(&mut tensors, "blk.0.ffn_up_exps.weight", &[EMBD, FF, 2]);
+    insert(&mut tensors, "blk.0.ffn_down_exps.weight", &[FF, EMBD, 2]);
+
+    assert!(ModelBuilder::new(tensors, config(1)).build().is_err());
+}
+
+#[test]
+fn missing_output_weight_is_tied_to_embeddings() {
+    let mut tensors = base_tensors(1);
+    insert_dense_ffn(&mut tensors, 0);
+    tensors.remove("output.weight");
+
+    let model = ModelBuilder::new(tensors, config(1)).build().unwrap();
+    assert!(model.output_layer.is_tied());
+    assert!(matches!(
+        model.output_layer.output_weights,
+        OutputWeights::TiedToEmbeddings
+    ));
+    assert_eq!(model.output_weights().info.name, "token_embd.weight");
+    assert!(model.output_layer.output_norm.is_some());
+}
+
+#[test]
+fn recognizes_fused_qkv_and_biases() {
+    let mut tensors = base_tensors(1);
+    insert_dense_ffn(&mut tensors, 0);
+    for name in ["attn_q", "attn_k", "attn_v"] {
+        tensors.remove(&format!("blk.0.{}.weight", name));
+    }
+    // 2 query heads and 1 KV head of dimension 2
+    insert(&mut tensors, "blk.0.attn_qkv.weight", &[EMBD, 8]);
+    insert(&mut tensors, "blk.0.attn_qkv.bias", &[8]);
+    insert(&mut tensors, "blk.0.attn_output.bias", &[EMBD]);
+    insert(&mut tensors, "blk.0.ffn_up.bias", &[FF]);
+    insert(&mut tensors, "blk.0.ffn_down.bias", &[EMBD]);
+    insert(&mut tensors, "output_norm.bias", &[EMBD]);
+    insert(&mut tensors, "output.bias", &[VOCAB]);
+
+    let mut config = config(1);
+    config.architecture = "gpt2".to_string();
+    config.attention_head_count_kv = Some(1);
+    let model = ModelBuilder::new(tensors, config.clone()).build().unwrap();
+    let block = model.get_block(0).unwrap();
+
+    let attention = &block.attention;
+    assert!(attention.is_fused());
+    assert!(attention.query_weights.is_none());
+    assert!(attention.output_bias.is_some());
+    assert_eq!(attention.model_dim(), EMBD);
+
+    let [q, k, v] = attention.split_qkv(&config).unwrap().unwrap();
+    assert_eq!(q.info.dims, vec![EMBD, 4]);
+    assert_eq!(k.info.dims, vec![EMBD, 2]);
+    assert_eq!(v.info.dims, vec![EMBD, 2]);
+    assert_eq!(v.as_f32_vec().unwrap()[0], (6 * EMBD) as f32);
+
+    let [_, _, v_bias] = attention.split_qkv_bias(&config).unwrap().unwrap();
+    assert_eq!(v_bias.as_f32_vec().unwrap(), vec![6.0, 7.0]);
+
+    // A zero head dimension from the metadata is an error, not a division by zero
+    let empty = tensor("blk.0.attn_qkv.weight", &[EMBD, 0]);
+    let zero_heads = ModelConfig {
+        attention_key_length: Some(0),
+        ..config.clone()
+    };
+    let err = split_fused_qkv(&empty, &zero_heads).unwrap_err();
+    assert!(matches!(err, GgufError::InvalidFormat(_)));
+
+    let FeedForward::Dense(ffn) = &block.feed_forward else {
+        panic!("expected a dense layer");
+    };
+    assert!(ffn.up_bias.is_some());
+    assert!(ffn.down_bias.is_some());
+    assert!(ffn.gate_bias.is_none());
+
+    assert!(model.output_layer.output_bias.is_some());
+    assert!(model.output_layer.output_norm_bias.is_some());
+    assert!(model.validate_shapes().is_empty());
+}
+
+#[test]
+fn recognizes_bert_embedding_norm() {
+    let mut tensors = HashMap::new();
+    insert(&mut tensors, "token_embd.weight", &[EMBD, VOCAB]);
+    insert(&mut tensors, "token_types.weight", &[EMBD, 2]);
+    insert(&mut tensors, "position_embd.weight", &[EMBD, 128]);
+    insert(&mut tensors, "token_embd_norm.weight", &[EMBD]);
+    insert(&mut tensors, "token_embd_norm.bias", &[EMBD]);
+    for name in ["attn_q", "attn_k", "attn_v", "attn_output"] {
+        insert(
+            &mut tensors,
+            &format!("blk.0.{}.weight", name),
+            &[EMBD, EMBD],
+        );
+        insert(&mut tensors, &format!("blk.0.{}.bias", name), &[EMBD]);
+    }
+    for name in ["attn_output_norm", "layer_output_norm"] {
+        insert(&mut tensors, &format!("blk.0.{}.weight", name), &[EMBD]);
+        insert(&mut tensors, &format!("blk.0.{}.bias", name), &[EMBD]);
+    }
+    insert(&mut tensors, "blk.0.ffn_up.weight", &[EMBD, FF]);
+    insert(&mut tensors, "blk.0.ffn_down.weight", &[FF, EMBD]);
+
+    let mut config = config(1);
+    config.architecture = "bert".to_string();
+    let model = ModelBuilder::new(tensors, config)
+        .strict(true)
+        .build()
+        .unwrap();
+    let embeddings = &model.embeddings;
+    assert!(embeddings.embedding_norm.is_some());
+    assert!(embeddings.embedding_norm_bias.is_some());
+    assert!(embeddings.token_type_embeddings.is_some());
+    assert!(model.output_layer.is_tied());
+    assert!(model.validate_shapes().is_empty());
+}
+
+#[test]
+fn builtin_schemas_cover_architecture_tensors() {
+    let build = |architecture: &str, tensors: HashMap<String, Tensor>| {
+        let mut config = config(1);
+        config.architecture = architecture.to_string();
+        let model = ModelBuilder::new(tensors, config)
+            .strict(true)
+            .build()
+            .unwrap();
+        assert!(model.validate_shapes().is_empty(), "{}", architecture);
+        model
+    };
+    let fuse_qkv = |tensors: &mut HashMap<String, Tensor>| {
+        for name in ["attn_q", "attn_k", "attn_v"] {
+            tensors.remove(&format!("blk.0.{}.weight", name));
+        }
+        insert(tensors, "blk.0.attn_qkv.weight", &[EMBD, 3 * EMBD]);
+    };
+
+    // Gemma 2: tied output and post-attention/post-FFN norms
+    let mut tensors = base_tensors(1);
+    insert_dense_ffn(&mut tensors, 0);
+    tensors.remove("output.weight");
+    insert(&mut tensors, "blk.0.post_attention_norm.weight", &[EMBD]);
+    insert(&mut tensors, "blk.0.post_ffw_norm.weight", &[EMBD]);
+    let model = build("gemma2", tensors);
+    let block = model.get_block(0).unwrap();
+    assert!(block.attention.post_attention_norm.is_some());
+    let FeedForward::Dense(ffn) = &block.feed_forward else {
+        panic!("expected a dense layer");
+    };
+    assert!(ffn.post_ffn_norm.is_some());
+
+    // Falcon 40B: fused QKV and a second input norm instead of `ffn_norm`
+    let mut tensors = base_tensors(1);
+    fuse_qkv(&mut tensors);
+    tensors.remove("blk.0.ffn_norm.weight");
+    insert(&mut tensors, "blk.0.attn_norm.bias", &[EMBD]);
+    insert(&mut tensors, "blk.0.attn_norm_2.weight", &[EMBD]);
+    insert(&mut tensors, "blk.0.attn_norm_2.bias", &[EMBD]);
+    insert(&mut tensors, "blk.0.ffn_up.weight", &[EMBD, FF]);
+    insert(&mut tensors, "blk.0.ffn_down.weight", &[FF, EMBD]);
+    let model = build("falcon", tensors);
+    assert!(
+        model
+            .get_block(0)
+            .unwrap()
+            .attention
+            .attention_norm_2
+            .is_some()
+    );
+
+    // GPT-2: learned position embeddings
+    let mut tensors = base_tensors(1);
+    fuse_qkv(&mut tensors);
+    insert(&mut tensors, "position_embd.weight", &[EMBD, 128]);
+    insert(&mut tensors, "blk.0.ffn_up.weight", &[EMBD, FF]);
+    insert(&mut tensors, "blk.0.ffn_down.weight", &[FF, EMBD]);
+    let model = build("gpt2", tensors);
+    assert!(model.embeddings.position_embeddings.is_some());
+
+    // Phi-3 written with separate Q/K/V and a fused gate/up projection
+    let mut tensors = base_tensors(1);
+    insert(&mut tensors, "blk.0.ffn_up.weight", &[EMBD, 2 * FF]);
+    insert(&mut tensors, "blk.0.ffn_down.weight", &[FF, EMBD]);
+    let model = build("phi3", tensors);
+    assert!(!model.get_block(0).unwrap().attention.is_fused());
+}
+
+#[test]
+fn custom_architecture_schema() {
+    // A custom architecture whose attention output projection is named `attn_o`
+    let mut tensors = base_tensors(1);
+    insert_dense_ffn(&mut tensors, 0);
+    let attn_out = tensors.remove("blk.0.attn_output.weight").unwrap();
+    tensors.insert("blk.0.attn_o.weight".to_string(), attn_out);
+
+    let mut config = config(1);
+    config.architecture = "custom".to_string();
+    assert!(
+        ModelBuilder::new(tensors.clone(), config.clone())
+            .build()
+            .is_err()
+    );
+
+    let mut registry = SchemaRegistry::builtin();
+    registry.register(
+        "custom",
+        TensorSchema::standard().with_pattern(TensorKind::AttnOut, "blk.{bid}.attn_o"),
+    );
+    let model = ModelBuilder::new(tensors, config)
+        .with_registry(&registry)
+        .build()
+        .unwrap();
+    assert_eq!(model.get_block(0).unwrap().attention.model_dim(), EMBD);
+}
+
+#[test]
+fn selects_fragment_tensors_through_schema() {
+    let schema = TensorSchema::standard()
+        .with_pattern(TensorKind::TokenEmbd, "tok_embeddings")
+        .with_pattern(TensorKind::Output, "lm_head")
+        .with_pattern(TensorKind::AttnQ, "layers.{bid}.attention.wq");
+    let names = [
+        "tok_embeddings.weight",
+        "lm_head.weight",
+        "layers.0.attention.wq.weight",
+        "layers.3.attention.wq.weight",
+        "layers.3.feed_forward.extra.weight",
+        "blk.3.ffn_up.weight",
+        "rope_freqs.weight",
+    ];
+    let infos: Vec<TensorInfo> = names.iter().map(|name| tensor(name, &[1]).info).collect();
+
+    assert_eq!(
+        schema.kind_of("layers.3.attention.wq.weight"),
+        Some((TensorKind::AttnQ, Some(3)))
+    );
+    assert_eq!(
+        schema.kind_of("lm_head.bias"),
+        Some((TensorKind::Output, None))
+    );
+    assert_eq!(
+        schema.block_index("layers.3.feed_forward.extra.weight"),
+        Some(3)
+    );
+
+    let selection = LayerSelection::layers(3..4).with_output(true);
+    let selected: Vec<&str> = selection
+        .filter(&schema, &infos)
+        .iter()
+        .map(|info| info.name.as_str())
+        .collect();
+    assert_eq!(
+        selected,
+        vec![
+            "lm_head.weight",
+            "layers.3.attention.wq.weight",
+            "layers.3.feed_forward.extra.weight",
+            "blk.3.ffn_up.weight",
+            "rope_freqs.weight",
+        ]
+    );
+}
+
+#[test]
+fn validates_shapes_against_config() {
+    let mut tensors = base_tensors(2);
+    insert_dense_ffn(&mut tensors, 0);
+    insert_dense_ffn(&mut tensors, 1);
+    // Transposed down projection in the second block
+    insert(&mut tensors, "blk.1.ffn_down.weight", &[EMBD, FF]);
+
+    let model = ModelBuilder::new(tensors, config(2)).build().unwrap();
+    assert_eq!(model.embeddings.embedding_dim(), EMBD);
+    assert_eq!(model.vocab_size(), VOCAB);
+
+    let mismatches = model.validate_shapes();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].tensor, "blk.1.ffn_down.weight");
+    assert_eq!(mismatches[0].expected, vec![FF, EMBD]);
+    assert_eq!(mismatches[0].actual, vec![EMBD, FF]);
+}
+
+#[test]
+fn reports_unused_tensors() {
+    let mut tensors = base_tensors(1);
+    insert_dense_ffn(&mut tensors, 0);
+    insert(&mut tensors, "rope_freqs.weight", &[2]);
+    insert(&mut tensors, "v.blk.0.attn_q.weight", &[EMBD, EMBD]);
+
+    let model = ModelBuilder::new(tensors.clone(), config(1))
+        .build()
+        .unwrap();
+    assert_eq!(
+        model.unused_tensor_names(),
+        vec!["rope_freqs.weight", "v.blk.0.attn_q.weight"]
+    );
+
+    let err = ModelBuilder::new(tensors, config(1))
+        .strict(true)
+        .build()
+        .unwrap_err();
+    assert!(err.to_string().contains("rope_freqs.weight"));
+}
+
+#[test]
+fn builds_fragment_for_layer_range() {
+    let mut tensors = base_tensors(4);
+    for i in 0..4 {
+        insert_dense_ffn(&mut tensors, i);
+    }
+    tensors.remove("output.weight");
+    let data = tensor_data_section(&mut tensors);
+    let infos: Vec<TensorInfo> = tensors.values().map(|t| t.info.clone()).collect();
+    let schema = SchemaRegistry::builtin().schema_for("llama");
+
+    // Last pipeline stage: blocks 2..4 plus the (tied) output head
+    let selection = LayerSelection::layers(2..4).with_output(true);
+    let selected = selection
+        .load_tensors(&schema, &mut Cursor::new(&data), &infos, 0)
+        .unwrap();
+    assert!(selected.contains_key("token_embd.weight"));
+    assert!(!selected.contains_key("blk.1.attn_q.weight"));
+
+    let fragment = ModelBuilder::new(selected, config(4))
+        .build_fragment(&selection)
+        .unwrap();
+    assert_eq!(fragment.layers, 2..4);
+    assert!(fragment.holds_layer(3));
+    assert!(fragment.get_block(1).is_none());
+    assert_eq!(fragment.get_block(2).unwrap().layer_index, 2);
+    assert!(fragment.is_last());
+    assert!(!fragment.is_first());
+    assert_eq!(
+        fragment.output_weights().unwrap().info.name,
+        "token_embd.weight"
+    );
+    assert!(fragment.unused_tensors.is_empty());
+
+    // A tensor that can't be decoded fails the load instead of leaving a gap
+    let mut infos = infos;
+    let q = infos
+        .iter_mut()
+        .find(|info| info.name == "blk.2.attn_q.weight")
+        .unwrap();
+    q.tensor_type = TensorType::Iq2Xxs;
+    let err = selection
+        .load_tensors(&schema, &mut Cursor::new(&data), &infos, 0)
+        .unwrap_err();
+    assert!(matches!(err, GgufError::Unsupported(_)));
+
+    let out_of_range = LayerSelection::layers(2..5);
+    assert!(
+        ModelBuilder::new(HashMap::<String, Tensor>::new(), config(4))
+            .build_fragment(&out_of_range)
+            .is_err()
+    );
+}
@@ -0,0 +1,40 @@
+//! Tests for the `ndarray` conversions
+#![cfg(feature = "ndarray")]
+
+mod common;
+
+use common::tensor;
+use gguf_llms::{Tensor, TensorType};
+use ndarray::{Array2, s};
+
+#[test]
+fn converts_tensors_to_row_major_arrays() {
+    // ggml [n_embd = 4, n_vocab = 3] is row-major [3, 4], one embedding per row
+    let t = tensor("token_embd.weight", &[4, 3]);
+    let array = t.to_ndarray().unwrap();
+    assert_eq!(array.shape(), &[3, 4]);
+    assert_eq!(array[[2, 1]], t.get(&[1, 2]).unwrap());
+    assert_eq!(array.slice(s![1, ..]).to_vec(), t.row(1).unwrap());
+
+    let view = t.as_ndarray().unwrap();
+    assert!(view.is_view());
+    assert_eq!(view.view(), array.view());
+}
+
+#[test]
+fn builds_tensors_from_arrays() {
+    let array = Array2::from_shape_fn((2, 3), |(i, j)| (i * 3 + j) as f32);
+    let t = Tensor::from_ndarray("t", &array, TensorType::F32).unwrap();
+    assert_eq!(t.info.dims, vec![3, 2]);
+    assert_eq!(t.to_ndarray().unwrap(), array.clone().into_dyn());
+
+    // Transposed views are written in logical order
+    let transposed = Tensor::from_ndarray("t", &array.t(), TensorType::F16).unwrap();
+    assert_eq!(transposed.info.dims, vec![2, 3]);
+    assert_eq!(
+        transposed.as_f32_vec().unwrap(),
+        vec![0.0, 3.0, 1.0, 4.0, 2.0, 5.0]
+    );
+
+    assert!(Tensor::from_ndarray("t", &array, TensorType::Q80).is_err());
+}
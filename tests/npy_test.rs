@@ -0,0 +1,135 @@
+//! Tests for NumPy `.npy`/`.npz` export
+
+mod common;
+
+use common::tensor;
+use gguf_llms::npy::{NpzWriter, export_npz, to_npy};
+use gguf_llms::{NpyDtype, Tensor, TensorType};
+
+/// Split `.npy` contents into the header dict and the data
+fn parse_npy(npy: &[u8]) -> (&str, &[u8]) {
+    assert_eq!(&npy[..8], b"\x93NUMPY\x01\x00");
+    let len = u16::from_le_bytes([npy[8], npy[9]]) as usize;
+    assert_eq!((10 + len) % 64, 0);
+    let header = std::str::from_utf8(&npy[10..10 + len]).unwrap();
+    assert!(header.ends_with('\n'));
+    (header.trim_end(), &npy[10 + len..])
+}
+
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+#[test]
+fn writes_native_types_with_row_major_shapes() {
+    let t = tensor("a", &[3, 2]);
+    let npy = to_npy(&t, NpyDtype::Native).unwrap();
+    let (header, data) = parse_npy(&npy);
+    assert_eq!(
+        header,
+        "{'descr': '<f4', 'fortran_order': False, 'shape': (2, 3), }"
+    );
+    assert_eq!(data, t.data);
+
+    let half = tensor("h", &[5]).quantize(TensorType::F16).unwrap();
+    let npy = to_npy(&half, NpyDtype::Native).unwrap();
+    let (header, data) = parse_npy(&npy);
+    assert!(header.contains("'descr': '<f2'") && header.contains("'shape': (5,)"));
+    assert_eq!(data, half.data);
+
+    let ints = Tensor {
+        info: gguf_llms::TensorInfo {
+            tensor_type: TensorType::I32,
+            ..tensor("i", &[2, 2, 2]).info
+        },
+        data: (0..8i32).flat_map(|i| i.to_le_bytes()).collect(),
+    };
+    let npy = to_npy(&ints, NpyDtype::Native).unwrap();
+    assert!(
+        parse_npy(&npy)
+            .0
+            .contains("'descr': '<i4', 'fortran_order': False, 'shape': (2, 2, 2)")
+    );
+}
+
+#[test]
+fn converts_to_float32() {
+    // BF16 has no NumPy dtype, so it's widened
+    let bf16 = tensor("b", &[4]).quantize(TensorType::BF16).unwrap();
+    let npy = to_npy(&bf16, NpyDtype::Native).unwrap();
+    let (header, data) = parse_npy(&npy);
+    assert!(header.contains("'descr': '<f4'"));
+    assert_eq!(data, f32_bytes(&[0.0, 1.0, 2.0, 3.0]));
+
+    // Quantized tensors are dequantized
+    let q8 = tensor("q", &[32, 2]).quantize(TensorType::Q80).unwrap();
+    let npy = to_npy(&q8, NpyDtype::Native).unwrap();
+    let (header, data) = parse_npy(&npy);
+    assert!(header.contains("'shape': (2, 32)"));
+    assert_eq!(data, f32_bytes(&q8.as_f32_vec().unwrap()));
+
+    let half = tensor("h", &[2]).quantize(TensorType::F16).unwrap();
+    let npy = to_npy(&half, NpyDtype::Float32).unwrap();
+    assert_eq!(parse_npy(&npy).1, f32_bytes(&[0.0, 1.0]));
+
+    // Integers are converted too when asked for float32
+    let mut int = tensor("i", &[3]);
+    int.info.tensor_type = TensorType::I32;
+    int.data = [-3i32, 0, 7].iter().flat_map(|v| v.to_le_bytes()).collect();
+    let npy = to_npy(&int, NpyDtype::Float32).unwrap();
+    let (header, data) = parse_npy(&npy);
+    assert!(header.contains("'descr': '<f4'"));
+    assert_eq!(data, f32_bytes(&[-3.0, 0.0, 7.0]));
+}
+
+#[test]
+fn writes_raw_bytes() {
+    let q8 = tensor("q", &[64, 3]).quantize(TensorType::Q80).unwrap();
+    let npy = to_npy(&q8, NpyDtype::Raw).unwrap();
+    let (header, data) = parse_npy(&npy);
+    // Two 34-byte Q8_0 blocks per row
+    assert!(header.contains("'descr': '|u1'") && header.contains("'shape': (3, 68)"));
+    assert_eq!(data, q8.data);
+}
+
+#[test]
+fn writes_npz_archives() {
+    let tensors = [tensor("blk.0.attn_q.weight", &[3, 2]), tensor("norm", &[3])];
+    let mut writer = NpzWriter::new(Vec::new());
+    for t in &tensors {
+        writer
+            .write_tensor(&t.info.name, t, NpyDtype::Native)
+            .unwrap();
+    }
+    let zip = writer.finish().unwrap();
+
+    // Walk the local headers: stored entries holding the .npy data
+    let mut offset = 0;
+    for t in &tensors {
+        let header = &zip[offset..offset + 30];
+        assert_eq!(&header[..4], &0x04034b50u32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([header[8], header[9]]), 0);
+        let size = u32::from_le_bytes(header[18..22].try_into().unwrap()) as usize;
+        let name_len = u16::from_le_bytes([header[26], header[27]]) as usize;
+        let name = &zip[offset + 30..offset + 30 + name_len];
+        assert_eq!(name, format!("{}.npy", t.info.name).as_bytes());
+
+        let data = &zip[offset + 30 + name_len..offset + 30 + name_len + size];
+        assert_eq!(data, to_npy(t, NpyDtype::Native).unwrap());
+        offset += 30 + name_len + size;
+    }
+
+    // The end of central directory record counts both entries
+    let end = &zip[zip.len() - 22..];
+    assert_eq!(&end[..4], &0x06054b50u32.to_le_bytes());
+    assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+    assert_eq!(
+        u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize,
+        offset
+    );
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tensors.npz");
+    export_npz(&path, &tensors, NpyDtype::Native).unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), zip);
+}
@@ -0,0 +1,72 @@
+//! Tests for the multi-threaded tensor loader
+
+mod common;
+
+use std::io::{ErrorKind, Write};
+use std::sync::Mutex;
+
+use common::{base_tensors, insert_dense_ffn, tensor_data_section};
+use gguf_llms::metadata::TensorType;
+use gguf_llms::{GgufError, ParallelLoader, Tensor, TensorInfo};
+
+#[test]
+fn loads_tensors_in_parallel_with_progress() {
+    let mut tensors = base_tensors(2);
+    insert_dense_ffn(&mut tensors, 0);
+    insert_dense_ffn(&mut tensors, 1);
+    // F16 values 1.0, 2.0, -0.5
+    tensors.insert(
+        "half.weight".to_string(),
+        Tensor {
+            info: TensorInfo {
+                name: "half.weight".to_string(),
+                n_dims: 1,
+                dims: vec![3],
+                tensor_type: TensorType::F16,
+                offset: 0,
+            },
+            data: vec![0x00, 0x3c, 0x00, 0x40, 0x00, 0xb8],
+        },
+    );
+
+    let data_start = 64;
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&[0u8; 64]).unwrap();
+    file.write_all(&tensor_data_section(&mut tensors)).unwrap();
+    let infos: Vec<TensorInfo> = tensors.values().map(|t| t.info.clone()).collect();
+
+    let updates = Mutex::new(Vec::new());
+    let loaded = ParallelLoader::new()
+        .threads(4)
+        .load(&file, &infos, data_start, |p| {
+            updates.lock().unwrap().push(p)
+        })
+        .unwrap();
+    assert_eq!(loaded, tensors);
+
+    // Updates arrive in order even though they come from several threads
+    let updates = updates.into_inner().unwrap();
+    assert_eq!(updates.len(), tensors.len());
+    assert!(
+        updates.windows(2).all(|w| {
+            w[0].tensors_done <= w[1].tensors_done && w[0].bytes_done < w[1].bytes_done
+        })
+    );
+    let last = updates.last().unwrap();
+    assert_eq!(last.tensors_done, last.tensors_total);
+    assert_eq!(last.bytes_done, last.bytes_total);
+
+    let converted = ParallelLoader::new()
+        .convert_to_f32(true)
+        .load(&file, &infos, data_start, |_| {})
+        .unwrap();
+    let half = &converted["half.weight"];
+    assert_eq!(half.info.tensor_type, TensorType::F32);
+    assert_eq!(half.as_f32_vec().unwrap(), vec![1.0, 2.0, -0.5]);
+
+    // Reads past the end of the file fail the load with the I/O error
+    let err = ParallelLoader::new()
+        .load(&file, &infos, data_start + 4096, |_| {})
+        .unwrap_err();
+    assert!(matches!(err, GgufError::Io(e) if e.kind() == ErrorKind::UnexpectedEof));
+}
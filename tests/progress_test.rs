@@ -0,0 +1,88 @@
+//! Tests for load progress reporting and cancellation
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{Cursor, Write};
+
+use common::{insert, tensor_data_section};
+use gguf_llms::metadata::GgufError;
+use gguf_llms::{
+    CancellationToken, LoadProgress, ParallelLoader, Tensor, TensorInfo, TensorLoader,
+};
+
+/// Two small tensors followed by one spanning several read chunks (12 MiB)
+fn tensors() -> HashMap<String, Tensor> {
+    let mut tensors = HashMap::new();
+    insert(&mut tensors, "a.weight", &[4, 4]);
+    insert(&mut tensors, "b.weight", &[8]);
+    insert(&mut tensors, "c.weight", &[1024, 3072]);
+    tensors
+}
+
+#[test]
+fn reports_progress_per_chunk() {
+    let mut tensors = tensors();
+    let section = tensor_data_section(&mut tensors);
+    let infos: Vec<TensorInfo> = tensors.values().map(|t| t.info.clone()).collect();
+
+    let mut updates: Vec<LoadProgress> = Vec::new();
+    let loaded = TensorLoader::load_all_tensors_with_progress(
+        &mut Cursor::new(section),
+        &infos,
+        0,
+        |p| updates.push(p),
+        &CancellationToken::new(),
+    )
+    .unwrap();
+    assert_eq!(loaded, tensors);
+
+    let bytes_total = (16 + 8 + 1024 * 3072) * 4;
+    assert_eq!(updates[0].bytes_done, 0);
+    let last = updates.last().unwrap();
+    assert_eq!(last.tensors_done, 3);
+    assert_eq!(last.bytes_done, bytes_total);
+    assert_eq!(last.bytes_total, bytes_total);
+    assert_eq!(last.fraction(), 1.0);
+    // The large tensor reports progress before it's done
+    assert!(
+        updates
+            .iter()
+            .any(|p| p.tensors_done < 3 && p.bytes_done > 24 * 4)
+    );
+}
+
+#[test]
+fn cancels_within_a_tensor() {
+    let mut tensors = tensors();
+    let section = tensor_data_section(&mut tensors);
+    let infos: Vec<TensorInfo> = tensors.values().map(|t| t.info.clone()).collect();
+
+    // Cancel once the first chunk of the large tensor has been read
+    let token = CancellationToken::new();
+    let mut last = LoadProgress::default();
+    let result = TensorLoader::load_all_tensors_with_progress(
+        &mut Cursor::new(section.clone()),
+        &infos,
+        0,
+        |p| {
+            last = p;
+            if p.bytes_done > 24 * 4 {
+                token.cancel();
+            }
+        },
+        &token,
+    );
+    assert!(matches!(result, Err(GgufError::Cancelled)));
+    assert!(last.bytes_done < last.bytes_total);
+    assert!(token.is_cancelled());
+
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&section).unwrap();
+    let token = CancellationToken::new();
+    token.cancel();
+    let result = ParallelLoader::new()
+        .cancellation_token(token)
+        .load(&file, &infos, 0, |_| {});
+    assert!(matches!(result, Err(GgufError::Cancelled)));
+}
@@ -0,0 +1,313 @@
+//! Tests for quantization encoders
+
+use gguf_llms::quant::{dequantize, quantize};
+use gguf_llms::{Tensor, TensorInfo, TensorType};
+
+/// Deterministic pseudo-random values with a few outliers, like real weights
+fn weights(count: usize) -> Vec<f32> {
+    let mut state = 0x2545_f491u32;
+    (0..count)
+        .map(|i| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let v = (state as f32 / u32::MAX as f32 - 0.5) * 0.1;
+            if i % 97 == 0 { v * 8.0 } else { v }
+        })
+        .collect()
+}
+
+fn round_trip(tensor_type: TensorType, values: &[f32]) -> Vec<f32> {
+    let blocks = values.len() as u64 / tensor_type.block_size();
+    let mut data = vec![0u8; (blocks * tensor_type.type_size()) as usize];
+    quantize(tensor_type, values, &mut data).unwrap();
+    let mut decoded = vec![0.0; values.len()];
+    dequantize(tensor_type, &data, &mut decoded).unwrap();
+    decoded
+}
+
+/// Root mean square error relative to the root mean square of `values`
+fn relative_rmse(values: &[f32], decoded: &[f32]) -> f32 {
+    let err: f32 = values
+        .iter()
+        .zip(decoded)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum();
+    let norm: f32 = values.iter().map(|a| a * a).sum();
+    (err / norm).sqrt()
+}
+
+#[test]
+fn quantization_error_is_bounded() {
+    let values = weights(1024);
+    let cases = [
+        (TensorType::Q2K, 0.40),
+        (TensorType::Q3K, 0.25),
+        (TensorType::Q40, 0.16),
+        (TensorType::Q41, 0.11),
+        (TensorType::Q4K, 0.10),
+        (TensorType::Q50, 0.08),
+        (TensorType::Q51, 0.05),
+        (TensorType::Q5K, 0.05),
+        (TensorType::Q6K, 0.032),
+        (TensorType::Q80, 0.01),
+        (TensorType::Q81, 0.01),
+        (TensorType::Q8K, 0.022),
+        (TensorType::F16, 0.0005),
+        (TensorType::BF16, 0.003),
+    ];
+    for (tensor_type, bound) in cases {
+        let rmse = relative_rmse(&values, &round_trip(tensor_type, &values));
+        assert!(rmse < bound, "{:?}: relative RMSE {}", tensor_type, rmse);
+    }
+}
+
+#[test]
+fn zero_blocks_stay_zero() {
+    let values = vec![0.0; 256];
+    for tensor_type in [
+        TensorType::Q40,
+        TensorType::Q51,
+        TensorType::Q80,
+        TensorType::Q2K,
+        TensorType::Q3K,
+        TensorType::Q4K,
+        TensorType::Q5K,
+        TensorType::Q6K,
+        TensorType::Q8K,
+    ] {
+        assert_eq!(
+            round_trip(tensor_type, &values),
+            values,
+            "{:?}",
+            tensor_type
+        );
+    }
+}
+
+#[test]
+fn requantizing_decoded_blocks_reproduces_them() {
+    // Q4_0 with scale 1.0 spanning the full -8..=7 range
+    let mut q4_0 = vec![0x00, 0x3c];
+    q4_0.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+    // Q8_0 with scale 0.5 spanning the full -127..=127 range
+    let mut q8_0 = vec![0x00, 0x38, 127, 129];
+    q8_0.extend((0..30).map(|i| i * 3));
+
+    for (tensor_type, block) in [(TensorType::Q40, q4_0), (TensorType::Q80, q8_0)] {
+        let mut values = vec![0.0; 32];
+        dequantize(tensor_type, &block, &mut values).unwrap();
+        let mut encoded = vec![0; block.len()];
+        quantize(tensor_type, &values, &mut encoded).unwrap();
+        assert_eq!(encoded, block, "{:?}", tensor_type);
+    }
+}
+
+/// Decode a hex string into bytes
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn quants_match_ggml_reference() {
+    // Blocks from the C reference quantize_row_*_ref functions in ggml-quants.c, built with
+    // -ffp-contract=off, for `weights(256)`
+    let cases = [
+        (
+            TensorType::Q40,
+            concat!(
+                "dba870989889779788898889997898888997159e62d97bbde10feb12b246cc97",
+                "50f1cca9b59d3bce61c54f30fb5af9af4302b0c22f05469e5bbdcc7a90c50144",
+                "e51f72e66849f2ed269e2634af72cdada07eeed9cf0b023430495a9ed2a30c09",
+                "2341bb691eb360ee571ad1b4fe2877799089997998778988787878898999429e",
+                "cc77bc02b19f6b51f3d9b0b0ffd81369",
+            ),
+        ),
+        (
+            TensorType::Q41,
+            concat!(
+                "092665aa4f120230441422202221004302131104701efda9ad3795522ef015ed",
+                "5dca4478bf0e43660b1ea0a9c532ae4ab0df05a61661bcfe4f4dd0fa7d1ee3a9",
+                "a45344856f3afebb1ae09d29a8b71d121c1e4fa9d9cb508d32525f81112630f4",
+                "fdcbcfb5f11dc9a81d4cf2f5dbbe4386e03c8f00a8e41e3b1f26feb4bcbff0de",
+                "efcffdcbefddbdcdcccfeefea71e38aa438953fe4e6095ae1d375f5f1027eca7",
+            ),
+        ),
+        (
+            TensorType::Q50,
+            concat!(
+                "dba4ce57e6f7e01021f3de1d0002001122ef301f122e159a6ec45aedb4a2d65b",
+                "c20fd515647b871f90f27843b599d3434a33668cb28b8f51f6b4f23d86037085",
+                "5f0b469a0fa236c9b65987e5209a0298c91fd3ccbf81e5da269ab48f74074d69",
+                "5ee4994a30ebbcb29e06046960935a9acc2943caa4461702578156c21c76c1cb",
+                "ae24a278fe247aed5cc3eed2200112e220ee120fe0e0fff21221429a65b2353f",
+                "79ee6903722fc6a1e5a15050efb126b1",
+            ),
+        ),
+        (
+            TensorType::Q51,
+            concat!(
+                "d72165aa010000008f3513609829554155320185053531183b1afda9913ba512",
+                "4b6d2aa53df03aeaab8488e16f0d87cdd919a0a92cbcb5cc99744d8470ae0a4b",
+                "1dc27afc9f8aa1f4471ae3a9f05dc93649a6781adf65fd6736e02c43407e1a25",
+                "ea194fa94b708bf8a296900a55b4bf03323c50f8fb869f6bbf19c9a833d6bc35",
+                "2a99e5fba76e772be0681e0140d92c66ec21feb4fbffffff787fe0bdde8ffb87",
+                "cfaa8b8a8aafceed701a38aa9a4dcac0972297fc9dd0395e2a5eafbf215fd94e",
+            ),
+        ),
+        (
+            TensorType::Q80,
+            concat!(
+                "e5187f02fceb131502ee04f7ee050205f11112f7ee0714f501ff02fbec11ebfa",
+                "f9ef220e62f0d1aa6b83d8596027c60c7f70bdeb27b417d59d7c9b74d145c2f4",
+                "3684c6e3c00dcfa47229847ad1dfed9653677f5a88294cbd26c34057832a8ce5",
+                "3c7fc9c2597e530ecdb6c7dc7c336c423489641f04f758af28d6c210f2bb7f38",
+                "9e7617a5273f949c320e1b3b925cb7b17fa6a4ef92cf5d3a7ee95c4fdb0fbce4",
+                "e80ea7acbb7c7f4d5239670e5c52c5ef4874d1f2a24d7aa711e36c41aedf787f",
+                "5b40d51e77cc1ea02e6fb1c90819f212810a101301ec11fb02fdfa130d0aeeec",
+                "0f010cf412f306fcf0f3f4fa05104f0eba12b9677082ce7556f77f7d88fb50f6",
+                "c711cf7dcaee1d2e92aed6d890ab6d26",
+            ),
+        ),
+        (
+            TensorType::Q81,
+            concat!(
+                "e51847317f02fceb131502ee04f7ee050205f11112f7ee0714f501ff02fbec11",
+                "ebfaf9ef220e999862f0d1aa6b83d8596027c60c7f70bdeb27b417d59d7c9b74",
+                "d145c2f43684c6e3c00d582fcfa47229847ad1dfed9653677f5a88294cbd26c3",
+                "4057832a8ce53c7fc9c2597e530eb128cdb6c7dc7c336c423489641f04f758af",
+                "28d6c210f2bb7f389e7617a5273f949c320e032d1b3b925cb7b17fa6a4ef92cf",
+                "5d3a7ee95c4fdb0fbce4e80ea7acbb7c7f4d5239670e61345c52c5ef4874d1f2",
+                "a24d7aa711e36c41aedf787f5b40d51e77cc1ea02e6fb1c9081927b3f212810a",
+                "101301ec11fb02fdfa130d0aeeec0f010cf412f306fcf0f3f4fa05104f0e5620",
+                "ba12b9677082ce7556f77f7d88fb50f6c711cf7dcaee1d2e92aed6d890ab6d26",
+            ),
+        ),
+        (
+            TensorType::Q2K,
+            concat!(
+                "2f2423242324242323132323ff2424235f047460cdb1d49c9c08f4b87c7c8425",
+                "ab516896a36cc1ed05d9a43798d125342ebf04f7fd3c77e8b06dfcf12b77bf7d",
+                "42866cdd6c4cb4892c1448430b1ee2a615205325",
+            ),
+        ),
+        (
+            TensorType::Q3K,
+            concat!(
+                "cc4fbb6b54d2e73575dd5373c9e947daa32cda55d99a7513ddb690ab86846868",
+                "94b0947d378f549594fc69d0001478f7e76bb5ed279e34bcfc1be9c3e5a7dfcd",
+                "87d68b450ad288335f09030ecd1644099dd6a100a669f5ecd3fb5e6cecf25dfa",
+                "5f5a555a05a555552b69aaa9fb98",
+            ),
+        ),
+        (
+            TensorType::Q4K,
+            concat!(
+                "170e101d7f51d0520a09c9098170ffa2df624220d40452c0d2914083f2e33164",
+                "9431805314e112e242b23064a001415045223e5ae0afd5a6a601cc8e7f6dc02a",
+                "9c443a846b3de0aa11d68b1f94a40d1fc8ba205cb2d23e5101b5e0037c4adea5",
+                "0c4be4e7c2a53587e121820e9edb1b2a3c8f30deef0f5debcf7dfefd0d7fcf7e",
+                "4b8b5ffd4f6c9fac1e3d5c5c1c3deeaf",
+            ),
+        ),
+        (
+            TensorType::Q5K,
+            concat!(
+                "080a111d7e10d0500a09c909808fffa173f004d4ea6c58caca62ecce7e56f864",
+                "5ed066e86466c8ee604a6c547a78d4d4cfd59340d809a5a1b5428115f5d571c8",
+                "485321a639f225e4958370e8600383b189548da4f06eda8b6d02c93b0eeab154",
+                "48a77418d769f06421fc263e49781a2eb1a560c984f38ec2129bf02719a5ee8a",
+                "39a8f9ffb57a8a3ff273351d5ee73875782f70cdee0fabe7aefafbfa1affaefd",
+                "9727aefb9de83f582c6ab7b8285adc4e",
+            ),
+        ),
+        (
+            TensorType::Q6K,
+            concat!(
+                "d1703155fb1bc0845fb2b46f0f9ff46ceb0375fa1eb0e26ec369231ad1f7a626",
+                "d844fca5062f3aea28f75e8df12590556aad06c6481e072c9421a17d6d02b299",
+                "c9510d28435500b748f51cfde8513036591d792c21e7c6cd96a571c1e17d7c82",
+                "18cb1f745ee2cc44b82c2127dc17d42fe5c9c110e9509b48c25db8a8c454547e",
+                "a0fa8a9e314d2a2261f60a4541813ed94bb1d86693cd325df62d54c76861c5c4",
+                "d161cb2823e3a81b2f92231ed4a920a28c48b221b3867a45e3db878cc4d02c79",
+                "8015ec14eeededecedececec7f15ebec0405",
+            ),
+        ),
+        (
+            TensorType::Q8K,
+            concat!(
+                "7d02213b7c02fcec121402ef04f7ef050205f21112f8ef0714f501ff02fbed11",
+                "ecfafaef0ffef9f310edfa0d0f06f7021311f6fd06f404faf113f112f90af7fe",
+                "08edf7fcf9f31006ee11f9fbfdf10c0f120def060bf605f7090cee06effc0912",
+                "f8f70d12f8f4f7fa1308110a08ed100501ff0ef306f9f602fef51409f11304f2",
+                "060aeff00409ef0ef5f414f2f2fdeff90e0913fc0e0cfa02f5fcfc02f2f3f513",
+                "140c0d090f0df7fd0b12f9fef10c13f203fb110af3fb13140f0af90513f805f1",
+                "0712f3f7f212810a101301ec11fb02fdfa130d0aeeec0f010cf412f306fcf0f3",
+                "f4fa0510f503f51012ecf8120eff1414edff0dfef703f814f8fd0507eff3f9fa",
+                "eef311067600d3ff2200dfff12001a001e00f0fff6ff28003f003000ceffd7ff",
+                "3100d4ff",
+            ),
+        ),
+    ];
+    let values = weights(256);
+    for (tensor_type, expected) in cases {
+        let blocks = values.len() / tensor_type.block_size() as usize;
+        let mut encoded = vec![0u8; blocks * tensor_type.type_size() as usize];
+        quantize(tensor_type, &values, &mut encoded).unwrap();
+        assert_eq!(encoded, hex(expected), "{:?}", tensor_type);
+    }
+}
+
+#[test]
+fn quantizes_tensors() {
+    let values = weights(512);
+    let tensor = Tensor {
+        info: TensorInfo {
+            name: "blk.0.ffn_up.weight".to_string(),
+            n_dims: 2,
+            dims: vec![256, 2],
+            tensor_type: TensorType::F32,
+            offset: 0,
+        },
+        data: values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    };
+
+    let q6 = tensor.quantize(TensorType::Q6K).unwrap();
+    assert_eq!(q6.info.dims, vec![256, 2]);
+    assert_eq!(q6.data.len(), 2 * 210);
+    assert!(relative_rmse(&values, &q6.as_f32_vec().unwrap()) < 0.032);
+
+    // Requantizing goes through f32
+    let q8 = q6.quantize(TensorType::Q80).unwrap();
+    assert_eq!(q8.row(1).unwrap().len(), 256);
+
+    let narrow = tensor.slice(0, 0..100).unwrap();
+    assert!(narrow.quantize(TensorType::Q4K).is_err());
+    assert!(tensor.quantize(TensorType::Iq2Xxs).is_err());
+}
+
+#[test]
+fn supported_types_are_the_decodable_ones() {
+    for tensor_type in (0..=30).filter_map(TensorType::from_u32) {
+        let block = vec![0u8; tensor_type.type_size() as usize];
+        let mut out = vec![0.0; tensor_type.block_size() as usize];
+        let decoded = dequantize(tensor_type, &block, &mut out).is_ok();
+        assert_eq!(decoded, tensor_type.can_dequantize(), "{:?}", tensor_type);
+        let mut encoded = block.clone();
+        let encodes = quantize(tensor_type, &out, &mut encoded).is_ok();
+        assert_eq!(encodes, tensor_type.can_quantize(), "{:?}", tensor_type);
+
+        let info = TensorInfo {
+            name: "blk.0.ffn_up.weight".to_string(),
+            n_dims: 2,
+            dims: vec![256, 2],
+            tensor_type,
+            offset: 0,
+        };
+        assert!(info.is_well_formed());
+        assert_eq!(info.is_supported(), decoded, "{:?}", tensor_type);
+    }
+}
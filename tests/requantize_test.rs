@@ -0,0 +1,216 @@
+//! Tests for requantizing GGUF files
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::{Cursor, ErrorKind};
+
+use common::tensor;
+use gguf_llms::requantize::{QUANTIZATION_VERSION, requantize, requantize_file};
+use gguf_llms::{
+    FileType, GgufError, GgufHeader, GgufReader, GgufWriter, Imatrix, ImatrixEntry,
+    QuantizationPolicy, Tensor, TensorInfo, TensorLoader, TensorType, TypeRule, Value,
+};
+
+const BLOCKS: u32 = 8;
+
+/// A small F32 model whose weights have rows of 256 elements (one K-quant block)
+fn model_file() -> Vec<u8> {
+    let mut tensors = vec![
+        weight("token_embd.weight", &[256, 4]),
+        weight("output.weight", &[256, 4]),
+    ];
+    for i in 0..BLOCKS {
+        tensors.push(weight(&format!("blk.{}.attn_v.weight", i), &[256, 4]));
+        tensors.push(tensor(&format!("blk.{}.attn_norm.weight", i), &[256]));
+    }
+    // Rows of 96 elements can't hold K-quant blocks
+    tensors.push(weight("blk.0.ffn_down.weight", &[96, 4]));
+
+    let metadata = HashMap::from([(
+        "general.architecture".to_string(),
+        Value::String("llama".to_string()),
+    )]);
+    let infos: Vec<TensorInfo> = tensors.iter().map(|t| t.info.clone()).collect();
+    let mut writer = GgufWriter::new(Vec::new(), &metadata, &infos).unwrap();
+    for tensor in &tensors {
+        writer.write_tensor(tensor).unwrap();
+    }
+    writer.finish().unwrap()
+}
+
+/// An F32 weight with values in [-1, 1)
+fn weight(name: &str, dims: &[u64]) -> Tensor {
+    let mut tensor = tensor(name, dims);
+    let count = tensor.info.element_count();
+    tensor.data = (0..count)
+        .flat_map(|i| (((i * 37) % 200) as f32 / 100.0 - 1.0).to_le_bytes())
+        .collect();
+    tensor
+}
+
+fn read(file: Vec<u8>) -> (HashMap<String, Value>, HashMap<String, Tensor>) {
+    let mut reader = Cursor::new(file);
+    let header = GgufHeader::parse(&mut reader).unwrap();
+    let metadata = GgufReader::read_metadata(&mut reader, header.n_kv).unwrap();
+    let infos = TensorLoader::read_tensor_info(&mut reader, header.n_tensors).unwrap();
+    let start = TensorLoader::get_aligned_tensor_data_start(&mut reader, &metadata).unwrap();
+    let tensors = TensorLoader::load_all_tensors(&mut reader, &infos, start).unwrap();
+    (metadata, tensors)
+}
+
+#[test]
+fn applies_preset_policy() {
+    let mut output = Vec::new();
+    let policy = QuantizationPolicy::preset(FileType::Q4KM, BLOCKS as usize);
+    let report = requantize(&mut Cursor::new(model_file()), &mut output, &policy).unwrap();
+    assert!(report.size() < report.original_size() / 2);
+
+    let (metadata, tensors) = read(output);
+    assert_eq!(metadata["general.file_type"], Value::Uint32(15));
+    assert_eq!(
+        metadata["general.quantization_version"],
+        Value::Uint32(QUANTIZATION_VERSION)
+    );
+    assert_eq!(metadata["general.architecture"].as_string(), Some("llama"));
+
+    let tensor_type = |name: &str| tensors[name].info.tensor_type;
+    assert_eq!(tensor_type("token_embd.weight"), TensorType::Q4K);
+    assert_eq!(tensor_type("output.weight"), TensorType::Q6K);
+    // attn_v gets more bits in the first and last eighth of the layers
+    assert_eq!(tensor_type("blk.0.attn_v.weight"), TensorType::Q6K);
+    assert_eq!(tensor_type("blk.3.attn_v.weight"), TensorType::Q4K);
+    assert_eq!(tensor_type("blk.7.attn_v.weight"), TensorType::Q6K);
+    assert_eq!(tensor_type("blk.3.attn_norm.weight"), TensorType::F32);
+    // Q6_K falls back to Q8_0 for rows that aren't whole K-quant blocks
+    assert_eq!(tensor_type("blk.0.ffn_down.weight"), TensorType::Q80);
+
+    let original = weight("output.weight", &[256, 4]).as_f32_vec().unwrap();
+    let requantized = tensors["output.weight"].as_f32_vec().unwrap();
+    for (a, b) in original.iter().zip(&requantized) {
+        assert!((a - b).abs() < 0.02, "{} vs {}", a, b);
+    }
+    // Norms are copied unchanged
+    assert_eq!(
+        tensors["blk.3.attn_norm.weight"].data,
+        tensor("blk.3.attn_norm.weight", &[256]).data
+    );
+}
+
+#[test]
+fn custom_rules_take_precedence() {
+    let policy = QuantizationPolicy::new(TensorType::Q80)
+        .with_rule(TypeRule::new("blk.*.attn_v.weight", TensorType::F16).last_layers(2))
+        .with_rule(TypeRule::new("*_norm.weight", TensorType::F16))
+        .with_rule(TypeRule::new("blk.*", TensorType::Q40));
+    assert_eq!(policy.file_type, Some(FileType::Q80));
+
+    let mut output = Vec::new();
+    requantize(&mut Cursor::new(model_file()), &mut output, &policy).unwrap();
+    let (metadata, tensors) = read(output.clone());
+    assert_eq!(metadata["general.file_type"], Value::Uint32(7));
+
+    let tensor_type = |name: &str| tensors[name].info.tensor_type;
+    assert_eq!(tensor_type("output.weight"), TensorType::Q80);
+    assert_eq!(tensor_type("blk.6.attn_v.weight"), TensorType::F16);
+    assert_eq!(tensor_type("blk.5.attn_v.weight"), TensorType::Q40);
+    assert_eq!(tensor_type("blk.0.attn_norm.weight"), TensorType::F16);
+
+    // Requantizing already quantized files works through dequantization
+    let mut again = Vec::new();
+    let policy = QuantizationPolicy::preset(FileType::Q6K, BLOCKS as usize);
+    let report = requantize(&mut Cursor::new(output.clone()), &mut again, &policy).unwrap();
+    let change = &report.tensors[0];
+    assert_eq!(change.name, "token_embd.weight");
+    assert_eq!(
+        (change.original_type, change.tensor_type),
+        (TensorType::Q80, TensorType::Q6K)
+    );
+    assert_eq!(report.type_counts()[&TensorType::F16], BLOCKS as usize);
+
+    // Without a file type for the policy, the input's one is dropped rather than kept
+    let policy = QuantizationPolicy {
+        file_type: None,
+        ..QuantizationPolicy::new(TensorType::Q40)
+    };
+    let mut again = Vec::new();
+    requantize(&mut Cursor::new(output), &mut again, &policy).unwrap();
+    let (metadata, _) = read(again);
+    assert!(!metadata.contains_key("general.file_type"));
+}
+
+#[test]
+fn keeps_tensors_that_cant_be_converted() {
+    // IQ2_XXS blocks can be copied but not dequantized
+    let mut grid = tensor("blk.0.attn_q.weight", &[256, 4]);
+    grid.info.tensor_type = TensorType::Iq2Xxs;
+    grid.data = (0..4 * 66).map(|i| i as u8).collect();
+    let tensors = [weight("blk.0.attn_k.weight", &[256, 4]), grid.clone()];
+    let infos: Vec<TensorInfo> = tensors.iter().map(|t| t.info.clone()).collect();
+    let mut writer = GgufWriter::new(Vec::new(), &HashMap::new(), &infos).unwrap();
+    for tensor in &tensors {
+        writer.write_tensor(tensor).unwrap();
+    }
+    let file = writer.finish().unwrap();
+
+    let mut output = Vec::new();
+    let policy = QuantizationPolicy::new(TensorType::Q4K);
+    requantize(&mut Cursor::new(file.clone()), &mut output, &policy).unwrap();
+    let mut reader = Cursor::new(output);
+    let header = GgufHeader::parse(&mut reader).unwrap();
+    let metadata = GgufReader::read_metadata(&mut reader, header.n_kv).unwrap();
+    let infos = TensorLoader::read_tensor_info(&mut reader, header.n_tensors).unwrap();
+    let start = TensorLoader::get_aligned_tensor_data_start(&mut reader, &metadata).unwrap();
+    assert_eq!(infos[0].tensor_type, TensorType::Q4K);
+    let copied = TensorLoader::load_tensor(&mut reader, &infos[1], start).unwrap();
+    assert_eq!(copied.info.tensor_type, TensorType::Iq2Xxs);
+    assert_eq!(copied.data, grid.data);
+
+    // Types without an encoder are rejected before anything is written
+    let policy = QuantizationPolicy::new(TensorType::Q80)
+        .with_rule(TypeRule::new("blk.*", TensorType::Iq4Nl));
+    let mut output = Vec::new();
+    let err = requantize(&mut Cursor::new(file), &mut output, &policy).unwrap_err();
+    assert!(matches!(err, GgufError::Unsupported(_)), "{}", err);
+    assert!(output.is_empty());
+}
+
+#[test]
+fn refuses_to_overwrite_the_input() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.gguf");
+    std::fs::write(&path, model_file()).unwrap();
+
+    let policy = QuantizationPolicy::new(TensorType::Q80);
+    let same = dir.path().join(".").join("model.gguf");
+    let err = requantize_file(&path, &same, &policy).unwrap_err();
+    assert!(matches!(err, GgufError::Io(e) if e.kind() == ErrorKind::InvalidInput));
+    assert_eq!(std::fs::read(&path).unwrap(), model_file());
+
+    let output = dir.path().join("model-q8_0.gguf");
+    requantize_file(&path, &output, &policy).unwrap();
+    let written = std::fs::read(&output).unwrap();
+
+    // A failing requantization leaves an existing output as it was
+    let mut imatrix = Imatrix::default();
+    imatrix.entries.insert(
+        "blk.0.attn_v.weight".to_string(),
+        ImatrixEntry {
+            sums: vec![1.0; 3],
+            counts: vec![1.0],
+        },
+    );
+    let err = requantize_file(&path, &output, &policy.with_imatrix(imatrix)).unwrap_err();
+    assert!(matches!(err, GgufError::InvalidFormat(_)), "{}", err);
+    assert_eq!(std::fs::read(&output).unwrap(), written);
+}
+
+#[test]
+fn parses_type_names() {
+    assert_eq!(TensorType::from_name("q4_k"), Some(TensorType::Q4K));
+    assert_eq!(TensorType::Iq4Xs.name(), "IQ4_XS");
+    assert_eq!(FileType::from_name("Q4_K_M"), Some(FileType::Q4KM));
+    assert_eq!(FileType::from_u32(18), Some(FileType::Q6K));
+    assert_eq!(FileType::Q3KL.base_type(), TensorType::Q3K);
+    assert_eq!(FileType::for_type(TensorType::BF16), Some(FileType::BF16));
+}
@@ -0,0 +1,248 @@
+//! Tests for loading, splitting and merging split (sharded) GGUF files
+
+mod common;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use common::tensor;
+use gguf_llms::split::{
+    SplitLimit, merge_model, parse_split_path, plan_split, split_model, split_path,
+};
+use gguf_llms::{GgufError, GgufWriter, ShardedGguf, SplitInfo, Tensor, Value};
+
+/// Write `tensors` to a file with the given metadata
+fn write_file(path: &Path, metadata: &HashMap<String, Value>, tensors: &[Tensor]) {
+    let infos: Vec<_> = tensors.iter().map(|t| t.info.clone()).collect();
+    let mut writer = GgufWriter::new(File::create(path).unwrap(), metadata, &infos).unwrap();
+    for tensor in tensors {
+        writer.write_tensor(tensor).unwrap();
+    }
+    writer.finish().unwrap();
+}
+
+fn tensors() -> Vec<Tensor> {
+    vec![
+        tensor("token_embd.weight", &[4, 10]),
+        tensor("blk.0.attn_q.weight", &[4, 4]),
+        tensor("blk.0.attn_k.weight", &[4, 4]),
+        tensor("output.weight", &[4, 10]),
+    ]
+}
+
+/// Write `tensors()` as shards holding `sizes` tensors each, as llama.cpp does
+fn write_shards(prefix: &Path, sizes: &[usize]) {
+    let tensors = tensors();
+    let mut start = 0;
+    for (no, &size) in sizes.iter().enumerate() {
+        let split = SplitInfo {
+            no: no as u16,
+            count: sizes.len() as u16,
+            tensors_count: tensors.len() as u64,
+        };
+        let mut metadata: HashMap<String, Value> = split.to_metadata().into_iter().collect();
+        if no == 0 {
+            metadata.insert(
+                "general.architecture".to_string(),
+                Value::String("llama".to_string()),
+            );
+        }
+        let path = split_path(prefix, no as u16, sizes.len() as u16);
+        write_file(&path, &metadata, &tensors[start..start + size]);
+        start += size;
+    }
+}
+
+#[test]
+fn names_shards_like_llama_cpp() {
+    let path = split_path("models/llama", 1, 5);
+    assert_eq!(path, Path::new("models/llama-00002-of-00005.gguf"));
+    assert_eq!(
+        parse_split_path(&path),
+        Some((Path::new("models/llama").to_path_buf(), 1, 5))
+    );
+    assert_eq!(parse_split_path("models/llama.gguf"), None);
+    assert_eq!(parse_split_path("llama-00006-of-00005.gguf"), None);
+}
+
+#[test]
+fn loads_tensors_across_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("model");
+    write_shards(&prefix, &[1, 2, 1]);
+
+    // Any shard can be opened; its siblings are discovered
+    let model = ShardedGguf::open(split_path(&prefix, 1, 3)).unwrap();
+    assert_eq!(model.shards.len(), 3);
+    assert_eq!(model.tensor_count(), 4);
+    assert_eq!(
+        model.metadata()["general.architecture"],
+        Value::String("llama".to_string())
+    );
+
+    let names: Vec<&str> = model.tensor_infos().map(|i| i.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            "token_embd.weight",
+            "blk.0.attn_q.weight",
+            "blk.0.attn_k.weight",
+            "output.weight"
+        ]
+    );
+    assert_eq!(model.tensor_info("output.weight").unwrap().0, 2);
+
+    let loaded = model.load_all_tensors().unwrap();
+    let lazy = model.lazy_tensors();
+    for tensor in tensors() {
+        let name = &tensor.info.name;
+        assert_eq!(loaded[name].data, tensor.data);
+        assert_eq!(model.load_tensor(name).unwrap().data, tensor.data);
+        assert_eq!(*lazy[name].data().unwrap(), tensor.data);
+    }
+    assert!(model.load_tensor("blk.1.attn_q.weight").is_err());
+}
+
+#[test]
+fn opens_unsplit_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.gguf");
+    write_file(&path, &HashMap::new(), &tensors());
+
+    let model = ShardedGguf::open(&path).unwrap();
+    assert_eq!(model.shards.len(), 1);
+    assert_eq!(model.shards[0].split, None);
+    assert_eq!(model.load_all_tensors().unwrap().len(), 4);
+}
+
+#[test]
+fn rejects_inconsistent_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("model");
+    write_shards(&prefix, &[2, 2]);
+
+    // A missing shard
+    std::fs::remove_file(split_path(&prefix, 1, 2)).unwrap();
+    let err = ShardedGguf::open(split_path(&prefix, 0, 2)).unwrap_err();
+    assert!(matches!(err, GgufError::InvalidFormat(_)), "{}", err);
+
+    // Shards given out of order
+    write_shards(&prefix, &[2, 2]);
+    let paths = [split_path(&prefix, 1, 2), split_path(&prefix, 0, 2)];
+    assert!(ShardedGguf::open_paths(&paths).is_err());
+
+    // Tensor counts that don't add up to split.tensors.count
+    let split = SplitInfo {
+        no: 1,
+        count: 2,
+        tensors_count: 4,
+    };
+    let metadata = split.to_metadata().into_iter().collect();
+    write_file(&split_path(&prefix, 1, 2), &metadata, &tensors()[2..3]);
+    assert!(ShardedGguf::open(split_path(&prefix, 0, 2)).is_err());
+}
+
+#[test]
+fn plans_shards_by_count_or_size() {
+    let tensors = tensors();
+    let infos: Vec<_> = tensors.iter().map(|t| &t.info).collect();
+    // Sizes are 160, 64, 64 and 160 bytes
+    assert_eq!(plan_split(&infos, SplitLimit::MaxTensors(3)), [0..3, 3..4]);
+    assert_eq!(
+        plan_split(&infos, SplitLimit::MaxSize(128)),
+        [0..1, 1..3, 3..4]
+    );
+    assert_eq!(plan_split(&infos, SplitLimit::MaxSize(1 << 20)).len(), 1);
+    assert_eq!(plan_split(&[], SplitLimit::MaxTensors(3)), vec![0..0; 1]);
+}
+
+#[test]
+fn splits_and_merges_models() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("model.gguf");
+    let metadata = HashMap::from([
+        (
+            "general.architecture".to_string(),
+            Value::String("llama".to_string()),
+        ),
+        ("general.alignment".to_string(), Value::Uint32(64)),
+    ]);
+    write_file(&path, &metadata, &tensors());
+
+    let prefix = dir.path().join("split");
+    let model = ShardedGguf::open(&path).unwrap();
+    let paths = split_model(&model, &prefix, SplitLimit::MaxSize(128)).unwrap();
+    assert_eq!(paths.len(), 3);
+    assert_eq!(paths[2], split_path(&prefix, 2, 3));
+
+    let split = ShardedGguf::open(&paths[1]).unwrap();
+    assert_eq!(
+        split.shards[0].metadata["general.architecture"],
+        metadata["general.architecture"]
+    );
+    assert_eq!(split.shards[1].tensor_infos.len(), 2);
+    assert!(
+        !split.shards[1]
+            .metadata
+            .contains_key("general.architecture")
+    );
+    assert_eq!(
+        split.shards[1].metadata["general.alignment"],
+        Value::Uint32(64)
+    );
+
+    let merged_path = dir.path().join("merged.gguf");
+    merge_model(&split, &merged_path).unwrap();
+    let merged = ShardedGguf::open(&merged_path).unwrap();
+    assert_eq!(merged.shards[0].split, None);
+    assert_eq!(merged.metadata(), &metadata);
+    // Merging restores the original file byte for byte
+    assert_eq!(
+        std::fs::read(&merged_path).unwrap(),
+        std::fs::read(&path).unwrap()
+    );
+
+    // Split models can be resplit
+    let resplit = split_model(
+        &split,
+        dir.path().join("resplit"),
+        SplitLimit::MaxTensors(2),
+    );
+    let resplit = ShardedGguf::open(&resplit.unwrap()[0]).unwrap();
+    assert_eq!(resplit.shards.len(), 2);
+    let tensors = resplit.load_all_tensors().unwrap();
+    for tensor in self::tensors() {
+        assert_eq!(tensors[&tensor.info.name].data, tensor.data);
+    }
+}
+
+#[test]
+fn refuses_to_overwrite_input_shards() {
+    let dir = tempfile::tempdir().unwrap();
+    let prefix = dir.path().join("model");
+    write_shards(&prefix, &[2, 2]);
+    let first = split_path(&prefix, 0, 2);
+    let model = ShardedGguf::open(&first).unwrap();
+    let shards: Vec<Vec<u8>> = model
+        .shards
+        .iter()
+        .map(|shard| std::fs::read(&shard.path).unwrap())
+        .collect();
+
+    let is_invalid_input =
+        |err: GgufError| matches!(err, GgufError::Io(e) if e.kind() == ErrorKind::InvalidInput);
+    let err = merge_model(&model, split_path(&prefix, 1, 2)).unwrap_err();
+    assert!(is_invalid_input(err));
+    // Resplitting over its own shards fails before writing anything
+    let err = split_model(
+        &model,
+        dir.path().join("./model"),
+        SplitLimit::MaxTensors(2),
+    );
+    assert!(is_invalid_input(err.unwrap_err()));
+    for (shard, data) in model.shards.iter().zip(&shards) {
+        assert_eq!(&std::fs::read(&shard.path).unwrap(), data);
+    }
+}
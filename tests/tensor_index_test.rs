@@ -0,0 +1,128 @@
+//! Tests for row access, indexing and slicing of tensors
+
+mod common;
+
+use common::tensor;
+use gguf_llms::{Tensor, TensorInfo, TensorType};
+
+fn quantized(tensor_type: TensorType, dims: &[u64], data: Vec<u8>) -> Tensor {
+    Tensor {
+        info: TensorInfo {
+            name: "q".to_string(),
+            n_dims: dims.len() as u32,
+            dims: dims.to_vec(),
+            tensor_type,
+            offset: 0,
+        },
+        data,
+    }
+}
+
+/// A Q8_0 block with scale 0.5 and quants `first, first + 1, ...`
+fn q8_0_block(first: i8) -> Vec<u8> {
+    let mut block = vec![0x00, 0x38];
+    block.extend((0..32).map(|i| (first + i) as u8));
+    block
+}
+
+#[test]
+fn indexes_rows_and_elements_in_ggml_order() {
+    let t = tensor("t", &[4, 3, 2]);
+    assert_eq!(t.info.row_len(), 4);
+    assert_eq!(t.info.row_count(), 6);
+    assert_eq!(t.info.byte_strides(), vec![4, 16, 48]);
+
+    assert_eq!(t.get(&[1, 2, 1]).unwrap(), 21.0);
+    assert_eq!(t.row(5).unwrap(), vec![20.0, 21.0, 22.0, 23.0]);
+    assert!(t.get(&[4, 0, 0]).is_err());
+    assert!(t.row(6).is_err());
+}
+
+#[test]
+fn slices_along_any_axis() {
+    let t = tensor("t", &[4, 3, 2]);
+
+    let inner = t.slice(0, 1..3).unwrap();
+    assert_eq!(inner.info.dims, vec![2, 3, 2]);
+    assert_eq!(inner.as_f32_vec().unwrap()[..4], [1.0, 2.0, 5.0, 6.0]);
+
+    let middle = t.slice(1, 1..3).unwrap();
+    assert_eq!(middle.info.dims, vec![4, 2, 2]);
+    let expected: Vec<f32> = (4..12).chain(16..24).map(|v| v as f32).collect();
+    assert_eq!(middle.as_f32_vec().unwrap(), expected);
+
+    let outer = t.slice(2, 1..2).unwrap();
+    assert_eq!(outer.info.dims, vec![4, 3, 1]);
+    assert_eq!(outer.info.offset, 48);
+    assert_eq!(outer.as_f32_vec().unwrap()[0], 12.0);
+
+    assert!(t.slice(3, 0..1).is_err());
+    assert!(t.slice(1, 2..4).is_err());
+}
+
+#[test]
+fn dequantizes_single_rows_of_quantized_tensors() {
+    // Two rows of two Q8_0 blocks each
+    let data = [
+        q8_0_block(-16),
+        q8_0_block(0),
+        q8_0_block(10),
+        q8_0_block(-40),
+    ]
+    .concat();
+    let t = quantized(TensorType::Q80, &[64, 2], data);
+    assert_eq!(t.info.byte_size(), 4 * 34);
+
+    let row = t.row(1).unwrap();
+    assert_eq!(row.len(), 64);
+    assert_eq!(row[0], 5.0);
+    assert_eq!(row[32], -20.0);
+    assert_eq!(t.get(&[33, 1]).unwrap(), -19.5);
+    assert_eq!(t.as_f32_vec().unwrap()[64..], row[..]);
+
+    let second_block = t.slice(0, 32..64).unwrap();
+    assert_eq!(second_block.info.dims, vec![32, 2]);
+    assert_eq!(second_block.row(1).unwrap(), row[32..]);
+    assert!(t.slice(0, 16..48).is_err());
+}
+
+#[test]
+fn dequantizes_q4_0_blocks() {
+    // Scale 1.0; low nibbles count up, high nibbles count down
+    let mut block = vec![0x00, 0x3c];
+    block.extend((0..16u8).map(|j| j | ((15 - j) << 4)));
+    let values = quantized(TensorType::Q40, &[32], block)
+        .as_f32_vec()
+        .unwrap();
+
+    let expected: Vec<f32> = (-8..8).chain((-8..8).rev()).map(|v| v as f32).collect();
+    assert_eq!(values, expected);
+}
+
+#[test]
+fn dequantizes_k_quant_blocks() {
+    // Q4_K with d = 1, dmin = 0 and all eight sub-block scales 1
+    let mut block = vec![0x00, 0x3c, 0x00, 0x00];
+    block.extend([1, 1, 1, 1, 0, 0, 0, 0, 1, 1, 1, 1]);
+    block.extend((0..128u8).map(|i| (i % 16) | (15 - i % 16) << 4));
+    let values = quantized(TensorType::Q4K, &[256], block)
+        .as_f32_vec()
+        .unwrap();
+    assert_eq!(values[..3], [0.0, 1.0, 2.0]);
+    assert_eq!(values[32..35], [15.0, 14.0, 13.0]);
+    assert_eq!(values[64 + 17], 1.0);
+
+    // Q6_K with d = 0.5, scale 2 for the first sub-block of each half and 1 elsewhere
+    let mut block = vec![5; 128];
+    block.extend(vec![0; 64]);
+    block.extend([2, 1, 1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 1, 1, 1]);
+    block.extend([0x00, 0x38]);
+    let values = quantized(TensorType::Q6K, &[256], block)
+        .as_f32_vec()
+        .unwrap();
+    assert_eq!(values[0], -27.0);
+    assert_eq!(values[16], -13.5);
+    assert_eq!(values[32], -13.5);
+    assert_eq!(values[64], -16.0);
+    assert_eq!(values[128], -27.0);
+}
@@ -0,0 +1,63 @@
+//! Tests for typed views over tensor data
+
+use std::borrow::Cow;
+
+use gguf_llms::{Tensor, TensorInfo, TensorType};
+
+fn tensor(tensor_type: TensorType, data: Vec<u8>) -> Tensor {
+    Tensor {
+        info: TensorInfo {
+            name: "t".to_string(),
+            n_dims: 1,
+            dims: vec![1],
+            tensor_type,
+            offset: 0,
+        },
+        data,
+    }
+}
+
+#[test]
+fn borrows_aligned_f32_data() {
+    let values = [1.5f32, -2.0, 0.25];
+    let t = tensor(
+        TensorType::F32,
+        values.iter().flat_map(|v| v.to_le_bytes()).collect(),
+    );
+
+    let view = t.as_f32_slice().unwrap();
+    assert!(matches!(view, Cow::Borrowed(_)));
+    assert_eq!(*view, values);
+    assert_eq!(t.as_f32_vec().unwrap(), values);
+}
+
+#[test]
+fn views_integer_and_f64_data() {
+    let i8s = tensor(TensorType::I8, vec![0x01, 0xff]);
+    assert_eq!(*i8s.as_i8_slice().unwrap(), [1, -1]);
+
+    let i16s = tensor(TensorType::I16, vec![0x34, 0x12, 0xfe, 0xff]);
+    assert_eq!(*i16s.as_i16_slice().unwrap(), [0x1234, -2]);
+
+    let i32s = tensor(TensorType::I32, (-7i32).to_le_bytes().to_vec());
+    assert_eq!(*i32s.as_i32_slice().unwrap(), [-7]);
+
+    let i64s = tensor(TensorType::I64, (1i64 << 40).to_le_bytes().to_vec());
+    assert_eq!(*i64s.as_i64_slice().unwrap(), [1 << 40]);
+
+    let f64s = tensor(TensorType::F64, 0.1f64.to_le_bytes().to_vec());
+    assert_eq!(*f64s.as_f64_slice().unwrap(), [0.1]);
+
+    let f16s = tensor(TensorType::F16, vec![0x00, 0x3c]);
+    assert_eq!(*f16s.as_f16_slice().unwrap(), [0x3c00]);
+}
+
+#[test]
+fn rejects_mismatched_types_and_lengths() {
+    let t = tensor(TensorType::I32, vec![0; 8]);
+    assert!(t.as_f32_slice().is_err());
+    assert!(t.as_i64_slice().is_err());
+
+    let truncated = tensor(TensorType::I32, vec![0; 6]);
+    assert!(truncated.as_i32_slice().is_err());
+}
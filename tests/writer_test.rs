@@ -0,0 +1,77 @@
+//! Tests for writing GGUF files
+
+mod common;
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use common::tensor;
+use gguf_llms::{GgufHeader, GgufReader, GgufWriter, TensorLoader, Value, ValueType};
+
+fn metadata() -> HashMap<String, Value> {
+    HashMap::from([
+        (
+            "general.architecture".to_string(),
+            Value::String("llama".to_string()),
+        ),
+        ("llama.block_count".to_string(), Value::Uint32(1)),
+        ("llama.rope.freq_base".to_string(), Value::Float32(1e4)),
+        ("test.flag".to_string(), Value::Bool(true)),
+        ("test.offset".to_string(), Value::Int64(-3)),
+        (
+            "tokenizer.ggml.tokens".to_string(),
+            Value::Array(
+                ValueType::String,
+                vec![
+                    Value::String("a".to_string()),
+                    Value::String("b".to_string()),
+                ],
+            ),
+        ),
+    ])
+}
+
+#[test]
+fn round_trips_metadata_and_tensors() {
+    let tensors = [tensor("a.weight", &[3]), tensor("b.weight", &[4, 2])];
+    let infos: Vec<_> = tensors.iter().map(|t| t.info.clone()).collect();
+
+    let mut writer = GgufWriter::new(Cursor::new(Vec::new()), &metadata(), &infos).unwrap();
+    assert_eq!(writer.tensor_infos()[1].offset, 32);
+    writer.write_tensor(&tensors[0]).unwrap();
+    // Data may arrive in arbitrary chunks
+    for chunk in tensors[1].data.chunks(5) {
+        writer.write_tensor_data(chunk).unwrap();
+    }
+    let mut file = writer.finish().unwrap();
+    file.set_position(0);
+
+    let header = GgufHeader::parse(&mut file).unwrap();
+    assert_eq!((header.version, header.n_tensors, header.n_kv), (3, 2, 6));
+    let read_metadata = GgufReader::read_metadata(&mut file, header.n_kv).unwrap();
+    assert_eq!(read_metadata, metadata());
+
+    let read_infos = TensorLoader::read_tensor_info(&mut file, header.n_tensors).unwrap();
+    let start = TensorLoader::get_aligned_tensor_data_start(&mut file, &read_metadata).unwrap();
+    assert_eq!(start % 32, 0);
+    for (tensor, info) in tensors.iter().zip(&read_infos) {
+        let loaded = TensorLoader::load_tensor(&mut file, info, start).unwrap();
+        assert_eq!(loaded.info.name, tensor.info.name);
+        assert_eq!(loaded.data, tensor.data);
+    }
+}
+
+#[test]
+fn rejects_out_of_order_or_missing_data() {
+    let tensors = [tensor("a.weight", &[3]), tensor("b.weight", &[4])];
+    let infos: Vec<_> = tensors.iter().map(|t| t.info.clone()).collect();
+
+    let mut writer = GgufWriter::new(Vec::new(), &metadata(), &infos).unwrap();
+    assert!(writer.write_tensor(&tensors[1]).is_err());
+    writer.write_tensor(&tensors[0]).unwrap();
+    assert_eq!(writer.next_tensor().unwrap().name, "b.weight");
+    assert!(writer.finish().is_err());
+
+    let mut writer = GgufWriter::new(Vec::new(), &metadata(), &infos[..1]).unwrap();
+    assert!(writer.write_tensor_data(&[0; 16]).is_err());
+}